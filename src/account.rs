@@ -1,18 +1,84 @@
 use std::fmt::{Display, Formatter};
 use serde::{Serialize, Deserialize};
-use std::io::Read;
-use std::io::prelude::*;
-use inquire::Text;
-use colored::Colorize;
+use chrono::prelude::*;
+use chrono::NaiveDate;
+use inquire::{Text, Select, DateSelect, Confirm, InquireError, validator::Validation};
 
 use crate::Transaction;
+use crate::transaction::Status;
+use crate::budget::Budget;
+use crate::category_rules::CategoryRule;
+use crate::recurring::RecurringRule;
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
+use crate::currency::{default_currency, query_currency};
+use crate::money::{Money, query_money};
 
 // ACCOUNT -----------------------------------------------------------------------------------------
+// only meaningful for acc accounts, categories are left as the default
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    #[default]
+    Current, // also what every account saved before account types existed becomes
+    Savings,
+    CreditCard,
+    Cash,
+    Loan,
+    Investment,
+}
+
+impl Display for AccountType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            AccountType::Current => write!(f, "Current"),
+            AccountType::Savings => write!(f, "Savings"),
+            AccountType::CreditCard => write!(f, "Credit card"),
+            AccountType::Cash => write!(f, "Cash"),
+            AccountType::Loan => write!(f, "Loan"),
+            AccountType::Investment => write!(f, "Investment"),
+        }
+    }
+}
+
+impl AccountType {
+    pub const ALL: [AccountType; 6] = [AccountType::Current, AccountType::Savings, AccountType::CreditCard,
+                                       AccountType::Cash, AccountType::Loan, AccountType::Investment];
+
+    // what a limit on how far below zero the balance may go is called, None if this type doesn't have one
+    pub fn limit_name(&self) -> Option<&str> {
+        return match *self {
+            AccountType::Current => Some("overdraft"),
+            AccountType::CreditCard => Some("credit limit"),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Account {
     pub id: u32,
     pub name: String,
-    pub category: bool // true if cat account, false if acc account
+    pub category: bool, // true if cat account, false if acc account
+    // hidden from pickers and the calendar, but its transactions are kept
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub account_type: AccountType,
+    // ISO 4217 code, every amount in the account is in minor units of it
+    #[serde(default = "crate::currency::default_currency")]
+    pub currency: String,
+    // balance before any of its transactions, counted from <opening_date> (or always, when there is none)
+    #[serde(default)]
+    pub opening_balance: Money,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "crate::transaction::optional_date_format")]
+    pub opening_date: Option<NaiveDate>,
+    // credit limit or overdraft, positive: how far below zero the balance may go
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<Money>,
+    // categories only, the category this one rolls up into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u32>,
 }
 
 impl Display for Account {
@@ -22,71 +88,96 @@ impl Display for Account {
 }
 
 impl Account {
-    pub fn get_total_amount(all_transactions: &Vec<Transaction>, account: &Account) -> i64 {
-        let mut total:i64 = 0;
+    // everything in the account, including transfers into it, whatever the date
+    pub fn get_total_amount(all_transactions: &Vec<Transaction>, account: &Account) -> Money {
+        let mut total:Money = account.opening_balance;
         for transaction in all_transactions.into_iter() {
             if transaction.account_id_1() == account.id {
                 total += transaction.amount();
             }
+            if transaction.account_id_2() == account.id && transaction.is_transfer() {
+                total -= transaction.amount_2();
+            }
         }
         return total;
     }
-}
 
-// TRANSACTION FILE I/O ----------------------------------------------------------------------------
+    pub fn opening_balance_on(&self, date: NaiveDate) -> Money {
+        return match self.opening_date {
+            Some(d) if d > date => Money::ZERO,
+            _ => self.opening_balance,
+        }
+    }
 
-macro_rules! oafe {
-    ($e:ident) => {
-        println!("{}: {}\nStarting with fresh Account Database. Inconsistency likely if Transaction Database is not also fresh.","Error opening accounts file".to_string().red().bold(),$e)
-    }
-}
-
-pub fn get_accounts_from_file(filepath:&str) -> Vec<Account> { // TODO return results rather than handling errors here??
-    // open file
-    match std::fs::File::open(filepath) {
-        Ok(f) => {
-            let mut file = f;
-            let mut s = String::new();
-            match file.read_to_string(&mut s) {
-                Ok(_) => {
-                    // Deserialize JSON
-                    match serde_json::from_str(&mut s) {
-                        Ok(v) => return v,
-                        Err(e) => { oafe!(e); return Vec::new()}
-                    }
-                },
-                Err(e) => {oafe!(e); return Vec::new()}
+    // closing balance at the end of <date>, including pending transactions
+    pub fn balance_on(&self, transactions: &Vec<Transaction>, date: NaiveDate) -> Money {
+        return self.balance_on_where(transactions, date, |_| true);
+    }
+
+    // closing balance at the end of <date> counting only what has cleared
+    pub fn cleared_balance_on(&self, transactions: &Vec<Transaction>, date: NaiveDate) -> Money {
+        return self.balance_on_where(transactions, date, |t| !t.is_pending());
+    }
+
+    // closing balance at the end of <date> counting only what has been checked against a statement
+    pub fn reconciled_balance_on(&self, transactions: &Vec<Transaction>, date: NaiveDate) -> Money {
+        return self.balance_on_where(transactions, date, |t| t.is_reconciled());
+    }
+
+    fn balance_on_where(&self, transactions: &Vec<Transaction>, date: NaiveDate, counts: impl Fn(&Transaction) -> bool) -> Money {
+        let mut total = self.opening_balance_on(date);
+        for t in transactions.iter() {
+            if t.date() > date || !counts(t) { continue }
+            total += t.amount_for(self.id);
+        }
+        return total;
+    }
+
+    // how much more can be spent before going over the limit, None when there is no limit
+    pub fn available_credit(&self, balance: Money) -> Option<Money> {
+        return self.limit.map(|l| balance + l);
+    }
+
+    // true if <balance> is below what the account allows: past its limit if it has one, otherwise below
+    // zero, except for loans and credit cards which are normally negative
+    pub fn is_over_limit(&self, balance: Money) -> bool {
+        return match self.limit {
+            Some(l) => balance < -l,
+            None => match self.account_type {
+                AccountType::Loan | AccountType::CreditCard => false,
+                _ => balance.is_negative(),
             }
-        },
-        Err(e) => {oafe!(e); return Vec::new()}
-        // TODO should create an empty file to see that will be allowed to, to save running the program for ages before realising that you can't save anything??
+        }
     }
 }
 
+// TRANSACTION FILE I/O ----------------------------------------------------------------------------
+
+pub fn get_accounts_from_file(filepath:&str) -> Result<Vec<Account>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
+}
+
 macro_rules! safe {
     ($e:ident) => {
-        println!("Error saving accounts to file: {}", $e)
+        return Err(format!("Error saving accounts to file: {}", $e))
     }
 }
 
-pub fn save_accounts_to_file(filepath:&str, accounts:Vec<Account>) {
+pub fn save_accounts_to_file(filepath:&str, accounts:Vec<Account>) -> Result<(), String> {
     // Serialize to JSON
     match serde_json::to_string(&accounts) {
         Ok(s) => {
             // save to file
-            match std::fs::File::create(filepath){
-                Ok(f) => {
-                    let mut file = f;
-                    match file.write_all(s.as_bytes()) {
-                        Ok(_) => {}, // success & exit function without complaining
-                        Err(e) => safe!(e)
-                    }
-                },
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
                 Err(e) => safe!(e)
             }
         },
         Err(e) => safe!(e)
     }
+    return Ok(());
 }
 
 // ACCOUNT FUNCTIONS -------------------------------------------------------------------------------
@@ -94,19 +185,26 @@ impl Account {
     pub fn cat_accounts(accounts: &Vec<Account>) -> Vec<Account> {
         let mut cat_accounts = Vec::new();
         for account in accounts.to_vec().into_iter() {
-            if account.category { cat_accounts.push(account) }
+            if account.category && !account.archived { cat_accounts.push(account) }
         }
         return cat_accounts;
     }
     pub fn acc_accounts(accounts: &Vec<Account>) -> Vec<Account> {
         let mut acc_accounts = Vec::new();
         for account in accounts.to_vec().into_iter() {
-            if !account.category { acc_accounts.push(account) }
+            if !account.category && !account.archived { acc_accounts.push(account) }
         }
         return acc_accounts;
     }
 }
 
+pub fn get_account_name(accounts: &Vec<Account>, id: u32) -> Option<String> {
+    for a in accounts.iter() {
+        if a.id == id { return Some(a.name.clone()) }
+    }
+    return None;
+}
+
 pub fn new_account_from_inputs(existing_accounts: &Vec<Account>) -> Result<Account, inquire::InquireError> {
     // find new largest id
     let mut id = 0;
@@ -126,11 +224,58 @@ pub fn new_account_from_inputs(existing_accounts: &Vec<Account>) -> Result<Accou
         Err(error) => return Err(error),
     }
 
-    return Ok(Account{
+    let mut account = Account {
         id,
         name,
         category: false,
-    });
+        archived: false,
+        account_type: AccountType::default(),
+        currency: default_currency(),
+        opening_balance: Money::ZERO,
+        opening_date: None,
+        limit: None,
+        parent: None,
+    };
+    query_account_details(&mut account, &Vec::new())?;
+    return Ok(account);
+}
+
+// type, currency, opening balance and limit of an acc account, each starting from its current value.
+// Amounts are stored in minor units of the currency, so it can only be changed before there are any
+pub fn query_account_details(account: &mut Account, transactions: &Vec<Transaction>) -> Result<(), InquireError> {
+    let types = AccountType::ALL.to_vec();
+    let current = types.iter().position(|t| *t == account.account_type).unwrap_or(0);
+    account.account_type = Select::new("Account type:", types).with_starting_cursor(current).prompt()?;
+    if transactions.iter().any(|t| t.involves(account.id)) || !account.opening_balance.is_zero() || account.limit.is_some() {
+        println!("Currency: {} (can't be changed once the account has amounts in it)", account.currency);
+    } else {
+        account.currency = query_currency("Currency:", &account.currency)?;
+    }
+
+    account.opening_balance = query_money("Opening balance:",
+        "Balance before any transactions entered here, e.g. from the statement you start from. Negative if money is owed",
+        &account.currency, Some(account.opening_balance), false)?;
+    account.opening_date = if account.opening_balance.is_zero() { None } else {
+        Some(DateSelect::new("Opening balance date:")
+            .with_week_start(Weekday::Mon)
+            .with_default(account.opening_date.unwrap_or(Utc::now().date_naive()))
+            .prompt()?)
+    };
+
+    account.limit = match account.account_type.limit_name() {
+        Some(limit_name) => {
+            let has_limit = Confirm::new(&format!("Does it have an agreed {}?", limit_name))
+                .with_default(account.limit.is_some())
+                .prompt()?;
+            if has_limit {
+                Some(query_money(&format!("Size of the {}:", limit_name),
+                                 "How far below zero the balance may go, as a positive number",
+                                 &account.currency, account.limit, true)?)
+            } else { None }
+        },
+        None => None,
+    };
+    return Ok(());
 }
 
 pub fn new_category_from_inputs(existing_accounts: &Vec<Account>) -> Result<Account, inquire::InquireError> {
@@ -152,9 +297,187 @@ pub fn new_category_from_inputs(existing_accounts: &Vec<Account>) -> Result<Acco
         Err(error) => return Err(error),
     }
 
-    return Ok(Account{
+    let mut category = Account{
         id,
         name,
         category: true,
+        archived: false,
+        account_type: AccountType::default(),
+        currency: default_currency(),
+        opening_balance: Money::ZERO,
+        opening_date: None,
+        limit: None,
+        parent: None,
+    };
+    let cat_accounts = Account::cat_accounts(existing_accounts);
+    if !cat_accounts.is_empty() { category.parent = query_parent(existing_accounts, &cat_accounts, &category)? }
+    return Ok(category);
+}
+
+
+// CATEGORY TREE -----------------------------------------------------------------------------------
+// a category in a picker, indented under its parent
+#[derive(Clone)]
+pub struct TreeEntry {
+    pub account: Account,
+    pub depth: usize,
+}
+
+impl Display for TreeEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}{}", "  ".repeat(self.depth), self.account.name)
+    }
+}
+
+// <categories> depth first, each followed by its children in creation order. One whose parent isn't in
+// <categories> (e.g. archived) is shown at the top level
+pub fn category_tree(categories: &Vec<Account>) -> Vec<TreeEntry> {
+    let mut tree = Vec::new();
+    let is_root = |c: &Account| match c.parent {
+        Some(p) => !categories.iter().any(|x| x.id == p),
+        None => true,
+    };
+    // (category, depth), popped from the end so children are pushed in reverse
+    let mut stack:Vec<(&Account, usize)> = categories.iter().filter(|c| is_root(c)).rev().map(|c| (c, 0)).collect();
+    while let Some((c, depth)) = stack.pop() {
+        if tree.iter().any(|e: &TreeEntry| e.account.id == c.id) { continue } // only possible with a cycle
+        tree.push(TreeEntry { account: c.clone(), depth });
+        for child in categories.iter().rev().filter(|x| x.parent == Some(c.id)) { stack.push((child, depth+1)) }
+    }
+    // anything left is in a cycle, which the menus never create, but don't lose it
+    for c in categories.iter() {
+        if !tree.iter().any(|e| e.account.id == c.id) { tree.push(TreeEntry { account: c.clone(), depth: 0 }) }
+    }
+    return tree;
+}
+
+// <id> and every category below it
+pub fn with_descendants(accounts: &Vec<Account>, id: u32) -> Vec<u32> {
+    let mut ids = vec!(id);
+    let mut i = 0;
+    while i < ids.len() {
+        for a in accounts.iter() {
+            if a.parent == Some(ids[i]) && !ids.contains(&a.id) { ids.push(a.id) }
+        }
+        i += 1;
+    }
+    return ids;
+}
+
+// a parent for <category> from <categories>, excluding itself and anything below it. What is below it is
+// found from all of <accounts>, since an archived category in between still links the two
+pub fn query_parent(accounts: &Vec<Account>, categories: &Vec<Account>, category: &Account) -> Result<Option<u32>, InquireError> {
+    let below = with_descendants(accounts, category.id);
+    let tree:Vec<TreeEntry> = category_tree(categories).into_iter().filter(|e| !below.contains(&e.account.id)).collect();
+    let mut labels = vec!("(none, top level)".to_string());
+    labels.extend(tree.iter().map(|e| e.to_string()));
+    let current = match category.parent {
+        Some(p) => tree.iter().position(|e| e.account.id == p).map_or(0, |i| i+1),
+        None => 0,
+    };
+    let label = Select::new("Parent category:", labels.clone()).with_starting_cursor(current).prompt()?;
+    return Ok(match labels.iter().position(|l| *l == label) {
+        Some(0) | None => None,
+        Some(i) => Some(tree[i-1].account.id),
     });
-}
\ No newline at end of file
+}
+
+// <from> is being merged into <to>: its sub-categories move under <to>, and if <to> was itself below
+// <from> it first moves up to where <from> was. Returns the categories that changed, as (old, new)
+pub fn reparent_children(accounts: &mut Vec<Account>, from: &Account, to: &Account) -> Vec<(Account, Account)> {
+    let mut changed = Vec::new();
+    let to_was_below = with_descendants(accounts, from.id).contains(&to.id);
+    for a in accounts.iter_mut() {
+        let parent = if a.id == to.id && to_was_below { from.parent }
+                     else if a.parent == Some(from.id) && a.id != to.id { Some(to.id) }
+                     else { a.parent };
+        if parent != a.parent {
+            let old = a.clone();
+            a.parent = parent;
+            changed.push((old, a.clone()));
+        }
+    }
+    return changed;
+}
+
+
+// ACCOUNT MAINTENANCE -----------------------------------------------------------------------------
+fn describe_account(a: &Account) -> String {
+    return format!("{} ({}{})", a.name, if a.category { "category" } else { "account" }, if a.archived { ", archived" } else { "" });
+}
+
+// pick one of <accounts> for which <include> is true, labelled with its kind so that an account and a
+// category with the same name can be told apart
+pub fn pick_account<F: Fn(&Account) -> bool>(accounts: &Vec<Account>, prompt: &str, include: F) -> Result<Account, InquireError> {
+    let choices:Vec<Account> = accounts.iter().filter(|a| include(a)).cloned().collect();
+    if choices.is_empty() { return Err(InquireError::InvalidConfiguration("Nothing to choose from".to_string())) }
+    let labels:Vec<String> = choices.iter().map(describe_account).collect();
+    let label = Select::new(prompt, labels.clone()).prompt()?;
+    return Ok(choices[labels.iter().position(|l| *l == label).unwrap()].clone());
+}
+
+// <account> with a new name, which no other account or category of the same kind may already have
+pub fn renamed_account_from_inputs(accounts: &Vec<Account>, account: &Account) -> Result<Account, InquireError> {
+    let others:Vec<String> = accounts.iter().filter(|a| a.category == account.category && a.id != account.id)
+        .map(|a| a.name.to_lowercase()).collect();
+    let name = Text::new("New name:")
+        .with_initial_value(&account.name)
+        .with_validator(move |s: &str| {
+            if s.trim().is_empty() { return Ok(Validation::Invalid("Name can't be empty".into())) }
+            if others.contains(&s.trim().to_lowercase()) { return Ok(Validation::Invalid("That name is already taken".into())) }
+            return Ok(Validation::Valid);
+        })
+        .prompt()?;
+    let mut renamed = account.clone();
+    renamed.name = name.trim().to_string();
+    return Ok(renamed);
+}
+
+// (transactions, sub-categories + budgets + auto-categorisation rules + recurring transactions) referring to <id>
+pub fn count_references(id: u32, accounts: &Vec<Account>, transactions: &Vec<Transaction>, budgets: &Vec<Budget>,
+                        rules: &Vec<CategoryRule>, recurring: &Vec<RecurringRule>) -> (usize, usize) {
+    let others = accounts.iter().filter(|a| a.parent == Some(id)).count()
+        + budgets.iter().filter(|b| b.category_id == id).count()
+        + rules.iter().filter(|r| r.category_id == id).count()
+        + recurring.iter().filter(|r| r.template.involves(id)).count();
+    return (transactions.iter().filter(|t| t.involves(id)).count(), others);
+}
+
+// move everything referring to <from> over to <to> (of the same kind, and currency for acc accounts), so
+// that <from> can be deleted. A budget for <from> is dropped if <to> already has one
+pub fn reassign_references(from: &Account, to: &Account, transactions: &mut Vec<Transaction>, budgets: &mut Vec<Budget>,
+                           rules: &mut Vec<CategoryRule>, recurring: &mut Vec<RecurringRule>) -> Result<(), String> {
+    if from.category != to.category || from.id == to.id {
+        return Err(format!("Can't move \"{}\" onto \"{}\"", from.name, to.name));
+    }
+    // amounts are in minor units of the account's own currency, so moving them would change their value
+    if !from.category && from.currency != to.currency {
+        return Err(format!("\"{}\" is in {} but \"{}\" is in {}, transactions can only move between accounts in the same currency",
+                           from.name, from.currency, to.name, to.currency));
+    }
+    // a transfer between the two would end up going from an account to itself
+    let transfers = transactions.iter().filter(|t| t.is_transfer() && t.involves(from.id) && t.involves(to.id)).count();
+    if transfers > 0 {
+        return Err(format!("{} transfer(s) between \"{}\" and \"{}\" would become transfers to itself", transfers, from.name, to.name));
+    }
+    // anything reconciled no longer matches what was checked, so goes back to cleared
+    for t in transactions.iter_mut() {
+        if !t.involves(from.id) { continue }
+        *t = t.reassigned(from.id, to.id);
+        if t.is_reconciled() { *t = t.with_status(Status::Cleared) }
+    }
+    for r in recurring.iter_mut() {
+        if r.template.involves(from.id) { r.template = r.template.reassigned(from.id, to.id) }
+    }
+    for r in rules.iter_mut() {
+        if r.category_id == from.id { r.category_id = to.id }
+    }
+    if budgets.iter().any(|b| b.category_id == to.id) {
+        budgets.retain(|b| b.category_id != from.id);
+    } else {
+        for b in budgets.iter_mut() {
+            if b.category_id == from.id { b.category_id = to.id }
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,187 @@
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use chrono::prelude::*;
+
+use crate::crypto::seal;
+
+// ATOMIC SAVES ------------------------------------------------------------------------------------
+// write to a temporary file next to <filepath>, flush it to disk, then rename it over the original so
+// that a crash or a full disk part way through never leaves a half-written file behind
+pub fn write_atomically(filepath:&str, contents:&[u8]) -> std::io::Result<()> {
+    let path = Path::new(filepath);
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    // make the rename itself durable (not possible to open a directory like this on all platforms)
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        if let Ok(d) = std::fs::File::open(dir) { let _ = d.sync_all(); }
+    }
+    return Ok(());
+}
+
+// write a data file, encrypted if the profile is encrypted
+pub fn write_data_file(filepath:&str, contents:&[u8]) -> std::io::Result<()> {
+    let sealed = seal(contents).map_err(std::io::Error::other)?;
+    return write_atomically(filepath, &sealed);
+}
+
+
+// BACKUPS -----------------------------------------------------------------------------------------
+// each backup is a directory named by its timestamp, e.g. data/backups/2023-06-30_18-04-59.312/, holding a
+// copy of each of the data files as they were before a save. Two made in the same millisecond get a
+// counter after the timestamp (..._2), and backups from older versions have no milliseconds
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S%.3f";
+const TIMESTAMP_PARSE_FORMAT: &str = "%Y-%m-%d_%H-%M-%S%.f";
+pub const BACKUPS_KEPT: usize = 10;
+
+// when a backup was made, from its directory name, None if it isn't a backup
+fn backup_time(name:&str) -> Option<(NaiveDateTime, u32)> {
+    let (stamp, counter) = match name.rsplit_once('_') {
+        Some((stamp, n)) if n.parse::<u32>().is_ok() => (stamp, n.parse::<u32>().unwrap()),
+        _ => (name, 1),
+    };
+    return NaiveDateTime::parse_from_str(stamp, TIMESTAMP_PARSE_FORMAT).ok().map(|t| (t, counter));
+}
+
+// true if the backup holds exactly the data files that exist now, byte for byte
+fn same_as_backup(filepaths:&[&str], backup_dir:&Path) -> bool {
+    for filepath in filepaths.iter() {
+        let path = Path::new(filepath);
+        let copy = backup_dir.join(path.file_name().unwrap_or_default());
+        let same = match (std::fs::read(path), std::fs::read(&copy)) {
+            (Ok(current), Ok(backed_up)) => current == backed_up,
+            (Err(_), Err(_)) => !path.exists() && !copy.exists(),
+            _ => false,
+        };
+        if !same { return false }
+    }
+    return true;
+}
+
+pub fn create_backup(filepaths:&[&str], backups_dir:&str, keep:usize) -> std::io::Result<()> {
+    // saving when nothing has changed shouldn't push older backups out
+    if let Some(newest) = list_backups(backups_dir).last() {
+        if same_as_backup(filepaths, &Path::new(backups_dir).join(newest)) { return Ok(()) }
+    }
+    let stamp = Local::now().format(TIMESTAMP_FORMAT).to_string();
+    let mut dir = Path::new(backups_dir).join(&stamp);
+    let mut counter = 2;
+    while dir.exists() {
+        dir = Path::new(backups_dir).join(format!("{}_{}", stamp, counter));
+        counter += 1;
+    }
+    let mut copied = false;
+    for filepath in filepaths.iter() {
+        let path = Path::new(filepath);
+        if !path.exists() { continue } // nothing saved yet, so nothing to back up
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(path, dir.join(path.file_name().unwrap_or_default()))?;
+        copied = true;
+    }
+    if copied { prune_backups(backups_dir, keep)?; }
+    return Ok(());
+}
+
+// backup directory names, oldest first
+pub fn list_backups(backups_dir:&str) -> Vec<String> {
+    let mut backups = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(backups_dir) {
+        for e in entries.flatten() {
+            let name = e.file_name().to_string_lossy().to_string();
+            if e.path().is_dir() && backup_time(&name).is_some() {
+                backups.push(name);
+            }
+        }
+    }
+    backups.sort_by_key(|name| backup_time(name));
+    return backups;
+}
+
+fn prune_backups(backups_dir:&str, keep:usize) -> std::io::Result<()> {
+    let backups = list_backups(backups_dir);
+    if backups.len() > keep {
+        for name in backups[..backups.len()-keep].iter() {
+            std::fs::remove_dir_all(Path::new(backups_dir).join(name))?;
+        }
+    }
+    return Ok(());
+}
+
+// the contents of every file in the backup that has the same name as one of <filepaths>, read into memory
+// so that making another backup (which may prune this one) can't lose them before they are restored
+pub fn read_backup(backup:&str, filepaths:&[&str], backups_dir:&str) -> std::io::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let dir = Path::new(backups_dir).join(backup);
+    let mut files = Vec::new();
+    for filepath in filepaths.iter() {
+        let path = Path::new(filepath);
+        let from = dir.join(path.file_name().unwrap_or_default());
+        if !from.exists() { continue }
+        files.push((path.to_path_buf(), std::fs::read(&from)?));
+    }
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("backup {} has no data files in it", backup)));
+    }
+    return Ok(files);
+}
+
+// write the files read by read_backup back over the data files
+pub fn restore_backup(files:&Vec<(PathBuf, Vec<u8>)>) -> std::io::Result<()> {
+    for (path, contents) in files.iter() {
+        write_atomically(&path.to_string_lossy(), contents)?;
+    }
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name:&str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("moxdtrkr-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn unchanged_files_arent_backed_up_again() {
+        let dir = scratch_dir("unchanged");
+        let file = dir.join("accounts.json").to_string_lossy().to_string();
+        let backups = dir.join("backups").to_string_lossy().to_string();
+        std::fs::write(&file, "[1]").unwrap();
+        for _ in 0..3 { create_backup(&[&file], &backups, 10).unwrap() }
+        assert_eq!(list_backups(&backups).len(), 1);
+        // changes straight after, within the same millisecond or not, still get a backup of their own
+        for contents in ["[1,2]", "[1,2,3]", "[1,2,3,4]"] {
+            std::fs::write(&file, contents).unwrap();
+            create_backup(&[&file], &backups, 10).unwrap();
+        }
+        let names = list_backups(&backups);
+        assert_eq!(names.len(), 4);
+        let newest = Path::new(&backups).join(names.last().unwrap()).join("accounts.json");
+        assert_eq!(std::fs::read_to_string(newest).unwrap(), "[1,2,3,4]");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn backups_sort_by_time_then_counter() {
+        let dir = scratch_dir("sort");
+        let backups = dir.to_string_lossy().to_string();
+        for name in ["2023-06-30_18-05-00.001_10", "2023-06-30_18-05-00.001", "2023-06-30_18-05-00.001_2",
+                     "2023-06-30_18-04-59", "2023-06-30_18-04-59.500", "not-a-backup"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        assert_eq!(list_backups(&backups), vec!("2023-06-30_18-04-59", "2023-06-30_18-04-59.500",
+            "2023-06-30_18-05-00.001", "2023-06-30_18-05-00.001_2", "2023-06-30_18-05-00.001_10"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
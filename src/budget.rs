@@ -0,0 +1,291 @@
+use std::fmt::{Display, Formatter};
+use serde::{Serialize, Deserialize};
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDate};
+use inquire::{Select, Confirm, InquireError};
+
+use crate::{Account, Transaction, format_money, format_money_colour_bg};
+use crate::money::{Money, query_money};
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
+
+// BUDGET ------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum BudgetPeriod {
+    Weekly,  // Monday to Sunday, consistent with the calendar
+    Monthly, // 1st to last day of the calendar month
+}
+
+impl Display for BudgetPeriod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            BudgetPeriod::Weekly => write!(f, "Weekly"),
+            BudgetPeriod::Monthly => write!(f, "Monthly"),
+        }
+    }
+}
+
+impl BudgetPeriod {
+    // first day of the period containing <date>
+    pub fn start_of(&self, date: NaiveDate) -> NaiveDate {
+        match *self {
+            BudgetPeriod::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            BudgetPeriod::Monthly => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+        }
+    }
+    // first day of the period after the one starting on <start>
+    pub fn next_start(&self, start: NaiveDate) -> NaiveDate {
+        match *self {
+            BudgetPeriod::Weekly => start + Duration::days(7),
+            BudgetPeriod::Monthly => match start.month() {
+                12 => NaiveDate::from_ymd_opt(start.year()+1, 1, 1).unwrap(),
+                m => NaiveDate::from_ymd_opt(start.year(), m+1, 1).unwrap(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Budget {
+    pub category_id: u32, // category account this budget applies to
+    pub amount: Money,    // allowed to be spent per period, in the base currency
+    pub period: BudgetPeriod,
+    pub rollover: bool,   // carry anything left unspent into the next period
+    #[serde(with="crate::transaction::test_date_format")]
+    pub start: NaiveDate, // rollover is only accumulated from here onwards
+}
+
+pub struct BudgetProgress {
+    pub period_start: NaiveDate,
+    pub period_end: NaiveDate, // exclusive
+    pub allowance: Money, // amount + anything rolled over
+    pub spent: Money,
+}
+
+impl BudgetProgress {
+    pub fn remaining(&self) -> Money { return self.allowance - self.spent }
+    pub fn overspent(&self) -> bool { return self.spent > self.allowance }
+}
+
+impl Budget {
+    // money spent against the budget's category in [lb, ub), money in (refunds) counts against spending
+    pub fn spent_between(&self, transactions: &Vec<Transaction>, lb: NaiveDate, ub: NaiveDate) -> Money {
+        let mut spent = Money::ZERO;
+        for t in transactions.iter() {
+            if t.date() >= lb && t.date() < ub {
+                spent -= t.amount_in_category(self.category_id);
+            }
+        }
+        return spent;
+    }
+
+    pub fn progress(&self, transactions: &Vec<Transaction>, today: NaiveDate) -> BudgetProgress {
+        let period_start = self.period.start_of(today);
+        let period_end = self.period.next_start(period_start);
+
+        // roll over whatever was left in each previous period, never rolling over an overspend
+        let mut carried = Money::ZERO;
+        if self.rollover {
+            let mut lb = self.period.start_of(self.start);
+            while lb < period_start {
+                let ub = self.period.next_start(lb);
+                carried = (carried + self.amount - self.spent_between(transactions, lb, ub)).max(Money::ZERO);
+                lb = ub;
+            }
+        }
+
+        return BudgetProgress {
+            period_start,
+            period_end,
+            allowance: self.amount + carried,
+            spent: self.spent_between(transactions, period_start, period_end),
+        }
+    }
+}
+
+
+// BUDGET FILE I/O ---------------------------------------------------------------------------------
+pub fn get_budgets_from_file(filepath:&str) -> Result<Vec<Budget>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
+}
+
+macro_rules! sbfe {
+    ($e:ident) => {
+        return Err(format!("Error saving budgets to file: {}", $e))
+    }
+}
+
+pub fn save_budgets_to_file(filepath:&str, budgets:Vec<Budget>) -> Result<(), String> {
+    // Serialize to JSON
+    match serde_json::to_string(&budgets) {
+        Ok(s) => {
+            // save to file
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
+                Err(e) => sbfe!(e)
+            }
+        },
+        Err(e) => sbfe!(e)
+    }
+    return Ok(());
+}
+
+
+// BUDGET FUNCTIONS --------------------------------------------------------------------------------
+pub fn print_budgets(
+    budgets: &Vec<Budget>,
+    cat_accounts: &Vec<Account>,
+    transactions: &Vec<Transaction>, // in <currency>
+    currency: &str,
+    signed_with_colour: bool,
+    brackets_when_neg: bool,
+) {
+    if budgets.is_empty() {
+        println!("No budgets set up yet.");
+        return;
+    }
+    let today = Utc::now().date_naive();
+
+    let mut max_len = 0; // find longest category name
+    for b in budgets.iter() {
+        let len = category_name(cat_accounts, b.category_id).len();
+        if len > max_len { max_len = len; }
+    }
+
+    for b in budgets.iter() {
+        let p = b.progress(transactions, today);
+        let mut s = category_name(cat_accounts, b.category_id);
+        while s.len() < max_len+2 { s.push(' '); }
+        let status = if p.overspent() {
+            format!("Overspent: {}",
+                    format_money_colour_bg(p.remaining(), currency, signed_with_colour, brackets_when_neg))
+        } else {
+            format!("Remaining: {}",
+                    format_money_colour_bg(p.remaining(), currency, signed_with_colour, brackets_when_neg))
+        };
+        println!("{s}{} {} to {}  Spent: {} of {}  {}",
+                 b.period,
+                 p.period_start,
+                 p.period_end - Duration::days(1),
+                 format_money(p.spent, currency, true, brackets_when_neg),
+                 format_money(p.allowance, currency, true, brackets_when_neg),
+                 status,
+        );
+    }
+}
+
+fn category_name(cat_accounts: &Vec<Account>, id: u32) -> String {
+    for a in cat_accounts.iter() {
+        if a.id == id { return a.name.clone() }
+    }
+    return "<Category name not found>".to_string();
+}
+
+// create a budget for a category, replacing any budget that category already has
+// <currency> is the base currency, that spending from every account is converted into
+pub fn new_budget_from_inputs(cat_accounts: &Vec<Account>, currency: &str) -> Result<Budget, InquireError> {
+    if cat_accounts.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No categories to choose from".to_string()))}
+
+    let category_id = match Select::new("Category to budget for:", cat_accounts.to_vec()).prompt() {
+        Ok(c) => c.id,
+        Err(e) => return Err(e),
+    };
+
+    let period = match Select::new("Budget period:", vec!(BudgetPeriod::Monthly, BudgetPeriod::Weekly)).prompt() {
+        Ok(p) => p,
+        Err(e) => return Err(e),
+    };
+
+    let amount = match query_money("Amount per period:",
+                                   &format!("Type the amount in {}, e.g. 150.00. Positive values only", currency),
+                                   currency, None, true) {
+        Ok(am) => am,
+        Err(e) => return Err(e),
+    };
+
+    let rollover = match Confirm::new("Roll unspent amounts over into the next period?")
+        .with_default(false)
+        .prompt() {
+        Ok(b) => b,
+        Err(e) => return Err(e),
+    };
+
+    return Ok(Budget {
+        category_id,
+        amount,
+        period,
+        rollover,
+        start: period.start_of(Utc::now().date_naive()),
+    });
+}
+
+pub fn remove_budget_from_inputs(budgets: &mut Vec<Budget>, cat_accounts: &Vec<Account>) -> Result<(), InquireError> {
+    let mut budgeted = Vec::new();
+    for a in cat_accounts.iter() {
+        if budgets.iter().any(|b| b.category_id == a.id) { budgeted.push(a.clone()) }
+    }
+    if budgeted.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No budgets to remove".to_string()))}
+
+    match Select::new("Remove the budget for which category?", budgeted).prompt() {
+        Ok(c) => budgets.retain(|b| b.category_id != c.id),
+        Err(e) => return Err(e),
+    }
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate { NaiveDate::from_ymd_opt(y, m, d).unwrap() }
+    fn spend(pence: i64, on: NaiveDate) -> Transaction {
+        return Transaction::new_transaction(0, 1, Money::from_minor(-pence), String::new(), on, String::new());
+    }
+    fn monthly(rollover: bool) -> Budget {
+        return Budget { category_id: 1, amount: Money::from_minor(10000), period: BudgetPeriod::Monthly, rollover, start: date(2023, 1, 1) };
+    }
+
+    #[test]
+    fn rollover_carries_what_was_left_but_never_an_overspend() {
+        let transactions = vec!(
+            spend(3000, date(2023, 1, 10)),  // 70.00 left to carry into February
+            spend(5000, date(2023, 2, 5)),   // 120.00 left to carry into March
+            spend(25000, date(2023, 3, 20)), // overspent, nothing carried into April
+            spend(1000, date(2023, 4, 2)),
+        );
+        let b = monthly(true);
+        assert_eq!(b.progress(&transactions, date(2023, 2, 15)).allowance, Money::from_minor(17000));
+        assert_eq!(b.progress(&transactions, date(2023, 3, 15)).allowance, Money::from_minor(22000));
+        let april = b.progress(&transactions, date(2023, 4, 15));
+        assert_eq!(april.allowance, Money::from_minor(10000));
+        assert_eq!(april.spent, Money::from_minor(1000));
+        assert_eq!(april.remaining(), Money::from_minor(9000));
+    }
+
+    #[test]
+    fn without_rollover_each_period_starts_afresh() {
+        let transactions = vec!(spend(3000, date(2023, 1, 10)), spend(12000, date(2023, 2, 28)));
+        let feb = monthly(false).progress(&transactions, date(2023, 2, 1));
+        assert_eq!(feb.allowance, Money::from_minor(10000));
+        assert_eq!((feb.period_start, feb.period_end), (date(2023, 2, 1), date(2023, 3, 1)));
+        assert!(feb.overspent());
+    }
+
+    #[test]
+    fn refunds_count_against_spending() {
+        let transactions = vec!(spend(3000, date(2023, 1, 10)), spend(-1000, date(2023, 1, 11)));
+        assert_eq!(monthly(false).progress(&transactions, date(2023, 1, 31)).spent, Money::from_minor(2000));
+    }
+
+    #[test]
+    fn weeks_run_monday_to_sunday() {
+        // 1 Jan 2023 was a Sunday
+        assert_eq!(BudgetPeriod::Weekly.start_of(date(2023, 1, 1)), date(2022, 12, 26));
+        assert_eq!(BudgetPeriod::Monthly.next_start(date(2023, 12, 1)), date(2024, 1, 1));
+    }
+}
@@ -1,7 +1,10 @@
 use chrono::prelude::*;
-use chrono::NaiveDate;
+use chrono::{NaiveDate, Months};
 
-use crate::{Account, ColouredString, pence_to_pound_colour, pence_to_pound_colour_bg, pence_to_pound_transfer, SideBySide, Transaction};
+use crate::{Account, get_account_name, ColouredString, format_money_colour, format_money_colour_bg, format_money_transfer, SideBySide, Transaction};
+use crate::transaction::Status;
+use crate::currency::{ExchangeRate, currency_of, convert_or_same};
+use crate::money::Money;
 
 pub const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
 pub const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
@@ -41,6 +44,19 @@ macro_rules! wrap_string {
     }
 }
 
+fn last_of_month(first: NaiveDate) -> NaiveDate {
+    return first.checked_add_months(Months::new(1)).unwrap().pred_opt().unwrap();
+}
+
+// the zero index and number of weeks for a grid covering the calendar month starting on <first>, from
+// the Monday on or before the 1st to the Sunday on or after its last day
+pub fn month_grid(first: NaiveDate, today: i64) -> (i64, i64) {
+    let last = last_of_month(first);
+    let start = first - chrono::Duration::days(first.weekday().num_days_from_monday() as i64);
+    let weeks = (last - start).num_days() / 7 + 1;
+    return (today + (start - Utc::now().date_naive()).num_days(), weeks);
+}
+
 pub fn render_calendar(
     day_width:usize,
     weeks:i64,
@@ -49,12 +65,20 @@ pub fn render_calendar(
     signed_with_colour:bool,
     brackets_when_neg:bool,
     transactions:&Vec<Transaction>,
+    projected_transactions:&Vec<Transaction>, // forecast entries, drawn differently to real ones
     visible_accounts:&Vec<Account>,
+    visible_tags:&Vec<String>, // only entries with one of these tags are drawn, all when empty
+    accounts:&Vec<Account>,
+    exchange_rates:&Vec<ExchangeRate>,
+    base_currency:&str, // closing balances are converted into this
     highlight_index:i64,
     zero_index:i64,
+    month:Option<NaiveDate>, // month view: the 1st of the month shown, days outside it are left blank
 ) -> SideBySide
 {
-    let mut sbs = SideBySide::new(7*(day_width+1)+1);
+    // month view adds a column of subtotals for each week
+    let columns = if month.is_some() { 8 } else { 7 };
+    let mut sbs = SideBySide::new(columns*(day_width+1)+1);
     let h_sep = '-';
     let corner_sep = '+';
     let v_sep = '|';
@@ -65,73 +89,87 @@ pub fn render_calendar(
     for _ in 0..day_width { hdiv.push(h_sep); }
 
     let mut hdiv_row = String::from(corner_sep);
-    for _day_index in 0..7 {
+    for _day_index in 0..columns {
         hdiv_row.push_str(hdiv.as_str());
         hdiv_row.push(corner_sep)
     }
 
+    // closing balances include the forecast
+    let mut all_transactions = transactions.clone();
+    all_transactions.extend(projected_transactions.iter().cloned());
+    let closing_balance = |date: NaiveDate| get_closing_balance(
+        date, &all_transactions, visible_accounts, exchange_rates, base_currency, true);
+
     // generate Year and Month display
     {
         let first_date = Utc::now().date().checked_add_signed(
             chrono::Duration::days(-today+zero_index)).unwrap();
         let last_date = Utc::now().date().checked_add_signed(
             chrono::Duration::days(7*weeks-today+zero_index)).unwrap();
-        let yhtxt = format!("{} {} - {} {}",
+        let yhtxt = match month {
+            Some(first) => format!("{} {}", MONTHS[first.month0() as usize], first.year()),
+            None => format!("{} {} - {} {}",
                             MONTHS[first_date.month0() as usize], first_date.year(),
                             MONTHS[last_date.month0() as usize], last_date.year(),
-        );
+            ),
+        };
         let mut year_header = String::from("");
-        while year_header.len() < ((7*(day_width+1)+1) - yhtxt.len())/2 {
+        while year_header.len() < ((columns*(day_width+1)+1) - yhtxt.len())/2 {
             year_header.push(' ');
         }
         sbs.add_line(ColouredString::from_string(format!("{}{}", year_header, yhtxt)));
+        // and the balance either side of the month
+        if let Some(first) = month {
+            let last = last_of_month(first);
+            let mut balances = ColouredString::from_str("Opening ");
+            balances.push_coloured_string(format_money_colour_bg(
+                closing_balance(first.pred_opt().unwrap()), base_currency, signed_with_colour, brackets_when_neg));
+            balances.push_str("  Closing ");
+            balances.push_coloured_string(format_money_colour_bg(
+                closing_balance(last), base_currency, signed_with_colour, brackets_when_neg));
+            let mut line = ColouredString::new();
+            while line.len() < ((columns*(day_width+1)+1).saturating_sub(balances.len()))/2 {
+                line.push_str(" ");
+            }
+            line.push_coloured_string(balances);
+            sbs.add_line(line);
+        }
     }
 
     // do the day of the week titles
     {
         let mut hdiv_row_titles = String::from(corner_sep);
-        for day_index in 0..7 {
-            let before = (day_width - 3) / 2;
-            let after = day_width - 3 - before;
+        for day_index in 0..columns {
+            let title = if day_index < 7 { DAYS[day_index] } else { "Week" };
+            let before = (day_width - title.len()) / 2;
+            let after = day_width - title.len() - before;
             for _ in 0..before { hdiv_row_titles.push(h_sep); }
-            hdiv_row_titles.push_str(DAYS[day_index]);
+            hdiv_row_titles.push_str(title);
             for _ in 0..after { hdiv_row_titles.push(h_sep); }
             hdiv_row_titles.push(corner_sep)
         }
         sbs.add_line(ColouredString::from_string(hdiv_row_titles));
     }
 
-    // construct the transaction grid
-    let mut ts:Vec<Vec<Vec<Transaction>>> = vec!(
-        vec!(vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),),
-        vec!(vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),),
-        vec!(vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),),
-        vec!(vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),vec!(),),
-    );
+    // construct the transaction grid, each entry flagged with whether it is projected or not
+    let mut ts:Vec<Vec<Vec<(Transaction, bool)>>> = vec![vec![vec!(); 7]; weeks as usize];
     // populate grid with transactions
     let accs = acc_ids_from(&visible_accounts);
-    for t in transactions.to_vec().into_iter() {
-        let days_diff = (Utc.from_utc_date(&t.date()) - Utc::now().date()).num_days();
+    let flagged = transactions.iter().map(|t| (t, false))
+        .chain(projected_transactions.iter().map(|t| (t, true)));
+    for (t, is_projected) in flagged {
+        let days_diff = (t.date() - Utc::now().date_naive()).num_days();
         let i = today + days_diff - zero_index;
-        if (i >= 0) && (i < 7*weeks as i64) && (accs.contains(&t.account_id_1()) || accs.contains(&t.account_id_2())) {
-            // on display range and in the accounts list
-            ts[(i/7) as usize][(i%7) as usize].push(t.clone());
+        if (i >= 0) && (i < 7*weeks as i64) && (accs.contains(&t.account_id_1()) || accs.contains(&t.account_id_2()))
+            && t.matches_tags(visible_tags) {
+            // on display range, in the accounts list and tagged as chosen
+            ts[(i/7) as usize][(i%7) as usize].push((t.clone(), is_projected));
         }
     }
     // construct the transaction grid counters
-    let mut ts_counter:Vec<Vec<usize>> = vec!(
-        vec!(0,0,0,0,0,0,0),
-        vec!(0,0,0,0,0,0,0),
-        vec!(0,0,0,0,0,0,0),
-        vec!(0,0,0,0,0,0,0),
-    );
+    let mut ts_counter:Vec<Vec<usize>> = vec![vec![0; 7]; weeks as usize];
     // construct the transaction grid overflow summations
-    let mut ts_overflow:Vec<Vec<i64>> = vec!(
-        vec!(0,0,0,0,0,0,0),
-        vec!(0,0,0,0,0,0,0),
-        vec!(0,0,0,0,0,0,0),
-        vec!(0,0,0,0,0,0,0),
-    );
+    let mut ts_overflow:Vec<Vec<Money>> = vec![vec![Money::ZERO; 7]; weeks as usize];
 
 
     // display calendar
@@ -141,6 +179,7 @@ pub fn render_calendar(
         for _ in 0..max_transactions_per_day {
             t_rows.push(ColouredString::from_string(v_sep.to_string()));
         }
+        let mut week_days: Vec<NaiveDate> = Vec::new(); // those in the month, for the subtotal
         for day_index in 0..7 {
             let today_index = week_index*7 + day_index;
             // assemble date header
@@ -148,19 +187,40 @@ pub fn render_calendar(
             let diff:i64 = today_index - today + zero_index;
             let date = today_date.checked_add_signed(
                 chrono::Duration::days(diff)).unwrap();
+            if month.map_or(false, |m| date.month() != m.month()) { // blank padding outside the month
+                while header.len() < ((day_index as usize)+1)*(day_width+1) {
+                    header.push_str(" ");
+                }
+                header.push_string(v_sep.to_string());
+                for x in 0..max_transactions_per_day {
+                    while t_rows[x].len() < ((day_index as usize)+1)*(day_width+1) {
+                        t_rows[x].push_str(" ");
+                    }
+                    t_rows[x].push_string(v_sep.to_string());
+                }
+                continue;
+            }
+            week_days.push(date.naive_utc());
             // create the header for the day
             let date_string = ColouredString::from_string(format!("{} {}",
                                                                   date.day(),
                                                                   MONTHS[date.month0() as usize]
             ));
-            let closing_amt = pence_to_pound_colour_bg(
-                get_closing_balance(
-                    today_date.checked_add_signed(chrono::Duration::days(diff)).unwrap().naive_utc(),
-                    transactions,
-                    visible_accounts
-                ),
+            let mut closing_amt = format_money_colour_bg(
+                closing_balance(today_date.checked_add_signed(chrono::Duration::days(diff)).unwrap().naive_utc()),
+                base_currency,
                 signed_with_colour,
                 brackets_when_neg);
+            if diff > 0 && !projected_transactions.is_empty() { // projected balance
+                closing_amt = closing_amt.italic();
+            }
+            // flag days on which any visible account is past its limit (or below zero, if it has none)
+            let day = today_date.checked_add_signed(chrono::Duration::days(diff)).unwrap().naive_utc();
+            if visible_accounts.iter().any(|a| a.is_over_limit(a.balance_on(&all_transactions, day))) {
+                let mut flagged = ColouredString::from_str("!").red().bold();
+                flagged.push_coloured_string(closing_amt);
+                closing_amt = flagged;
+            }
             // add date_string
             if today_index - highlight_index + zero_index == 0{ // special formatting for highlighted day
                 if diff == 0 { // special formatting for today
@@ -183,36 +243,50 @@ pub fn render_calendar(
             header.push_coloured_string(closing_amt);
             header.push_string(v_sep.to_string());
             // add transactions
-            for t in ts[week_index as usize][day_index as usize].to_vec().into_iter() {
+            for (t, is_projected) in ts[week_index as usize][day_index as usize].to_vec().into_iter() {
                 let x = ts_counter[week_index as usize][day_index as usize];
                 if (x >= max_transactions_per_day-1) && (ts[week_index as usize][day_index as usize].len() > max_transactions_per_day) { // add to extra total
                     if accs.contains(&t.account_id_1()) {
-                        ts_overflow[week_index as usize][day_index as usize] += t.amount();
+                        ts_overflow[week_index as usize][day_index as usize] += convert_or_same(exchange_rates,
+                            t.amount(), &currency_of(accounts, t.account_id_1()), base_currency, t.date());
                     }
                     if accs.contains(&t.account_id_2()) {
-                        ts_overflow[week_index as usize][day_index as usize] -= t.amount();
+                        ts_overflow[week_index as usize][day_index as usize] -= convert_or_same(exchange_rates,
+                            t.amount_2(), &currency_of(accounts, t.account_id_2()), base_currency, t.date());
                     }
                 } else { // show transaction as a whole
                     let mut s;
+                    let currency = currency_of(accounts, t.account_id_1());
                     let p = match t.is_transfer() {
                         true => {
-                            pence_to_pound_transfer(
+                            format_money_transfer(
                                 t.amount(),
+                                &currency,
                                 signed_with_colour,
                                 brackets_when_neg)
                         },
                         false => {
-                            pence_to_pound_colour(
+                            format_money_colour(
                                 t.amount(),
+                                &currency,
                                 signed_with_colour,
                                 brackets_when_neg)
                         },
                     };
                     s = ColouredString::from_string(truncate_ellipsis(
-                        if t.reference().len() > 0 { t.reference() } else { "(unnamed)".to_string() },
+                        format!("{}{}", if t.is_pending() && !is_projected {"~"} else {""},
+                                if t.reference().len() > 0 { t.reference() } else { "(unnamed)".to_string() }),
                         day_width-p.len()-1));
+                    if t.status() == Status::Reconciled {
+                        s = s.underline();
+                    }
                     s.push_str(" ");
                     s.push_coloured_string(p);
+                    if is_projected {
+                        s = s.italic().dimmed();
+                    } else if t.is_pending() {
+                        s = s.italic();
+                    }
                     t_rows[x].push_coloured_string(s);
                     ts_counter[week_index as usize][day_index as usize] += 1;
                 }
@@ -222,8 +296,9 @@ pub fn render_calendar(
                 > max_transactions_per_day {
                 let mut s;
                 s = ColouredString::from_str("Others ");
-                s.push_coloured_string(pence_to_pound_colour(
+                s.push_coloured_string(format_money_colour(
                     ts_overflow[week_index as usize][day_index as usize],
+                    base_currency,
                     signed_with_colour,
                     brackets_when_neg));
                 s = s.italic();
@@ -237,6 +312,25 @@ pub fn render_calendar(
                 t_rows[x].push_string(v_sep.to_string());
             }
         }
+        // the change in balance over the days of the week in the month
+        if let (Some(_), Some(first), Some(last)) = (month, week_days.first(), week_days.last()) {
+            let subtotal = format_money_colour(
+                closing_balance(*last) - closing_balance(first.pred_opt().unwrap()),
+                base_currency,
+                signed_with_colour,
+                brackets_when_neg);
+            while header.len()+subtotal.len() < 8*(day_width+1) {
+                header.push_str(" ");
+            }
+            header.push_coloured_string(subtotal);
+            header.push_string(v_sep.to_string());
+            for x in 0..max_transactions_per_day {
+                while t_rows[x].len() < 8*(day_width+1) {
+                    t_rows[x].push_str(" ");
+                }
+                t_rows[x].push_string(v_sep.to_string());
+            }
+        }
         // draw those created lines
         sbs.add_line( header);
         for i in 0..max_transactions_per_day {
@@ -254,22 +348,36 @@ pub fn render_day_submenu(
     signed_with_colour:bool,
     brackets_when_neg:bool,
     transactions: &Vec<Transaction>,
+    projected_transactions: &Vec<Transaction>,
     visible_accounts: &Vec<Account>,
+    visible_tags: &Vec<String>,
     accounts: &Vec<Account>,
+    exchange_rates: &Vec<ExchangeRate>,
+    base_currency: &str,
     width:usize,
 ) -> (SideBySide, Option<Transaction>, isize) // return copy of the highlighted transaction too, as well as the largest index for bounds calculations
 {
-    let highlighted_date = Utc::now().date().checked_add_signed(
-        chrono::Duration::days(-cal_this_index+cal_highlight_index)).unwrap().naive_utc();
+    let highlighted_date = Utc::now().date_naive().checked_add_signed(
+        chrono::Duration::days(-cal_this_index+cal_highlight_index)).unwrap();
 
     let accs = acc_ids_from(&visible_accounts);
     let mut highlighted_date_transactions = Vec::new();
     for t in transactions.to_vec().into_iter() {
-        if t.date() == highlighted_date && (accs.contains(&t.account_id_1()) || accs.contains(&t.account_id_2())) { // on display range
+        if t.date() == highlighted_date && (accs.contains(&t.account_id_1()) || accs.contains(&t.account_id_2()))
+            && t.matches_tags(visible_tags) { // on display range
             highlighted_date_transactions.push(t.clone());
         }
     }
     let num_hi_trans = highlighted_date_transactions.len() as isize;
+    let mut highlighted_date_projected = Vec::new();
+    for t in projected_transactions.iter() {
+        if t.date() == highlighted_date && (accs.contains(&t.account_id_1()) || accs.contains(&t.account_id_2()))
+            && t.matches_tags(visible_tags) {
+            highlighted_date_projected.push(t.clone());
+        }
+    }
+    let mut all_transactions = transactions.clone();
+    all_transactions.extend(projected_transactions.iter().cloned());
 
     let mut sbs = SideBySide::new(32);
     sbs.add_line(ColouredString::new()); // blank line at the top!
@@ -280,10 +388,18 @@ pub fn render_day_submenu(
                           highlighted_date.year()), width) {
         sbs.add_line(ColouredString::from_string(s).bold().black().on_white());
     }
-    wrap_string!(sbs, "Closing balance:", width);
-    sbs.add_line(pence_to_pound_colour_bg(get_closing_balance( // TODO deal with wrapping / truncating numbers later
-                            highlighted_date, transactions, visible_accounts
-                        ),signed_with_colour, brackets_when_neg));
+    wrap_string!(sbs, "Closing balance, cleared:", width);
+    sbs.add_line(format_money_colour_bg(get_closing_balance( // TODO deal with wrapping / truncating numbers later
+                            highlighted_date, transactions, visible_accounts, exchange_rates, base_currency, false
+                        ), base_currency, signed_with_colour, brackets_when_neg));
+    if highlighted_date > Utc::now().date_naive() && !projected_transactions.is_empty() {
+        wrap_string!(sbs, "Projected, including pending:", width);
+    } else {
+        wrap_string!(sbs, "Including pending:", width);
+    }
+    sbs.add_line(format_money_colour_bg(get_closing_balance(
+                            highlighted_date, &all_transactions, visible_accounts, exchange_rates, base_currency, true
+                        ), base_currency, signed_with_colour, brackets_when_neg));
     for s in wrap("Transactions:".to_string(), width) {
         sbs.add_line(ColouredString::from_string(s).italic());
     }
@@ -297,17 +413,20 @@ pub fn render_day_submenu(
                 "" => "(unnamed)",
                 r => r
             }.to_string(), width);
+        let currency = currency_of(accounts, t.account_id_1());
         for (index, s) in ss.clone().into_iter().enumerate() {
             let amount = match t.is_transfer() {
                 true => {
-                    pence_to_pound_transfer(
+                    format_money_transfer(
                         t.amount(),
+                        &currency,
                         signed_with_colour,
                         brackets_when_neg)
                 },
                 false => {
-                    pence_to_pound_colour(
+                    format_money_colour(
                         t.amount(),
+                        &currency,
                         signed_with_colour,
                         brackets_when_neg)
                 }
@@ -357,7 +476,23 @@ pub fn render_day_submenu(
                     for s in wrap(ac, width) {
                         sbs.add_line(ColouredString::from_string(s).purple());
                     }
-                    wrap_string!(sbs, format!("Category: {}",acc.name), width);
+                    if t.is_split() { // expand each category line
+                        wrap_string!(sbs, "Split across:", width);
+                        for split in t.splits().into_iter() {
+                            let amount = format_money_colour(split.amount, &currency, signed_with_colour, brackets_when_neg);
+                            let name = match get_account_name(accounts, split.category_id) {
+                                Some(name) => name,
+                                None => "<Category name not found>".to_string(),
+                            };
+                            let mut line = ColouredString::from_string(truncate_ellipsis(
+                                format!("- {}", name), width.saturating_sub(amount.len()+1)));
+                            line.push_str(" ");
+                            line.push_coloured_string(amount);
+                            sbs.add_line(line);
+                        }
+                    } else {
+                        wrap_string!(sbs, format!("Category: {}",acc.name), width);
+                    }
                 },
                 false => { // Display as another account
                     wrap_string!(sbs, "From Account:", width);
@@ -368,6 +503,11 @@ pub fn render_day_submenu(
                     for s in wrap(acc.name, width) {
                         sbs.add_line(ColouredString::from_string(s).purple());
                     }
+                    if t.changes_currency() {
+                        let mut line = ColouredString::from_str("Received: ");
+                        line.push_coloured_string(format_money_transfer(-t.amount_2(), &acc.currency, signed_with_colour, brackets_when_neg));
+                        sbs.add_line(line);
+                    }
                 }
             },
             Err(_) => {
@@ -375,33 +515,60 @@ pub fn render_day_submenu(
             }
         }
         wrap_string!(sbs, format!("Date: {}",t.date()), width);
+        match t.status() {
+            Status::Pending => for s in wrap(format!("Status: {}", if t.date() > Utc::now().date_naive() {"scheduled"} else {"pending"}), width) {
+                sbs.add_line(ColouredString::from_string(s).italic());
+            },
+            Status::Reconciled => for s in wrap("Status: reconciled".to_string(), width) {
+                sbs.add_line(ColouredString::from_string(s).underline());
+            },
+            Status::Cleared => wrap_string!(sbs, "Status: cleared", width),
+        }
+        if !t.tags().is_empty() {
+            wrap_string!(sbs, format!("Tags: {}", t.tags().join(", ")), width);
+        }
         wrap_string!(sbs, "Notes:", width);
         wrap_string!(sbs, t.notes(), width);
     }
+    // forecast entries are shown for information only, so can't be highlighted
+    if !highlighted_date_projected.is_empty() {
+        sbs.add_line(ColouredString::from_string(truncate("----------------".to_string(), width)));
+        for s in wrap("Forecast:".to_string(), width) {
+            sbs.add_line(ColouredString::from_string(s).italic());
+        }
+        for t in highlighted_date_projected.into_iter() {
+            let mut line = ColouredString::from_string(truncate_ellipsis(
+                match t.reference().as_str() { "" => "(unnamed)".to_string(), r => r.to_string() },
+                width.saturating_sub(12)));
+            line.push_str(" ");
+            let currency = currency_of(accounts, t.account_id_1());
+            line.push_coloured_string(match t.is_transfer() {
+                true => format_money_transfer(t.amount(), &currency, signed_with_colour, brackets_when_neg),
+                false => format_money_colour(t.amount(), &currency, signed_with_colour, brackets_when_neg),
+            });
+            sbs.add_line(line.italic().dimmed());
+        }
+    }
     return (sbs,highlighted_transaction,num_hi_trans);
 }
 
 fn get_closing_balance(
     date:NaiveDate,
     transactions: &Vec<Transaction>,
-    visible_accounts: &Vec<Account>
-) -> i64
+    visible_accounts: &Vec<Account>,
+    exchange_rates: &Vec<ExchangeRate>,
+    base_currency: &str,
+    include_pending: bool,
+) -> Money
 {
-    // calculate the closing balance summed over all the <accounts> on >date>
-    let accs = acc_ids_from(&visible_accounts);
-    let mut sum:i64 = 0;
-    for transaction in transactions.to_vec().into_iter() {
-        // If the main transaction account, add amount
-        if transaction.date() <= date && accs.contains(&transaction.account_id_1()) {
-            sum += transaction.amount();
-        }
-        // If the destination / secondary account visible too, then subtract to offset it!
-        // (or if this is the only one visible then it counts negatively)
-        if transaction.date() <= date && accs.contains(&transaction.account_id_2()) {
-            sum -= transaction.amount();
-        }
+    // calculate the closing balance summed over all the <accounts> on <date>, each converted into the base
+    // currency at that day's rate. A transfer between two visible accounts cancels out
+    let mut sum = Money::ZERO;
+    for a in visible_accounts.iter() {
+        let balance = if include_pending { a.balance_on(transactions, date) } else { a.cleared_balance_on(transactions, date) };
+        sum += convert_or_same(exchange_rates, balance, &a.currency, base_currency, date);
     }
-    // TODO this is where to account for prediction values ??
+    // any forecast is accounted for by the caller including projected transactions in <transactions>
     return sum;
 }
 
@@ -0,0 +1,266 @@
+use std::fmt::{Display, Formatter};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use inquire::{Select, Text, Confirm, InquireError, validator::Validation};
+use regex::{Regex, RegexBuilder};
+
+use crate::{Account, pence_to_pound};
+use crate::money::Money;
+use crate::currency::DEFAULT_CURRENCY;
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
+
+// CATEGORY RULE -----------------------------------------------------------------------------------
+// all patterns are matched case-insensitively against the transaction reference
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub enum RulePattern {
+    Substring(String),
+    Prefix(String),
+    Regex(RulePatternRegex),
+}
+
+// a regular expression compiled once, when it is entered or loaded, rather than for every reference it is
+// tried against. Saved as the pattern text, and a file with an invalid one fails to load
+#[derive(Clone)]
+pub struct RulePatternRegex(Regex);
+
+impl RulePatternRegex {
+    pub fn new(pattern: &str) -> Result<RulePatternRegex, regex::Error> {
+        return RegexBuilder::new(pattern).case_insensitive(true).build().map(RulePatternRegex);
+    }
+    pub fn as_str(&self) -> &str {
+        return self.0.as_str();
+    }
+}
+
+impl PartialEq for RulePatternRegex {
+    fn eq(&self, other: &Self) -> bool {
+        return self.as_str() == other.as_str();
+    }
+}
+
+impl Serialize for RulePatternRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(self.as_str());
+    }
+}
+
+impl<'de> Deserialize<'de> for RulePatternRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        return RulePatternRegex::new(&pattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid regular expression /{}/ in a rule: {}", pattern, e)));
+    }
+}
+
+impl Display for RulePattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            RulePattern::Substring(p) => write!(f, "contains \"{}\"", p),
+            RulePattern::Prefix(p) => write!(f, "starts with \"{}\"", p),
+            RulePattern::Regex(p) => write!(f, "matches /{}/", p.as_str()),
+        }
+    }
+}
+
+impl RulePattern {
+    pub fn matches(&self, reference: &str) -> bool {
+        match self {
+            RulePattern::Substring(p) => reference.to_lowercase().contains(&p.to_lowercase()),
+            RulePattern::Prefix(p) => reference.to_lowercase().starts_with(&p.to_lowercase()),
+            RulePattern::Regex(p) => p.0.is_match(reference),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CategoryRule {
+    pub id: u32,
+    pub pattern: RulePattern,
+    pub min_amount: Option<Money>, // inclusive bounds, signed as stored on the transaction
+    pub max_amount: Option<Money>,
+    pub category_id: u32,
+}
+
+impl Display for CategoryRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "Reference {}", self.pattern)?;
+        match (self.min_amount, self.max_amount) {
+            (Some(lo), Some(hi)) => write!(f, ", amount {} to {}", pence_to_pound(lo, true, false), pence_to_pound(hi, true, false)),
+            (Some(lo), None) => write!(f, ", amount at least {}", pence_to_pound(lo, true, false)),
+            (None, Some(hi)) => write!(f, ", amount at most {}", pence_to_pound(hi, true, false)),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl CategoryRule {
+    pub fn matches(&self, reference: &str, amount: Money) -> bool {
+        if let Some(lo) = self.min_amount { if amount < lo { return false } }
+        if let Some(hi) = self.max_amount { if amount > hi { return false } }
+        return self.pattern.matches(reference);
+    }
+}
+
+// category of the first rule (in the order they were created) that matches, if any
+pub fn categorise(rules: &Vec<CategoryRule>, reference: &str, amount: Money) -> Option<u32> {
+    for r in rules.iter() {
+        if r.matches(reference, amount) { return Some(r.category_id) }
+    }
+    return None;
+}
+
+
+// CATEGORY RULE FILE I/O --------------------------------------------------------------------------
+pub fn get_category_rules_from_file(filepath:&str) -> Result<Vec<CategoryRule>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
+}
+
+macro_rules! scrfe {
+    ($e:ident) => {
+        return Err(format!("Error saving category rules to file: {}", $e))
+    }
+}
+
+pub fn save_category_rules_to_file(filepath:&str, rules:Vec<CategoryRule>) -> Result<(), String> {
+    // Serialize to JSON
+    match serde_json::to_string(&rules) {
+        Ok(s) => {
+            // save to file
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
+                Err(e) => scrfe!(e)
+            }
+        },
+        Err(e) => scrfe!(e)
+    }
+    return Ok(());
+}
+
+
+// CATEGORY RULE FUNCTIONS -------------------------------------------------------------------------
+pub fn print_category_rules(rules: &Vec<CategoryRule>, cat_accounts: &Vec<Account>) {
+    if rules.is_empty() {
+        println!("No categorisation rules set up yet.");
+        return;
+    }
+    println!("Rules are tried in this order, the first match wins:");
+    for r in rules.iter() {
+        let name = match cat_accounts.iter().find(|a| a.id == r.category_id) {
+            Some(a) => a.name.clone(),
+            None => "<Category name not found>".to_string(),
+        };
+        println!("- {} => {}", r, name);
+    }
+}
+
+fn query_optional_amount(prompt: &str, initial: Option<Money>) -> Result<Option<Money>, InquireError> {
+    let initial = initial.map(|m| m.display(DEFAULT_CURRENCY, true, false).to_string()).unwrap_or_default();
+    let s = Text::new(prompt)
+        .with_initial_value(&initial)
+        .with_help_message("Leave blank for no limit. Negative values are money spent")
+        .with_validator(|s: &str| {
+            if s.trim().is_empty() { return Ok(Validation::Valid) }
+            match Money::parse(s, DEFAULT_CURRENCY) {
+                Ok(_) => Ok(Validation::Valid),
+                Err(e) => Ok(Validation::Invalid(e.into())),
+            }
+        })
+        .prompt()?;
+    if s.trim().is_empty() { return Ok(None) }
+    return Ok(Some(Money::parse(&s, DEFAULT_CURRENCY).unwrap()));
+}
+
+// pattern, amount range and category of <rule>, each starting from its current value
+fn query_rule_details(rule: &mut CategoryRule, cat_accounts: &Vec<Account>) -> Result<(), InquireError> {
+    if cat_accounts.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No categories to choose from".to_string()))}
+
+    let (current_kind, current_text) = match &rule.pattern {
+        RulePattern::Substring(p) => (0, p.clone()),
+        RulePattern::Prefix(p) => (1, p.clone()),
+        RulePattern::Regex(p) => (2, p.as_str().to_string()),
+    };
+    let kind = Select::new("Match references that:", vec!(
+        "Contain some text",
+        "Start with some text",
+        "Match a regular expression",
+    )).with_starting_cursor(current_kind).prompt()?;
+    rule.pattern = match kind {
+        "Contain some text" => RulePattern::Substring(Text::new("Text:").with_initial_value(&current_text).prompt()?),
+        "Start with some text" => RulePattern::Prefix(Text::new("Text:").with_initial_value(&current_text).prompt()?),
+        _ => RulePattern::Regex(RulePatternRegex::new(&Text::new("Regular expression:")
+            .with_initial_value(&current_text)
+            .with_validator(|s: &str| match RulePatternRegex::new(s) {
+                Ok(_) => Ok(Validation::Valid),
+                Err(e) => Ok(Validation::Invalid(e.to_string().into())),
+            })
+            .prompt()?).expect("regular expression already validated")),
+    };
+
+    let has_range = rule.min_amount.is_some() || rule.max_amount.is_some();
+    if Confirm::new("Only apply to a range of amounts?").with_default(has_range).prompt()? {
+        rule.min_amount = query_optional_amount("Minimum amount:", rule.min_amount)?;
+        rule.max_amount = query_optional_amount("Maximum amount:", rule.max_amount)?;
+    } else {
+        (rule.min_amount, rule.max_amount) = (None, None);
+    }
+
+    let current = cat_accounts.iter().position(|a| a.id == rule.category_id).unwrap_or(0);
+    rule.category_id = Select::new("Category to assign:", cat_accounts.to_vec())
+        .with_starting_cursor(current)
+        .prompt()?.id;
+    return Ok(());
+}
+
+pub fn new_category_rule_from_inputs(existing_rules: &Vec<CategoryRule>, cat_accounts: &Vec<Account>)
+    -> Result<CategoryRule, InquireError>
+{
+    // find new largest id
+    let mut id = 0;
+    for r in existing_rules.iter() {
+        if r.id >= id { id = r.id + 1; }
+    }
+
+    let mut rule = CategoryRule {
+        id,
+        pattern: RulePattern::Substring(String::new()),
+        min_amount: None,
+        max_amount: None,
+        category_id: cat_accounts.first().map_or(0, |a| a.id),
+    };
+    query_rule_details(&mut rule, cat_accounts)?;
+    return Ok(rule);
+}
+
+// <rule> with its pattern, amount range and category changed, keeping its place in the order
+pub fn modified_category_rule_from_inputs(rule: &CategoryRule, cat_accounts: &Vec<Account>)
+    -> Result<CategoryRule, InquireError>
+{
+    let mut new_rule = rule.clone();
+    query_rule_details(&mut new_rule, cat_accounts)?;
+    return Ok(new_rule);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regex_rules_are_saved_as_text_and_checked_on_load() {
+        let json = r#"[{"id":0,"pattern":{"Regex":"^tesco\\s+\\d+"},"min_amount":null,"max_amount":-100,"category_id":3}]"#;
+        let rules: Vec<CategoryRule> = serde_json::from_str(json).unwrap();
+        assert_eq!(categorise(&rules, "TESCO 1234", Money::from_minor(-250)), Some(3));
+        assert_eq!(categorise(&rules, "TESCO 1234", Money::from_minor(-50)), None);
+        assert_eq!(categorise(&rules, "Big Tesco 1234", Money::from_minor(-250)), None);
+        assert_eq!(serde_json::to_string(&rules).unwrap(), json);
+
+        let invalid = r#"[{"id":0,"pattern":{"Regex":"tes(co"},"min_amount":null,"max_amount":null,"category_id":3}]"#;
+        match serde_json::from_str::<Vec<CategoryRule>>(invalid) {
+            Ok(_) => panic!("invalid regular expression loaded"),
+            Err(e) => assert!(e.to_string().contains("/tes(co/")),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use chrono::prelude::*;
+use chrono::{NaiveDate, Duration};
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use serde_json::json;
+
+use crate::{Account, Transaction, format_money};
+use crate::transaction::{Status, status_for};
+use crate::money::Money;
+use crate::currency::currency_of;
+use crate::storage::{Backend, Storage, open_storage, migrate_storage};
+use crate::duplicates::find_duplicates_of;
+use crate::category_rules::{categorise, get_category_rules_from_file};
+use crate::load_error::empty_if_missing;
+use crate::data_dir::{DataPaths, DEFAULT_PROFILE, lock_profile};
+use crate::backup::{create_backup, BACKUPS_KEPT};
+use crate::journal::{Journal, Operation};
+use crate::crypto::{set_data_key, unlock_profile, query_new_passphrase, encrypt_profile, seal_remaining, read_header, change_passphrase, export_decrypted};
+
+// COMMAND LINE ------------------------------------------------------------------------------------
+// with no subcommand the interactive menus are started as usual
+#[derive(Parser)]
+#[command(name = "moxdtrkr", version, about = "Personal finance tracker")]
+pub struct Cli {
+    /// Directory holding the data files [default: ~/.local/share/moxdtrkr]
+    #[arg(long, global = true, env = "MOXDTRKR_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Named profile (ledger) to use, e.g. personal or household
+    #[arg(long, global = true, env = "MOXDTRKR_PROFILE", default_value = DEFAULT_PROFILE)]
+    pub profile: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Add a transaction between an account and a category. Refused if it looks like a duplicate of one
+    /// already there, unless --allow-duplicate is given
+    AddTransaction {
+        #[arg(long)]
+        account: String,
+        /// Defaults to the category that the category rules give for --ref and --amount
+        #[arg(long)]
+        category: Option<String>,
+        /// Negative values are money spent, e.g. -12.50, in the account's currency
+        #[arg(long, allow_hyphen_values = true)]
+        amount: String,
+        /// YYYY-MM-DD, defaults to today. A date after today is recorded as scheduled (pending)
+        #[arg(long)]
+        date: Option<NaiveDate>,
+        /// Record it as pending, not yet cleared by the bank
+        #[arg(long)]
+        pending: bool,
+        #[arg(long = "ref", default_value = "")]
+        reference: String,
+        #[arg(long, default_value = "")]
+        notes: String,
+        /// Add it even if it looks like a duplicate
+        #[arg(long)]
+        allow_duplicate: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add a transfer between two accounts. Refused if it looks like a duplicate of one already there,
+    /// unless --allow-duplicate is given
+    AddTransfer {
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        to: String,
+        /// Strictly positive, e.g. 100.00, in the currency of --from
+        #[arg(long)]
+        amount: String,
+        /// Amount arriving in the currency of --to, required when the two accounts' currencies differ
+        #[arg(long)]
+        received: Option<String>,
+        /// YYYY-MM-DD, defaults to today. A date after today is recorded as scheduled (pending)
+        #[arg(long)]
+        date: Option<NaiveDate>,
+        /// Record it as pending, not yet cleared by the bank
+        #[arg(long)]
+        pending: bool,
+        #[arg(long = "ref", default_value = "")]
+        reference: String,
+        #[arg(long, default_value = "")]
+        notes: String,
+        /// Add it even if it looks like a duplicate
+        #[arg(long)]
+        allow_duplicate: bool,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Closing balance of one or all accounts
+    Balance {
+        #[arg(long)]
+        account: Option<String>,
+        /// YYYY-MM-DD, defaults to today
+        #[arg(long)]
+        at: Option<NaiveDate>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Transactions in a date range (inclusive)
+    List {
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        #[arg(long)]
+        account: Option<String>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// All accounts and categories
+    Accounts {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Encrypt the profile's data files (and backups) with a passphrase
+    Encrypt,
+    /// Change the passphrase of an encrypted profile
+    ChangePassphrase,
+    /// Write decrypted copies of the profile's data files into a directory
+    Export {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Move the profile's accounts and transactions to another storage backend
+    Migrate {
+        #[arg(long, value_enum)]
+        to: Backend,
+    },
+}
+
+// append to the journal before the ledger is written. If there is no journal yet, the menus will start one
+// with a snapshot that already includes <t>
+fn journal_addition(paths: &DataPaths, t: &Transaction) -> Result<(), String> {
+    let mut journal = Journal::new(&paths.journal);
+    if !journal.exists() { return Ok(()) }
+    journal.record(Operation::AddTransaction { after: t.clone() }, "cli");
+    return journal.flush();
+}
+
+// the same backup the menus make before each save
+fn backup_before_insert(paths: &DataPaths) -> Result<(), String> {
+    return create_backup(&paths.data_files(), &paths.backups, BACKUPS_KEPT)
+        .map_err(|e| format!("Could not back up the data, nothing added: {}", e));
+}
+
+// look an account up by name (case-insensitive) or id. Archived ones are left out, as they are from the menus
+fn find_account(accounts: &Vec<Account>, name: &str, category: bool) -> Result<Account, String> {
+    let kind = if category { "category" } else { "account" };
+    for a in accounts.iter() {
+        if a.category == category && (a.name.eq_ignore_ascii_case(name) || a.id.to_string() == name) {
+            if a.archived { return Err(format!("The {} \"{}\" is archived, unarchive it first", kind, a.name)) }
+            return Ok(a.clone());
+        }
+    }
+    return Err(format!("No {} called \"{}\"", kind, name));
+}
+
+// the same check the menus make before adding, but as there is no one to ask it is an error
+fn check_not_duplicate(t: &Transaction, storage: &dyn Storage, accounts: &Vec<Account>, window_days: i64)
+    -> Result<(), String>
+{
+    let window = Duration::days(window_days);
+    let nearby = empty_if_missing(storage.transactions_between(Some(t.date() - window), Some(t.date() + window), &[t.account_id_1()]))
+        .map_err(|e| format!("{}: {}", storage.transactions_location(), e))?;
+    let found = find_duplicates_of(t, &nearby, window_days);
+    if found.is_empty() { return Ok(()) }
+    let lines: Vec<String> = found.iter()
+        .map(|(x, kind)| format!("  ({}) {}  {}  ref: {}", kind, x.date(),
+                                 format_money(x.amount(), &currency_of(accounts, x.account_id_1()), true, false), x.reference()))
+        .collect();
+    return Err(format!("This looks like a duplicate of:\n{}\nPass --allow-duplicate to add it anyway", lines.join("\n")));
+}
+
+fn name_of(accounts: &Vec<Account>, id: u32) -> String {
+    match accounts.iter().find(|a| a.id == id) {
+        Some(a) => a.name.clone(),
+        None => "<Account name not found>".to_string(),
+    }
+}
+
+// amounts are in minor units (pence, cents, yen...) of the account's currency
+fn transaction_json(t: &Transaction, accounts: &Vec<Account>) -> serde_json::Value {
+    let mut value = json!({
+        "date": t.date().to_string(),
+        "account": name_of(accounts, t.account_id_1()),
+        "transfer": t.is_transfer(),
+        // category for transactions, destination account for transfers
+        "other": name_of(accounts, t.account_id_2()),
+        "amount_minor": t.amount(),
+        "currency": currency_of(accounts, t.account_id_1()),
+        "splits": t.splits().iter()
+            .map(|s| json!({"category": name_of(accounts, s.category_id), "amount_minor": s.amount}))
+            .collect::<Vec<serde_json::Value>>(),
+        "reference": t.reference(),
+        "notes": t.notes(),
+        "tags": t.tags(),
+        "status": t.status().to_string(),
+    });
+    if t.changes_currency() {
+        value["received_minor"] = json!(-t.amount_2());
+        value["received_currency"] = json!(currency_of(accounts, t.account_id_2()));
+    }
+    return value;
+}
+
+fn print_transaction(t: &Transaction, accounts: &Vec<Account>) {
+    println!("{}\t{}\t{}\t{}\t{}",
+             t.date(),
+             format_money(t.amount(), &currency_of(accounts, t.account_id_1()), true, false),
+             name_of(accounts, t.account_id_1()),
+             if t.is_transfer() { format!("to: {}", name_of(accounts, t.account_id_2())) }
+             else if t.is_split() {
+                 let names: Vec<String> = t.splits().iter().map(|s| name_of(accounts, s.category_id)).collect();
+                 format!("cat: split ({})", names.join(", "))
+             }
+             else { format!("cat: {}", name_of(accounts, t.account_id_2())) },
+             t.reference());
+}
+
+// run a non-interactive command against the same data files as the interactive mode
+pub fn run(command: Command, paths: &DataPaths, duplicate_window_days: i64) -> Result<(), String> {
+    // commands that write hold the profile's lock until they return, and refuse to run while the menus have
+    // the profile open, as their next save would overwrite whatever was written here
+    let _lock = match command {
+        Command::AddTransaction { .. } | Command::AddTransfer { .. } | Command::Encrypt | Command::ChangePassphrase
+        | Command::Migrate { .. } => Some(lock_profile(paths).map_err(|e| format!("{}, close it first", e))?),
+        Command::Balance { .. } | Command::List { .. } | Command::Accounts { .. } | Command::Export { .. } => None,
+    };
+    // commands that work on the data files as a whole rather than on the ledger
+    if let Command::Encrypt = command {
+        if read_header(&paths.encryption)?.is_none() {
+            let count = encrypt_profile(paths, &query_new_passphrase()?)?;
+            println!("Encrypted {} file(s). Keep the passphrase safe, the data can't be recovered without it", count);
+            return Ok(());
+        }
+        // already encrypted, but an earlier run may have been interrupted part way through
+        set_data_key(unlock_profile(paths)?);
+        let count = seal_remaining(paths)?;
+        if count == 0 { return Err("Profile is already encrypted".to_string()) }
+        println!("Encrypted the remaining {} file(s)", count);
+        return Ok(());
+    }
+    set_data_key(unlock_profile(paths)?);
+    match &command {
+        Command::ChangePassphrase => {
+            change_passphrase(paths, &query_new_passphrase()?)?;
+            println!("Passphrase changed");
+            return Ok(());
+        },
+        Command::Export { out } => {
+            let count = export_decrypted(paths, out)?;
+            println!("Exported {} file(s) to {}", count, out.display());
+            return Ok(());
+        },
+        Command::Migrate { to } => {
+            let (accounts, transactions) = migrate_storage(paths, *to)?;
+            println!("Moved {} account(s) and {} transaction(s) to {}", accounts, transactions, to);
+            return Ok(());
+        },
+        _ => {},
+    }
+
+    // refuse to do anything with data that failed to load, rather than saving over it
+    let mut storage = open_storage(paths)?;
+    let accounts = empty_if_missing(storage.load_accounts())
+        .map_err(|e| format!("{}: {}", storage.accounts_location(), e))?;
+    let today = Utc::now().date_naive();
+
+    match command {
+        Command::AddTransaction { account, category, amount, date, pending, reference, notes, allow_duplicate, json } => {
+            let account = find_account(&accounts, &account, false)?;
+            let amount = Money::parse(&amount, &account.currency)?;
+            let category_id = match category {
+                Some(name) => find_account(&accounts, &name, true)?.id,
+                None => {
+                    let rules = empty_if_missing(get_category_rules_from_file(&paths.category_rules))
+                        .map_err(|e| format!("{}: {}", paths.category_rules, e))?;
+                    match categorise(&rules, &reference, amount) {
+                        Some(id) if accounts.iter().any(|a| a.id == id && !a.archived) => id,
+                        _ => return Err(format!("No category rule matches \"{}\", give one with --category", reference)),
+                    }
+                },
+            };
+            let t = Transaction::new_transaction(account.id, category_id, amount, reference, date.unwrap_or(today), notes)
+                .with_status(status_for(date.unwrap_or(today), if pending { Status::Pending } else { Status::Cleared }));
+            if !allow_duplicate { check_not_duplicate(&t, storage.as_ref(), &accounts, duplicate_window_days)? }
+            backup_before_insert(paths)?;
+            journal_addition(paths, &t)?;
+            storage.insert_transaction(&t)?;
+            if json { println!("{}", transaction_json(&t, &accounts)) } else { print_transaction(&t, &accounts) }
+        },
+        Command::AddTransfer { from, to, amount, received, date, pending, reference, notes, allow_duplicate, json } => {
+            let src = find_account(&accounts, &from, false)?;
+            let dest = find_account(&accounts, &to, false)?;
+            if src.id == dest.id { return Err("Can't transfer from an account to itself".to_string()) }
+            let pence = Money::parse_positive(&amount, &src.currency)?;
+            let mut t = Transaction::new_transfer(src.id, dest.id, pence, reference, date.unwrap_or(today), notes)
+                .with_status(status_for(date.unwrap_or(today), if pending { Status::Pending } else { Status::Cleared }));
+            match received {
+                Some(r) if src.currency != dest.currency => {
+                    let received = Money::parse_positive(&r, &dest.currency)?;
+                    t = t.with_amount_2(-received);
+                },
+                Some(_) => return Err(format!("--received is only for transfers between currencies, both accounts are in {}", src.currency)),
+                None if src.currency != dest.currency => return Err(format!(
+                    "{} is in {} and {} is in {}, give the amount arriving with --received", src.name, src.currency, dest.name, dest.currency)),
+                None => {},
+            }
+            if !allow_duplicate { check_not_duplicate(&t, storage.as_ref(), &accounts, duplicate_window_days)? }
+            backup_before_insert(paths)?;
+            journal_addition(paths, &t)?;
+            storage.insert_transaction(&t)?;
+            if json { println!("{}", transaction_json(&t, &accounts)) } else { print_transaction(&t, &accounts) }
+        },
+        Command::Balance { account, at, json } => {
+            let date = at.unwrap_or(today);
+            let selected = match account {
+                Some(name) => vec!(find_account(&accounts, &name, false)?),
+                None => Account::acc_accounts(&accounts),
+            };
+            let mut balances = Vec::new();
+            for a in selected.iter() {
+                let transactions = empty_if_missing(storage.transactions_between(None, Some(date), &[a.id]))
+                    .map_err(|e| format!("{}: {}", storage.transactions_location(), e))?;
+                balances.push((a.name.clone(), a.currency.clone(), a.balance_on(&transactions, date)));
+            }
+            if json {
+                let list: Vec<serde_json::Value> = balances.iter()
+                    .map(|(name, currency, balance)| json!({"account": name, "date": date.to_string(), "balance_minor": balance, "currency": currency}))
+                    .collect();
+                println!("{}", serde_json::Value::Array(list));
+            } else {
+                for (name, currency, balance) in balances.iter() {
+                    println!("{}\t{}", name, format_money(*balance, currency, true, false));
+                }
+            }
+        },
+        Command::List { from, to, account, json } => {
+            let account_id = match account {
+                Some(name) => Some(find_account(&accounts, &name, false)?.id),
+                None => None,
+            };
+            let ids: Vec<u32> = account_id.into_iter().collect();
+            let mut listed = empty_if_missing(storage.transactions_between(from, to, &ids))
+                .map_err(|e| format!("{}: {}", storage.transactions_location(), e))?;
+            listed.sort_by_key(|t| t.date());
+            if json {
+                let list: Vec<serde_json::Value> = listed.iter().map(|t| transaction_json(t, &accounts)).collect();
+                println!("{}", serde_json::Value::Array(list));
+            } else {
+                for t in listed.iter() { print_transaction(t, &accounts) }
+            }
+        },
+        Command::Accounts { json } => {
+            if json {
+                println!("{}", serde_json::to_string(&accounts).map_err(|e| e.to_string())?);
+            } else {
+                for a in accounts.iter() {
+                    println!("{}\t{}\t{}", a.id, if a.category { "category".to_string() } else { format!("{} {}", a.account_type, a.currency) }, a.name);
+                }
+            }
+        },
+        Command::Encrypt | Command::ChangePassphrase | Command::Export { .. } | Command::Migrate { .. } => {}, // handled above
+    }
+    return Ok(());
+}
@@ -80,6 +80,8 @@ macro_rules! enroll {
 impl ColouredString {
     enroll!(bold);
     enroll!(italic);
+    enroll!(dimmed);
+    enroll!(underline);
 
     enroll!(black);
     enroll!(red);
@@ -0,0 +1,362 @@
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use argon2::{Argon2, Algorithm, Version, Params};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce, Key};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, rand_core::RngCore};
+use inquire::{Password, InquireError};
+
+use crate::backup::write_atomically;
+use crate::load_error::{LoadError, read_data_file};
+use crate::data_dir::DataPaths;
+
+// ENCRYPTED STORAGE -------------------------------------------------------------------------------
+// Each profile can be encrypted on its own. <profile dir>/encryption.json holds a random data key,
+// itself encrypted with a key derived from the passphrase (Argon2id), so that changing the passphrase
+// only rewrites that one file. Each data file is then MAGIC + nonce + XChaCha20-Poly1305 ciphertext.
+// Plaintext files are still read as they are, so a half-encrypted directory is never unreadable.
+const MAGIC: &[u8] = b"MOXDTRKR-ENC1\n";
+const LINE_PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+const HEADER_VERSION: u32 = 1;
+pub const PASSPHRASE_ENV: &str = "MOXDTRKR_PASSPHRASE";
+const PASSPHRASE_MIN_LEN: usize = 8;
+
+pub type DataKey = [u8; 32];
+
+#[derive(Serialize, Deserialize)]
+pub struct EncryptionHeader {
+    version: u32,
+    m_cost: u32, // Argon2id parameters, stored so that they can be raised later without breaking old files
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,        // hex
+    wrapped_key: String, // hex, nonce + encrypted data key
+}
+
+// key used by every data file load and save, None when the current profile isn't encrypted
+static DATA_KEY: Mutex<Option<DataKey>> = Mutex::new(None);
+
+pub fn set_data_key(key: Option<DataKey>) {
+    *DATA_KEY.lock().unwrap() = key;
+}
+fn data_key() -> Option<DataKey> {
+    return *DATA_KEY.lock().unwrap();
+}
+
+
+// ENCRYPTION --------------------------------------------------------------------------------------
+fn encrypt(key: &DataKey, plain: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut out = nonce.to_vec();
+    out.append(&mut cipher.encrypt(&nonce, plain).map_err(|_| "Encryption failed".to_string())?);
+    return Ok(out);
+}
+
+fn decrypt(key: &DataKey, data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < NONCE_LEN { return Err("Encrypted data is truncated".to_string()) }
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    return cipher.decrypt(XNonce::from_slice(&data[..NONCE_LEN]), &data[NONCE_LEN..])
+        .map_err(|_| "Decryption failed, wrong key or the data has been altered".to_string());
+}
+
+// contents as they should be written to disk for the current profile
+pub fn seal(plain: &[u8]) -> Result<Vec<u8>, String> {
+    return match data_key() {
+        Some(key) => {
+            let mut out = MAGIC.to_vec();
+            out.append(&mut encrypt(&key, plain)?);
+            Ok(out)
+        },
+        None => Ok(plain.to_vec()),
+    }
+}
+
+// one line of an append-only text file (the journal), encrypted and hex encoded if a key is set
+pub fn seal_line(line: &str) -> Result<String, String> {
+    return match data_key() {
+        Some(key) => Ok(format!("{}{}", LINE_PREFIX, to_hex(&encrypt(&key, line.as_bytes())?))),
+        None => Ok(line.to_string()),
+    }
+}
+
+pub fn unseal_line(line: &str) -> Result<String, String> {
+    let hex = match line.strip_prefix(LINE_PREFIX) {
+        Some(h) => h,
+        None => return Ok(line.to_string()),
+    };
+    return match data_key() {
+        Some(key) => String::from_utf8(decrypt(&key, &from_hex(hex)?)?).map_err(|e| e.to_string()),
+        None => Err("Line is encrypted but the profile has no encryption.json".to_string()),
+    }
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    return data.starts_with(MAGIC);
+}
+
+// contents of a file as read from disk, decrypted if it was encrypted
+pub fn unseal(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !is_sealed(&data) { return Ok(data) }
+    return match data_key() {
+        Some(key) => decrypt(&key, &data[MAGIC.len()..]),
+        None => Err("File is encrypted but the profile has no encryption.json".to_string()),
+    }
+}
+
+
+// PASSPHRASES -------------------------------------------------------------------------------------
+fn derive_key(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Result<DataKey, String> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32)).map_err(|e| e.to_string())?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    return Ok(key);
+}
+
+// a header protecting <data_key> with <passphrase>
+fn wrap_key(data_key: &DataKey, passphrase: &str) -> Result<EncryptionHeader, String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let (m_cost, t_cost, p_cost) = (Params::DEFAULT_M_COST, Params::DEFAULT_T_COST, Params::DEFAULT_P_COST);
+    let kek = derive_key(passphrase, &salt, m_cost, t_cost, p_cost)?;
+    return Ok(EncryptionHeader {
+        version: HEADER_VERSION,
+        m_cost, t_cost, p_cost,
+        salt: to_hex(&salt),
+        wrapped_key: to_hex(&encrypt(&kek, data_key)?),
+    });
+}
+
+fn unwrap_key(header: &EncryptionHeader, passphrase: &str) -> Result<DataKey, String> {
+    let kek = derive_key(passphrase, &from_hex(&header.salt)?, header.m_cost, header.t_cost, header.p_cost)?;
+    let key = decrypt(&kek, &from_hex(&header.wrapped_key)?).map_err(|_| "Wrong passphrase".to_string())?;
+    return key.try_into().map_err(|_| "Encryption header is corrupt".to_string());
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s.len() % 2 != 0 { return Err("Encryption header is corrupt".to_string()) }
+    return (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i+2], 16).map_err(|_| "Encryption header is corrupt".to_string()))
+        .collect();
+}
+
+pub fn read_header(filepath: &str) -> Result<Option<EncryptionHeader>, String> {
+    return match std::fs::read_to_string(filepath) {
+        Ok(s) => serde_json::from_str(&s).map(Some).map_err(|e| format!("{}: {}", filepath, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {}", filepath, e)),
+    }
+}
+
+fn write_header(filepath: &str, header: &EncryptionHeader) -> Result<(), String> {
+    let s = serde_json::to_string_pretty(header).map_err(|e| e.to_string())?;
+    return write_atomically(filepath, s.as_bytes()).map_err(|e| format!("{}: {}", filepath, e));
+}
+
+fn query_passphrase(prompt: &str) -> Result<String, InquireError> {
+    return Password::new(prompt).prompt();
+}
+
+// asked twice so that a typo doesn't lock the data away
+pub fn query_new_passphrase() -> Result<String, String> {
+    loop {
+        let p = query_passphrase("New passphrase:").map_err(|e| e.to_string())?;
+        if p.chars().count() < PASSPHRASE_MIN_LEN {
+            println!("Use at least {} characters", PASSPHRASE_MIN_LEN);
+            continue;
+        }
+        if query_passphrase("Repeat new passphrase:").map_err(|e| e.to_string())? == p { return Ok(p) }
+        println!("Passphrases don't match, try again");
+    }
+}
+
+// the data key for a profile, asking for its passphrase (or taking it from MOXDTRKR_PASSPHRASE) if it
+// is encrypted, or None if it isn't
+pub fn unlock_profile(paths: &DataPaths) -> Result<Option<DataKey>, String> {
+    let header = match read_header(&paths.encryption)? {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    let unsealed = unsealed_files(paths);
+    if !unsealed.is_empty() {
+        println!("Warning: {} file(s) of this encrypted profile are still plaintext (was encrypting it interrupted?), run `encrypt` again to finish", unsealed.len());
+    }
+    if let Ok(p) = std::env::var(PASSPHRASE_ENV) {
+        return unwrap_key(&header, &p).map(Some);
+    }
+    for _ in 0..3 {
+        let p = query_passphrase(&format!("Passphrase for profile {}:", paths.profile)).map_err(|e| e.to_string())?;
+        match unwrap_key(&header, &p) {
+            Ok(key) => return Ok(Some(key)),
+            Err(e) => println!("{}", e),
+        }
+    }
+    return Err("Too many wrong passphrases".to_string());
+}
+
+
+// COMMANDS ----------------------------------------------------------------------------------------
+// every data file of the profile, including those in its backups
+fn profile_files(paths: &DataPaths) -> Vec<String> {
+    let mut filepaths: Vec<String> = paths.data_files().iter().map(|f| f.to_string()).collect();
+    if let Ok(backups) = std::fs::read_dir(&paths.backups) {
+        for b in backups.flatten() {
+            if let Ok(files) = std::fs::read_dir(b.path()) {
+                for f in files.flatten() { filepaths.push(f.path().to_string_lossy().to_string()) }
+            }
+        }
+    }
+    return filepaths;
+}
+
+// files of an encrypted profile still in plaintext, left behind if encrypting it was interrupted
+pub fn unsealed_files(paths: &DataPaths) -> Vec<String> {
+    let mut unsealed = Vec::new();
+    for filepath in profile_files(paths).into_iter() {
+        if filepath.ends_with(".sqlite3") { continue }
+        if let Ok(data) = std::fs::read(&filepath) {
+            if !is_sealed(&data) { unsealed.push(filepath) }
+        }
+    }
+    if let Ok(s) = std::fs::read_to_string(&paths.journal) {
+        if s.lines().any(|line| !line.starts_with(LINE_PREFIX)) { unsealed.push(paths.journal.clone()) }
+    }
+    return unsealed;
+}
+
+// encrypt every data file of a plaintext profile, including its backups, returning how many files were
+// encrypted. The header is written first, so if this is interrupted seal_remaining finishes the job
+pub fn encrypt_profile(paths: &DataPaths, passphrase: &str) -> Result<usize, String> {
+    if read_header(&paths.encryption)?.is_some() { return Err("Profile is already encrypted".to_string()) }
+    if std::path::Path::new(&paths.ledger_db).exists() {
+        return Err("Only JSON files can be encrypted, run `migrate --to json` first".to_string());
+    }
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    write_header(&paths.encryption, &wrap_key(&key, passphrase)?)?;
+    set_data_key(Some(key));
+    return seal_remaining(paths);
+}
+
+// encrypt whichever files of the profile are still plaintext with the current data key, returning how
+// many there were
+pub fn seal_remaining(paths: &DataPaths) -> Result<usize, String> {
+    if data_key().is_none() { return Err("Profile is not encrypted".to_string()) }
+    let mut count = 0;
+    for filepath in unsealed_files(paths).iter() {
+        if *filepath == paths.journal { continue }
+        let data = std::fs::read(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+        write_atomically(filepath, &seal(&data)?).map_err(|e| format!("{}: {}", filepath, e))?;
+        count += 1;
+    }
+    // the journal is appended to line by line, so each line is sealed on its own
+    if let Ok(s) = std::fs::read_to_string(&paths.journal) {
+        if s.lines().any(|line| !line.starts_with(LINE_PREFIX)) {
+            let mut sealed = String::new();
+            for line in s.lines() {
+                sealed.push_str(&if line.starts_with(LINE_PREFIX) { line.to_string() } else { seal_line(line)? });
+                sealed.push('\n');
+            }
+            write_atomically(&paths.journal, sealed.as_bytes()).map_err(|e| format!("{}: {}", paths.journal, e))?;
+            count += 1;
+        }
+    }
+    return Ok(count);
+}
+
+// re-wrap the data key under a new passphrase, the data files themselves are untouched
+pub fn change_passphrase(paths: &DataPaths, new_passphrase: &str) -> Result<(), String> {
+    let key = match data_key() {
+        Some(k) => k,
+        None => return Err("Profile is not encrypted".to_string()),
+    };
+    return write_header(&paths.encryption, &wrap_key(&key, new_passphrase)?);
+}
+
+// write a decrypted copy of every data file into <out_dir>, returning how many files were written
+pub fn export_decrypted(paths: &DataPaths, out_dir: &std::path::Path) -> Result<usize, String> {
+    std::fs::create_dir_all(out_dir).map_err(|e| format!("{}: {}", out_dir.display(), e))?;
+    let out_of = |filepath: &str| out_dir.join(std::path::Path::new(filepath).file_name().unwrap_or_default());
+    for filepath in paths.data_files().iter().chain([paths.journal.as_str()].iter()) {
+        let out = out_of(filepath);
+        if out.exists() { return Err(format!("{} already exists, not overwriting it", out.display())) }
+    }
+    let mut count = 0;
+    for filepath in paths.data_files().iter() {
+        if *filepath == paths.ledger_db { continue } // encrypted profiles never use the database
+        let s = match read_data_file(filepath) {
+            Ok(s) => s,
+            Err(LoadError::Missing) => continue,
+            Err(e) => return Err(format!("{}: {}", filepath, e)),
+        };
+        let out = out_of(filepath);
+        std::fs::write(&out, s).map_err(|e| format!("{}: {}", out.display(), e))?;
+        count += 1;
+    }
+    if let Ok(s) = std::fs::read_to_string(&paths.journal) {
+        let mut plain = String::new();
+        for line in s.lines() {
+            plain.push_str(&unseal_line(line)?);
+            plain.push('\n');
+        }
+        let out = out_of(&paths.journal);
+        std::fs::write(&out, plain).map_err(|e| format!("{}: {}", out.display(), e))?;
+        count += 1;
+    }
+    return Ok(count);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // DATA_KEY is shared by every test, so everything that sets it happens in this one test
+    #[test]
+    fn sealed_data_only_opens_with_its_key() {
+        set_data_key(None);
+        assert_eq!(seal(b"plain").unwrap(), b"plain".to_vec());
+        assert_eq!(seal_line("plain").unwrap(), "plain");
+
+        let key = [7u8; 32];
+        set_data_key(Some(key));
+        let sealed = seal(b"{\"accounts\":[]}").unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(unseal(sealed.clone()).unwrap(), b"{\"accounts\":[]}".to_vec());
+        assert_ne!(seal(b"{\"accounts\":[]}").unwrap(), sealed); // a fresh nonce every time
+        let line = seal_line("{\"op\":\"Snapshot\"}").unwrap();
+        assert!(line.starts_with(LINE_PREFIX));
+        assert_eq!(unseal_line(&line).unwrap(), "{\"op\":\"Snapshot\"}");
+        assert_eq!(unseal(b"still plaintext".to_vec()).unwrap(), b"still plaintext".to_vec());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(unseal(tampered).is_err());
+        set_data_key(Some([8u8; 32]));
+        assert!(unseal(sealed.clone()).is_err());
+        assert!(unseal_line(&line).is_err());
+        set_data_key(None);
+        assert!(unseal(sealed).is_err());
+    }
+
+    #[test]
+    fn data_key_only_unwraps_with_its_passphrase() {
+        let key = [42u8; 32];
+        let header = wrap_key(&key, "correct horse").unwrap();
+        assert_eq!(unwrap_key(&header, "correct horse").unwrap(), key);
+        assert_eq!(unwrap_key(&header, "wrong horse").unwrap_err(), "Wrong passphrase");
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(from_hex(&to_hex(&[0, 15, 16, 255])).unwrap(), vec!(0, 15, 16, 255));
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
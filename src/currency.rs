@@ -0,0 +1,293 @@
+use std::fmt::{Display, Formatter};
+use serde::{Serialize, Deserialize};
+use chrono::prelude::*;
+use chrono::NaiveDate;
+use inquire::{Select, Text, CustomType, DateSelect, InquireError};
+
+use crate::{Account, Transaction};
+use crate::money::Money;
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
+
+// CURRENCY ----------------------------------------------------------------------------------------
+// amounts are always stored in minor units (pence, cents, ...) of the currency of the account they
+// belong to. Currencies are referred to by their ISO 4217 code
+pub const DEFAULT_CURRENCY: &str = "GBP";
+
+// (code, symbol, number of decimal places)
+const CURRENCIES: [(&str, &str, u32); 14] = [
+    ("GBP", "£", 2),
+    ("EUR", "€", 2),
+    ("USD", "$", 2),
+    ("JPY", "¥", 0),
+    ("CHF", "CHF ", 2),
+    ("CAD", "CA$", 2),
+    ("AUD", "A$", 2),
+    ("NZD", "NZ$", 2),
+    ("SEK", "SEK ", 2),
+    ("NOK", "NOK ", 2),
+    ("DKK", "DKK ", 2),
+    ("PLN", "PLN ", 2),
+    ("INR", "₹", 2),
+    ("CNY", "CN¥", 2),
+];
+
+pub fn default_currency() -> String { return DEFAULT_CURRENCY.to_string() }
+
+pub fn currency_codes() -> Vec<String> {
+    return CURRENCIES.iter().map(|(code, _, _)| code.to_string()).collect();
+}
+
+// anything not in the list above is shown by its code, with 2 decimal places
+pub fn currency_symbol(code: &str) -> String {
+    return match CURRENCIES.iter().find(|(c, _, _)| *c == code) {
+        Some((_, symbol, _)) => symbol.to_string(),
+        None => format!("{} ", code),
+    }
+}
+
+pub fn minor_digits(code: &str) -> u32 {
+    return match CURRENCIES.iter().find(|(c, _, _)| *c == code) {
+        Some((_, _, digits)) => *digits,
+        None => 2,
+    }
+}
+
+// currency of the account (or category) with id <id>, the default if it can't be found
+pub fn currency_of(accounts: &Vec<Account>, id: u32) -> String {
+    return match accounts.iter().find(|a| a.id == id) {
+        Some(a) => a.currency.clone(),
+        None => default_currency(),
+    }
+}
+
+pub fn query_currency(prompt: &str, current: &str) -> Result<String, InquireError> {
+    let codes = currency_codes();
+    let start = codes.iter().position(|c| c == current).unwrap_or(0);
+    return Select::new(prompt, codes).with_starting_cursor(start).prompt();
+}
+
+
+// EXCHANGE RATE -----------------------------------------------------------------------------------
+// entered by hand or imported, never fetched. The rate in force on a date is the latest one on or
+// before it, and either direction of a pair can be used
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExchangeRate {
+    #[serde(with="crate::transaction::test_date_format")]
+    pub date: NaiveDate,
+    pub from: String,
+    pub to: String,
+    pub rate: f64, // units of <to> for one unit of <from>
+}
+
+impl Display for ExchangeRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}  1 {} = {} {}", self.date, self.from, self.rate, self.to)
+    }
+}
+
+// rate to multiply an amount in <from> by to get <to> on <date>. Falls back to the earliest rate known
+// when every rate for the pair is after <date>
+pub fn rate_on(rates: &Vec<ExchangeRate>, from: &str, to: &str, date: NaiveDate) -> Option<f64> {
+    if from == to { return Some(1.0) }
+    let mut pair: Vec<(NaiveDate, f64)> = Vec::new();
+    for r in rates.iter() {
+        if r.rate <= 0.0 { continue }
+        if r.from == from && r.to == to { pair.push((r.date, r.rate)) }
+        if r.from == to && r.to == from { pair.push((r.date, 1.0/r.rate)) }
+    }
+    pair.sort_by_key(|(d, _)| *d);
+    return match pair.iter().rev().find(|(d, _)| *d <= date) {
+        Some((_, rate)) => Some(*rate),
+        None => pair.first().map(|(_, rate)| *rate),
+    }
+}
+
+// <amount> in <from> in <to>, None if there is no rate between them (or the result is out of range)
+pub fn convert(rates: &Vec<ExchangeRate>, amount: Money, from: &str, to: &str, date: NaiveDate) -> Option<Money> {
+    if from == to { return Some(amount) }
+    let rate = rate_on(rates, from, to, date)?;
+    let scale = 10f64.powi(minor_digits(to) as i32 - minor_digits(from) as i32);
+    let converted = (amount.minor() as f64 * rate * scale).round();
+    if !converted.is_finite() || converted.abs() >= i64::MAX as f64 { return None }
+    return Some(Money::from_minor(converted as i64));
+}
+
+// as convert, but left as it is when there is no rate. Totals are then off, so wherever these are
+// shown the currencies in <missing_rates> are warned about
+pub fn convert_or_same(rates: &Vec<ExchangeRate>, amount: Money, from: &str, to: &str, date: NaiveDate) -> Money {
+    return convert(rates, amount, from, to, date).unwrap_or(amount);
+}
+
+// currencies of accounts in use that can't be converted to <base>
+pub fn missing_rates(accounts: &Vec<Account>, rates: &Vec<ExchangeRate>, base: &str) -> Vec<String> {
+    let mut missing: Vec<String> = Vec::new();
+    let today = Utc::now().date_naive();
+    for a in accounts.iter().filter(|a| !a.category && !a.archived) {
+        if rate_on(rates, &a.currency, base, today).is_none() && !missing.contains(&a.currency) {
+            missing.push(a.currency.clone());
+        }
+    }
+    return missing;
+}
+
+// every transaction with its amounts in <base>, at the rate on the day it happened. For reports that
+// add up across accounts (category totals, graphs, budgets)
+pub fn in_base_currency(transactions: &Vec<Transaction>, accounts: &Vec<Account>, rates: &Vec<ExchangeRate>, base: &str)
+                        -> Vec<Transaction> {
+    return transactions.iter().map(|t| {
+        let from_1 = currency_of(accounts, t.account_id_1());
+        let from_2 = currency_of(accounts, t.account_id_2());
+        if from_1 == base && (!t.is_transfer() || from_2 == base) { return t.clone() }
+        t.converted(|a| convert_or_same(rates, a, &from_1, base, t.date()),
+                    |a| convert_or_same(rates, a, &from_2, base, t.date()))
+    }).collect();
+}
+
+
+// EXCHANGE RATE FILE I/O --------------------------------------------------------------------------
+pub fn get_exchange_rates_from_file(filepath:&str) -> Result<Vec<ExchangeRate>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
+}
+
+macro_rules! serfe {
+    ($e:ident) => {
+        return Err(format!("Error saving exchange rates to file: {}", $e))
+    }
+}
+
+pub fn save_exchange_rates_to_file(filepath:&str, rates:Vec<ExchangeRate>) -> Result<(), String> {
+    // Serialize to JSON
+    match serde_json::to_string(&rates) {
+        Ok(s) => {
+            // save to file
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
+                Err(e) => serfe!(e)
+            }
+        },
+        Err(e) => serfe!(e)
+    }
+    return Ok(());
+}
+
+// rows of date (YYYY-MM-DD), from, to, rate, e.g. "2026-01-31,EUR,GBP,0.86". A header row is skipped.
+// Returns the rates read, and a message for each row that couldn't be
+pub fn read_exchange_rates_csv(filepath: &str) -> Result<(Vec<ExchangeRate>, Vec<String>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(filepath)
+        .map_err(|e| e.to_string())?;
+    let mut rates = Vec::new();
+    let mut errors = Vec::new();
+    for (line, r) in reader.records().enumerate() {
+        let record = r.map_err(|e| e.to_string())?;
+        let field = |i: usize| record.get(i).map(|s| s.trim()).unwrap_or("");
+        if line == 0 && field(0).eq_ignore_ascii_case("date") { continue }
+        let date = match NaiveDate::parse_from_str(field(0), "%F") {
+            Ok(d) => d,
+            Err(e) => { errors.push(format!("line {}: date \"{}\": {}", line+1, field(0), e)); continue },
+        };
+        let rate = match field(3).parse::<f64>() {
+            Ok(x) if x > 0.0 && x.is_finite() => x,
+            _ => { errors.push(format!("line {}: \"{}\" is not a positive rate", line+1, field(3))); continue },
+        };
+        if field(1).is_empty() || field(2).is_empty() {
+            errors.push(format!("line {}: missing currency", line+1));
+            continue;
+        }
+        let (from, to) = (field(1).to_uppercase(), field(2).to_uppercase());
+        if from == to {
+            errors.push(format!("line {}: rate from {} to itself", line+1, from));
+            continue;
+        }
+        rates.push(ExchangeRate { date, from, to, rate });
+    }
+    return Ok((rates, errors));
+}
+
+
+// EXCHANGE RATE FUNCTIONS -------------------------------------------------------------------------
+pub fn print_exchange_rates(rates: &Vec<ExchangeRate>, accounts: &Vec<Account>, base: &str) {
+    println!("Base currency: {}", base);
+    if rates.is_empty() {
+        println!("No exchange rates entered yet.");
+    }
+    let mut sorted = rates.clone();
+    sorted.sort_by(|a, b| (&a.from, &a.to, a.date).cmp(&(&b.from, &b.to, b.date)));
+    for r in sorted.iter() { println!("  {}", r) }
+    for c in missing_rates(accounts, rates, base) {
+        println!("Warning: no rate between {} and {}, amounts in {} are added up unconverted", c, base, c);
+    }
+}
+
+pub fn new_exchange_rate_from_inputs(base: &str) -> Result<ExchangeRate, InquireError> {
+    let from = query_currency("From currency:", if base == DEFAULT_CURRENCY { "EUR" } else { DEFAULT_CURRENCY })?;
+    let to = query_currency("To currency:", base)?;
+    if from == to { return Err(InquireError::InvalidConfiguration(
+        "Can't have a rate between a currency and itself".to_string()))}
+    let rate = CustomType::<f64>::new(&format!("How many {} for one {}?", to, from))
+        .with_error_message("Please type a valid number")
+        .with_help_message("e.g. 0.86 for EUR to GBP when one euro buys 86p")
+        .prompt()?;
+    if rate <= 0.0 { return Err(InquireError::InvalidConfiguration(
+        "Rate must be of strictly positive value".to_string()))}
+    let date = DateSelect::new("Rate from:")
+        .with_week_start(Weekday::Mon)
+        .with_help_message("Used for anything on or after this date, until a newer rate")
+        .prompt()?;
+    return Ok(ExchangeRate { date, from, to, rate });
+}
+
+pub fn import_exchange_rates_from_inputs(rates: &mut Vec<ExchangeRate>) -> Result<usize, InquireError> {
+    let filepath = Text::new("Path to the CSV of rates:")
+        .with_help_message("Columns: date (YYYY-MM-DD), from, to, rate")
+        .prompt()?;
+    let (read, errors) = match read_exchange_rates_csv(filepath.trim()) {
+        Ok(r) => r,
+        Err(e) => return Err(InquireError::InvalidConfiguration(format!("Could not read rates: {}", e))),
+    };
+    for e in errors.iter() { println!("Skipping {}", e) }
+    // kept, but no account can be in a currency that isn't in the list, so these are probably typos
+    let known = currency_codes();
+    let mut unknown: Vec<&String> = read.iter().flat_map(|r| [&r.from, &r.to]).filter(|c| !known.contains(c)).collect();
+    unknown.sort();
+    unknown.dedup();
+    for c in unknown.iter() { println!("Warning: {} isn't one of the supported currencies, so no account can use its rates", c) }
+    // a rate for the same pair and day replaces the old one
+    for r in read.iter() {
+        rates.retain(|x| !(x.date == r.date && x.from == r.from && x.to == r.to));
+        rates.push(r.clone());
+    }
+    return Ok(read.len());
+}
+
+pub fn remove_exchange_rate_from_inputs(rates: &mut Vec<ExchangeRate>) -> Result<(), InquireError> {
+    if rates.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No exchange rates to remove".to_string()))}
+    let r = Select::new("Remove which rate?", rates.to_vec()).prompt()?;
+    rates.retain(|x| *x != r);
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rates_must_be_finite_and_between_two_currencies() {
+        let filepath = std::env::temp_dir().join(format!("moxdtrkr-test-rates-{}.csv", std::process::id()));
+        std::fs::write(&filepath, "date,from,to,rate\n2024-01-01,EUR,GBP,inf\n2024-01-01,eur,EUR,1.0\n\
+                                   2024-01-01,EUR,GBP,NaN\n2024-01-01,EUR,GBP,-1\n2024-01-02,eur,gbp,0.86\n").unwrap();
+        let (rates, errors) = read_exchange_rates_csv(&filepath.to_string_lossy()).unwrap();
+        let _ = std::fs::remove_file(&filepath);
+        assert_eq!(errors.len(), 4);
+        assert!(errors[1].contains("EUR to itself"));
+        assert_eq!(rates.len(), 1);
+        assert_eq!((rates[0].from.as_str(), rates[0].to.as_str(), rates[0].rate), ("EUR", "GBP", 0.86));
+    }
+}
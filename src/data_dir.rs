@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+// DATA DIRECTORY ----------------------------------------------------------------------------------
+// <data dir>/              settings shared by every profile, and the files of the default profile
+// <data dir>/profiles/<name>/   the files of any other named profile
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_DIR: &str = "profiles";
+
+// --data-dir (or MOXDTRKR_DATA_DIR, both handled by clap) if given, otherwise the platform's data
+// directory, e.g. ~/.local/share/moxdtrkr
+pub fn resolve_data_dir(given: Option<PathBuf>) -> PathBuf {
+    if let Some(dir) = given { return dir }
+    return match dirs::data_dir() {
+        Some(dir) => dir.join("moxdtrkr"),
+        None => PathBuf::from("data"), // no home directory, fall back to the old behaviour
+    }
+}
+
+pub struct DataPaths {
+    pub profile: String,
+    pub dir: PathBuf,
+    pub accounts: String,
+    pub transactions: String,
+    pub budgets: String,
+    pub recurring: String,
+    pub import_profiles: String,
+    pub category_rules: String,
+    pub exchange_rates: String,
+    pub ledger_db: String, // only present if accounts and transactions have been migrated to SQLite
+    pub backups: String,
+    pub encryption: String, // only present if the profile is encrypted
+    pub journal: String, // append-only, so never backed up or restored
+    pub lock: String, // empty, locked by whichever moxdtrkr is writing to the profile
+}
+
+impl DataPaths {
+    pub fn new(data_dir: &Path, profile: &str) -> DataPaths {
+        let dir = if profile == DEFAULT_PROFILE { data_dir.to_path_buf() }
+                  else { data_dir.join(PROFILES_DIR).join(profile) };
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        return DataPaths {
+            profile: profile.to_string(),
+            accounts: file("accounts.json"),
+            transactions: file("transactions.json"),
+            budgets: file("budgets.json"),
+            recurring: file("recurring.json"),
+            import_profiles: file("import_profiles.json"),
+            category_rules: file("category_rules.json"),
+            exchange_rates: file("exchange_rates.json"),
+            ledger_db: file("ledger.sqlite3"),
+            backups: file("backups"),
+            encryption: file("encryption.json"),
+            journal: file("journal.jsonl"),
+            lock: file("moxdtrkr.lock"),
+            dir,
+        }
+    }
+
+    // everything that is backed up before each save, and can be restored from the main menu
+    pub fn data_files(&self) -> [&str; 8] {
+        return [&self.accounts, &self.transactions, &self.budgets, &self.recurring,
+                &self.import_profiles, &self.category_rules, &self.exchange_rates, &self.ledger_db];
+    }
+}
+
+// only one moxdtrkr may write to a profile at a time, otherwise whichever saves last throws away what the
+// other wrote. The lock is held for as long as the returned file is open, and is released by the OS if the
+// process dies
+pub fn lock_profile(paths: &DataPaths) -> Result<std::fs::File, String> {
+    let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&paths.lock)
+        .map_err(|e| format!("{}: {}", paths.lock, e))?;
+    return match file.try_lock() {
+        Ok(_) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => Err(format!("Profile {} is open in another moxdtrkr", paths.profile)),
+        Err(std::fs::TryLockError::Error(e)) => Err(format!("{}: {}", paths.lock, e)),
+    }
+}
+
+pub fn settings_filepath(data_dir: &Path) -> String {
+    return data_dir.join("settings.json").to_string_lossy().to_string();
+}
+pub fn legacy_settings_filepath(data_dir: &Path) -> String {
+    return data_dir.join("settings.csv").to_string_lossy().to_string();
+}
+
+
+// PROFILES ----------------------------------------------------------------------------------------
+// the default profile first, then the rest alphabetically
+pub fn list_profiles(data_dir: &Path) -> Vec<String> {
+    let mut profiles = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(data_dir.join(PROFILES_DIR)) {
+        for e in entries.flatten() {
+            let name = e.file_name().to_string_lossy().to_string();
+            if e.path().is_dir() && valid_profile_name(&name).is_ok() { profiles.push(name) }
+        }
+    }
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.to_string());
+    return profiles;
+}
+
+// names become directory names, so keep them to something that is safe on every platform
+pub fn valid_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() { return Err("Profile name can't be empty".to_string()) }
+    if name == DEFAULT_PROFILE { return Ok(()) }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Use only letters, numbers, '-' and '_'".to_string());
+    }
+    return Ok(());
+}
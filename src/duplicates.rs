@@ -0,0 +1,262 @@
+use std::fmt::{Display, Formatter};
+use std::collections::HashSet;
+use inquire::{Confirm, MultiSelect, Select, InquireError};
+use colored::Colorize;
+
+use crate::{Transaction, pence_to_pound_colour};
+
+// DUPLICATE DETECTION -----------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq)]
+pub enum DuplicateKind {
+    Exact,  // same account, date, amount and reference
+    Likely, // same account and amount, date within the window and a similar reference
+}
+
+impl Display for DuplicateKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            DuplicateKind::Exact => write!(f, "exact duplicate"),
+            DuplicateKind::Likely => write!(f, "likely duplicate"),
+        }
+    }
+}
+
+// lowercase words made only of letters and digits, so "TESCO STORES 1234" ~ "Tesco Stores-1234"
+fn words(reference: &str) -> HashSet<String> {
+    return reference
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+}
+
+pub fn similar_references(a: &str, b: &str) -> bool {
+    let (wa, wb) = (words(a), words(b));
+    if wa.is_empty() || wb.is_empty() { return wa.is_empty() && wb.is_empty() }
+    // at least half of the words (of the union) in common
+    let common = wa.intersection(&wb).count();
+    let all = wa.union(&wb).count();
+    return common * 2 >= all;
+}
+
+pub fn duplicate_kind(a: &Transaction, b: &Transaction, window_days: i64) -> Option<DuplicateKind> {
+    if a.account_id_1() != b.account_id_1() || a.amount() != b.amount() { return None }
+    if a.date() == b.date() && a.reference() == b.reference() { return Some(DuplicateKind::Exact) }
+    if (a.date() - b.date()).num_days().abs() <= window_days && similar_references(&a.reference(), &b.reference()) {
+        return Some(DuplicateKind::Likely);
+    }
+    return None;
+}
+
+// existing transactions that <t> looks like a duplicate of
+pub fn find_duplicates_of(t: &Transaction, transactions: &Vec<Transaction>, window_days: i64)
+    -> Vec<(Transaction, DuplicateKind)>
+{
+    let mut found = Vec::new();
+    for x in transactions.iter() {
+        if let Some(kind) = duplicate_kind(t, x, window_days) {
+            found.push((x.clone(), kind));
+        }
+    }
+    return found;
+}
+
+// groups of indices into <transactions> that are all duplicates of one another (transitively)
+pub fn duplicate_clusters(transactions: &Vec<Transaction>, window_days: i64) -> Vec<Vec<usize>> {
+    let mut cluster_of: Vec<Option<usize>> = vec![None; transactions.len()];
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    for i in 0..transactions.len() {
+        for j in (i+1)..transactions.len() {
+            if duplicate_kind(&transactions[i], &transactions[j], window_days).is_none() { continue }
+            match (cluster_of[i], cluster_of[j]) {
+                (None, None) => {
+                    cluster_of[i] = Some(clusters.len());
+                    cluster_of[j] = Some(clusters.len());
+                    clusters.push(vec!(i, j));
+                },
+                (Some(c), None) => { cluster_of[j] = Some(c); clusters[c].push(j); },
+                (None, Some(c)) => { cluster_of[i] = Some(c); clusters[c].push(i); },
+                (Some(c1), Some(c2)) if c1 != c2 => { // join the two clusters
+                    let moved = std::mem::take(&mut clusters[c2]);
+                    for k in moved.iter() { cluster_of[*k] = Some(c1) }
+                    clusters[c1].extend(moved);
+                },
+                _ => {},
+            }
+        }
+    }
+    clusters.retain(|c| !c.is_empty());
+    return clusters;
+}
+
+
+// DUPLICATE FUNCTIONS -----------------------------------------------------------------------------
+fn print_transaction_line(t: &Transaction) {
+    println!("  {}\t{}\tref: {}", t.date(), pence_to_pound_colour(t.amount(), true, false), t.reference());
+}
+
+// warn about anything <t> might be a duplicate of, returning whether it should still be added
+pub fn confirm_not_duplicate(t: &Transaction, transactions: &Vec<Transaction>, window_days: i64)
+    -> Result<bool, InquireError>
+{
+    let found = find_duplicates_of(t, transactions, window_days);
+    if found.is_empty() { return Ok(true) }
+    println!("{} this transaction looks like a duplicate of:", "Warning:".to_string().red().bold());
+    for (x, kind) in found.iter() {
+        print!("  ({})", kind);
+        print_transaction_line(x);
+    }
+    return Confirm::new("Add it anyway?").with_default(false).prompt();
+}
+
+struct ClusterEntry {
+    index: usize,
+    transaction: Transaction,
+}
+impl Display for ClusterEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}  {}  ref: {}  notes: {}",
+               self.transaction.date(),
+               pence_to_pound_colour(self.transaction.amount(), true, false),
+               self.transaction.reference(),
+               self.transaction.notes())
+    }
+}
+
+enum ClusterOptions {
+    Skip,
+    Merge,
+    Delete,
+    Stop,
+}
+impl Display for ClusterOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            ClusterOptions::Skip => write!(f, "Leave these alone"),
+            ClusterOptions::Merge => write!(f, "Merge into one (keeping the earliest, combining notes)"),
+            ClusterOptions::Delete => write!(f, "Choose which to delete"),
+            ClusterOptions::Stop => write!(f, "Stop reviewing duplicates"),
+        }
+    }
+}
+
+// walk through every cluster of duplicates, letting the user merge or delete them
+pub fn review_duplicates(transactions: &mut Vec<Transaction>, window_days: i64) -> Result<(), InquireError> {
+    let clusters = duplicate_clusters(transactions, window_days);
+    if clusters.is_empty() {
+        println!("No duplicates found.");
+        return Ok(());
+    }
+    println!("Found {} group(s) of possible duplicates.", clusters.len());
+
+    let mut to_delete: Vec<usize> = Vec::new();
+    let mut replacements: Vec<(usize, Transaction)> = Vec::new();
+    for (n, cluster) in clusters.into_iter().enumerate() {
+        let mut entries: Vec<ClusterEntry> = cluster.iter()
+            .map(|i| ClusterEntry { index: *i, transaction: transactions[*i].clone() })
+            .collect();
+        entries.sort_by_key(|e| e.transaction.date());
+
+        println!("Group {}:", n+1);
+        for e in entries.iter() { println!("  {}", e); }
+        match Select::new("What do you want to do with this group?", vec!(
+            ClusterOptions::Skip,
+            ClusterOptions::Merge,
+            ClusterOptions::Delete,
+            ClusterOptions::Stop,
+        )).prompt()? {
+            ClusterOptions::Skip => {},
+            ClusterOptions::Merge => {
+                let mut notes: Vec<String> = Vec::new();
+                for e in entries.iter() {
+                    let n = e.transaction.notes();
+                    if !n.is_empty() && !notes.contains(&n) { notes.push(n) }
+                }
+                let kept = &entries[0];
+                replacements.push((kept.index, kept.transaction.with_notes(notes.join("; "))));
+                for e in entries.iter().skip(1) { to_delete.push(e.index) }
+            },
+            ClusterOptions::Delete => {
+                for e in MultiSelect::new("Select the ones to delete:", entries).prompt()? {
+                    to_delete.push(e.index);
+                }
+            },
+            ClusterOptions::Stop => break,
+        }
+    }
+
+    to_delete.sort();
+    to_delete.dedup();
+    // reconciled transactions agree with a bank statement, so aren't deleted or merged by accident
+    let reconciled = to_delete.iter().chain(replacements.iter().map(|(i, _)| i))
+        .filter(|i| transactions[**i].is_reconciled()).count();
+    if reconciled > 0 && !Confirm::new(&format!(
+        "{} of the transactions to delete or merge have been reconciled against a statement. Unlock them?", reconciled))
+        .with_default(false)
+        .with_help_message("The statement balance will need reconciling again")
+        .prompt()? {
+        return Err(InquireError::InvalidConfiguration("Reconciled transactions are locked".to_string()));
+    }
+
+    // apply, by index so that identical transactions are removed individually
+    for (i, t) in replacements.into_iter() { transactions[i] = t; }
+    for i in to_delete.into_iter().rev() { transactions.remove(i); }
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::money::Money;
+
+    fn t(account: u32, pence: i64, day: u32, reference: &str) -> Transaction {
+        return Transaction::new_transaction(account, 9, Money::from_minor(pence),
+            reference.to_string(), NaiveDate::from_ymd_opt(2023, 5, day).unwrap(), String::new());
+    }
+
+    #[test]
+    fn similar_references_ignore_case_and_punctuation() {
+        assert!(similar_references("TESCO STORES 1234", "Tesco Stores-1234"));
+        assert!(!similar_references("Tesco", "Sainsburys"));
+        assert!(similar_references("", ""));
+    }
+
+    #[test]
+    fn kinds_need_the_same_account_and_amount() {
+        assert!(duplicate_kind(&t(0, -500, 1, "Coffee"), &t(0, -500, 1, "Coffee"), 3) == Some(DuplicateKind::Exact));
+        assert!(duplicate_kind(&t(0, -500, 1, "COFFEE SHOP"), &t(0, -500, 3, "Coffee shop"), 3) == Some(DuplicateKind::Likely));
+        assert!(duplicate_kind(&t(0, -500, 1, "Coffee"), &t(0, -500, 5, "Coffee"), 3).is_none()); // outside the window
+        assert!(duplicate_kind(&t(0, -500, 1, "Coffee"), &t(1, -500, 1, "Coffee"), 3).is_none());
+        assert!(duplicate_kind(&t(0, -500, 1, "Coffee"), &t(0, -501, 1, "Coffee"), 3).is_none());
+    }
+
+    #[test]
+    fn clusters_join_transitively() {
+        let transactions = vec!(
+            t(0, -500, 1, "Coffee"),
+            t(0, -999, 1, "Rent"),
+            t(0, -500, 3, "Coffee"), // within 2 days of both the first and the last
+            t(0, -999, 20, "Rent"),
+            t(0, -500, 5, "Coffee"),
+        );
+        let mut clusters = duplicate_clusters(&transactions, 2);
+        for c in clusters.iter_mut() { c.sort() }
+        assert_eq!(clusters, vec!(vec!(0, 2, 4)));
+    }
+
+    #[test]
+    fn clusters_merge_when_a_later_pair_links_them() {
+        // 0~2 and 1~3 start as separate clusters, then 2~3 joins them
+        let transactions = vec!(
+            t(0, -500, 1, "Coffee"),
+            t(0, -500, 7, "Coffee"),
+            t(0, -500, 3, "Coffee"),
+            t(0, -500, 5, "Coffee"),
+        );
+        let mut clusters = duplicate_clusters(&transactions, 2);
+        for c in clusters.iter_mut() { c.sort() }
+        assert_eq!(clusters, vec!(vec!(0, 1, 2, 3)));
+    }
+}
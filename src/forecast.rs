@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use chrono::{Duration, NaiveDate};
+
+use crate::{Account, Transaction};
+use crate::money::Money;
+use crate::recurring::RecurringRule;
+use crate::settings::ForecastMethod;
+
+// projected transactions on days after <today> up to and including <until>. These are never stored,
+// they are re-calculated whenever the calendar is drawn
+pub fn forecast_transactions(
+    method: ForecastMethod,
+    average_weeks: i64,
+    transactions: &Vec<Transaction>,
+    recurring: &Vec<RecurringRule>,
+    accounts: &Vec<Account>,
+    today: NaiveDate,
+    until: NaiveDate,
+) -> Vec<Transaction> {
+    return match method {
+        ForecastMethod::Off => Vec::new(),
+        ForecastMethod::Recurring => forecast_from_recurring(recurring, today, until),
+        ForecastMethod::Average => forecast_from_average(average_weeks, transactions, accounts, today, until),
+    }
+}
+
+fn forecast_from_recurring(recurring: &Vec<RecurringRule>, today: NaiveDate, until: NaiveDate) -> Vec<Transaction> {
+    let mut projected = Vec::new();
+    for r in recurring.iter() {
+        // work on a copy so that the real rules are not advanced
+        let mut rule = r.clone();
+        for t in rule.materialise(until).into_iter() {
+            if t.date() > today { projected.push(t) }
+        }
+    }
+    return projected;
+}
+
+// the average weekly amount for each (account, category) pair over the last <average_weeks> weeks is
+// projected once a week, on the same weekday as today
+fn forecast_from_average(
+    average_weeks: i64,
+    transactions: &Vec<Transaction>,
+    accounts: &Vec<Account>,
+    today: NaiveDate,
+    until: NaiveDate,
+) -> Vec<Transaction> {
+    let lb = today - Duration::days(7*average_weeks);
+    let mut totals: HashMap<(u32, u32), Money> = HashMap::new();
+    for t in transactions.iter() {
+        if t.date() > lb && t.date() <= today {
+            for (cat_id, amount) in t.category_lines().into_iter() {
+                *totals.entry((t.account_id_1(), cat_id)).or_insert(Money::ZERO) += amount;
+            }
+        }
+    }
+
+    let mut projected = Vec::new();
+    for ((acc_id, cat_id), total) in totals.into_iter() {
+        let weekly = total / average_weeks;
+        if weekly.is_zero() { continue }
+        let name = match accounts.iter().find(|a| a.id == cat_id) {
+            Some(a) => a.name.clone(),
+            None => "Unknown Category".to_string(),
+        };
+        let mut date = today + Duration::days(7);
+        while date <= until {
+            projected.push(Transaction::new_transaction(
+                acc_id,
+                cat_id,
+                weekly,
+                format!("Avg. {}", name),
+                date,
+                format!("Average weekly amount over the last {} weeks", average_weeks),
+            ));
+            date = date + Duration::days(7);
+        }
+    }
+    // keep a stable order within each day as HashMap iteration order is random
+    projected.sort_by_key(|t| (t.date(), t.account_id_1(), t.account_id_2()));
+    return projected;
+}
+
+// for each visible account, the first day from <today> (up to <until>) on which its closing balance,
+// including the projection, is past its limit (see Account::is_over_limit)
+pub fn over_limit_warnings(
+    transactions: &Vec<Transaction>,
+    projected: &Vec<Transaction>,
+    visible_accounts: &Vec<Account>,
+    today: NaiveDate,
+    until: NaiveDate,
+) -> Vec<(Account, NaiveDate, Money)> {
+    let mut warnings = Vec::new();
+    for a in visible_accounts.iter() {
+        // balance at the end of today
+        let mut balance = a.balance_on(transactions, today);
+        let mut date = today;
+        while date <= until {
+            if a.is_over_limit(balance) {
+                warnings.push((a.clone(), date, balance));
+                break;
+            }
+            date = date + Duration::days(1);
+            if a.opening_date == Some(date) { balance += a.opening_balance }
+            for t in transactions.iter().chain(projected.iter()) {
+                if t.date() == date { balance += t.amount_for(a.id) }
+            }
+        }
+    }
+    return warnings;
+}
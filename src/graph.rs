@@ -2,17 +2,15 @@ use std::collections::HashMap;
 use chrono::prelude::*;
 use chrono::Duration;
 use clearscreen;
-use crate::{ColouredString, sidebyside, Transaction};
+use crate::{ColouredString, sidebyside, Transaction, format_money};
+use crate::money::Money;
 
-fn render_bar_util(mut pence:i64, scale:i64) -> ColouredString { // scale is pence per character
-    let green = pence >= 0;
-    if pence < 0 {
-        pence = -pence;
-    }
+fn render_bar_util(amount:Money, scale:i64, currency:&str, signed_with_colour:bool, brackets_when_neg:bool)
+    -> ColouredString { // scale is minor units per character
+    let green = !amount.is_negative();
+    let mut pence = amount.abs().minor(); // length of the bar left to draw
     let mut bar = ColouredString::new();
-    let txt = format!("£{}.{:02}", // TODO update this so that it is using pence per pound and the standard settings for which sign and brackets!
-                   (pence/100).abs(),
-                   (pence%100).abs());
+    let txt = format_money(amount, currency, signed_with_colour, brackets_when_neg).to_string();
     for character in txt.chars() {
         if pence > 0 {
             if green{
@@ -36,14 +34,14 @@ fn render_bar_util(mut pence:i64, scale:i64) -> ColouredString { // scale is pen
     return bar;
 }
 
-pub fn draw_graph(labels: &Vec<ColouredString>, values: &Vec<i64>, selection_index: i64) {
+pub fn draw_graph(labels: &Vec<ColouredString>, values: &Vec<Money>, selection_index: i64, currency: &str,
+                  signed_with_colour: bool, brackets_when_neg: bool) {
     let y_width = 20;
     let width = 60;
 
     let mut max_value = 1;
     for v in values.to_vec().into_iter() {
-        if v > max_value { max_value = v }
-        if -v > max_value { max_value = -v } // account for magnitude only!
+        if v.abs().minor() > max_value { max_value = v.abs().minor() } // account for magnitude only!
     }
 
     let mut scale = max_value/width;
@@ -62,7 +60,7 @@ pub fn draw_graph(labels: &Vec<ColouredString>, values: &Vec<i64>, selection_ind
     }
 
     for v in values.to_vec().into_iter(){
-        y_values.add_line(render_bar_util(v, scale));
+        y_values.add_line(render_bar_util(v, scale, currency, signed_with_colour, brackets_when_neg));
     }
 
     sidebyside::SideBySide::print2(&y_annotations, &y_values, " | ");
@@ -73,10 +71,12 @@ pub fn draw_graph(labels: &Vec<ColouredString>, values: &Vec<i64>, selection_ind
 }
 
 enum SubGraphCounter {
-    CountAmount(i64, i64)
+    CountAmount(i64, Money)
 }
 
-pub fn graph_acc_cats(accounts: Vec<u32>, categories: Vec<u32>, transactions: &Vec<Transaction>) {
+// <transactions> are all in <currency>
+pub fn graph_acc_cats(accounts: Vec<u32>, categories: Vec<u32>, transactions: &Vec<Transaction>, currency: &str,
+                      signed_with_colour: bool, brackets_when_neg: bool) {
     let mut selecting = true;
     let num_weeks = 10;
 
@@ -103,13 +103,15 @@ pub fn graph_acc_cats(accounts: Vec<u32>, categories: Vec<u32>, transactions: &V
     for _ in 0..num_weeks {
         labels.push(ColouredString::from_string(format!("Week {}", lb)));
         // sum all values in range
-        let mut working_value = 0;
+        let mut working_value = Money::ZERO;
         for transaction in transactions.to_vec().into_iter() {
             if accounts.contains(&transaction.account_id_1()) &&
-                categories.contains(&transaction.account_id_2()) &&
                 (transaction.date() >= lb) &&
                 (transaction.date() < ub) {
-                working_value += transaction.amount();
+                // split transactions only count the lines in the selected categories
+                for (category, amount) in transaction.category_lines().into_iter() {
+                    if categories.contains(&category) { working_value += amount; }
+                }
             }
         }
         values.push(working_value);
@@ -137,23 +139,27 @@ pub fn graph_acc_cats(accounts: Vec<u32>, categories: Vec<u32>, transactions: &V
         // calc values into hash map
         let mut sub_graph:HashMap<String, SubGraphCounter> = HashMap::new();
         for transaction in transactions.to_vec().into_iter() {
+            let amount: Money = transaction.category_lines().into_iter()
+                .filter(|(category, _)| categories.contains(category))
+                .map(|(_, amount)| amount)
+                .sum();
             if accounts.contains(&transaction.account_id_1()) &&
-                categories.contains(&transaction.account_id_2()) &&
+                transaction.category_lines().iter().any(|(category, _)| categories.contains(category)) &&
                 (transaction.date() >= lb) &&
                 (transaction.date() < ub) {
                 match sub_graph.get(transaction.reference().as_str()) {
-                    Some(SubGraphCounter::CountAmount(count, amount)) => {sub_graph.insert(
+                    Some(SubGraphCounter::CountAmount(count, total)) => {sub_graph.insert(
                         transaction.reference().to_string(),
                         SubGraphCounter::CountAmount(
                             count + 1,
-                            transaction.amount() + amount
+                            amount + *total
                         )
                     );}
                     None => {sub_graph.insert(
                         transaction.reference().to_string(),
                         SubGraphCounter::CountAmount(
                             1,
-                            transaction.amount()
+                            amount
                         )
                     );}
                 }
@@ -165,7 +171,7 @@ pub fn graph_acc_cats(accounts: Vec<u32>, categories: Vec<u32>, transactions: &V
         for (k, SubGraphCounter::CountAmount(count, amount))
             in sub_graph.keys().zip(sub_graph.values()) {
             sub_graph_labels.push(ColouredString::from_string(format!("{} ({})",k,count.clone())));
-            sub_graph_values.push(amount.clone());
+            sub_graph_values.push(*amount);
         }
 
         // TODO rank sub-graph by size
@@ -173,9 +179,9 @@ pub fn graph_acc_cats(accounts: Vec<u32>, categories: Vec<u32>, transactions: &V
         // draw
         clearscreen::clear().expect("failed to clear screen");
         println!("Expenditure across selected categories and accounts in each week beginning:\n");
-        draw_graph(&labels, &values, hi);
+        draw_graph(&labels, &values, hi, currency, signed_with_colour, brackets_when_neg);
         println!("\nExpenditure breakdown for selected week\n");
-        draw_graph(&sub_graph_labels, &sub_graph_values, -1);
+        draw_graph(&sub_graph_labels, &sub_graph_values, -1, currency, signed_with_colour, brackets_when_neg);
         println!("\n{} {}",
              ColouredString::from_str("[↑↓ to move selection, esc to stop sub-graphing]").cyan(),
              ColouredString::from_str("[Ctrl-C from here will break things - don't do it!]").red(),
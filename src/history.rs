@@ -0,0 +1,163 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{Account, Transaction, pence_to_pound};
+
+// UNDO / REDO -------------------------------------------------------------------------------------
+// in-session only, nothing here is saved. Transactions have no id, so they are found again by value
+pub enum Change {
+    AddTransactions(Vec<Transaction>), // one from the menus, or a whole CSV import
+    ModifyTransaction { old: Transaction, new: Transaction, count: usize }, // count: identical copies replaced
+    DeleteTransaction { transaction: Transaction, count: usize },
+    AddAccount(Account), // or category
+    ModifyAccount { old: Account, new: Account }, // renamed, details changed, archived or unarchived
+    DeleteAccount(Account), // only ones nothing refers to, deleting with reassignment can't be undone
+}
+
+fn describe(t: &Transaction) -> String {
+    return format!("{} {} \"{}\"", t.date(), pence_to_pound(t.amount(), true, false), t.reference());
+}
+
+fn kind(a: &Account) -> &str {
+    return if a.category { "category" } else { "account" };
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Change::AddTransactions(ts) if ts.len() == 1 => write!(f, "Add {}", describe(&ts[0])),
+            Change::AddTransactions(ts) => write!(f, "Add {} imported transactions", ts.len()),
+            Change::ModifyTransaction { old, new, .. } => write!(f, "Modify {} => {}", describe(old), describe(new)),
+            Change::DeleteTransaction { transaction, .. } => write!(f, "Delete {}", describe(transaction)),
+            Change::AddAccount(a) => write!(f, "Add {} \"{}\"", kind(a), a.name),
+            Change::ModifyAccount { old, new } if old.name != new.name => write!(f, "Rename {} \"{}\" => \"{}\"", kind(old), old.name, new.name),
+            Change::ModifyAccount { old, new } if old.archived != new.archived =>
+                write!(f, "{} {} \"{}\"", if new.archived { "Archive" } else { "Unarchive" }, kind(new), new.name),
+            Change::ModifyAccount { new, .. } => write!(f, "Change details of {} \"{}\"", kind(new), new.name),
+            Change::DeleteAccount(a) => write!(f, "Delete {} \"{}\"", kind(a), a.name),
+        }
+    }
+}
+
+// remove the most recently added copy of <t>
+fn remove_one(transactions: &mut Vec<Transaction>, t: &Transaction) -> Result<(), String> {
+    match transactions.iter().rposition(|x| *x == *t) {
+        Some(i) => { transactions.remove(i); return Ok(()) },
+        None => return Err(format!("{} no longer exists", describe(t))),
+    }
+}
+
+fn replace_account(accounts: &mut Vec<Account>, current: &Account, replacement: &Account) -> Result<(), String> {
+    match accounts.iter_mut().find(|a| a.id == current.id) {
+        Some(a) => { *a = replacement.clone(); return Ok(()) },
+        None => return Err(format!("\"{}\" no longer exists", current.name)),
+    }
+}
+
+fn delete_account(accounts: &mut Vec<Account>, transactions: &Vec<Transaction>, a: &Account) -> Result<(), String> {
+    if transactions.iter().any(|t| t.involves(a.id)) {
+        return Err(format!("\"{}\" has transactions", a.name));
+    }
+    accounts.retain(|x| x.id != a.id);
+    return Ok(());
+}
+
+impl Change {
+    fn apply(&self, accounts: &mut Vec<Account>, transactions: &mut Vec<Transaction>) -> Result<(), String> {
+        match self {
+            Change::AddTransactions(ts) => transactions.extend(ts.iter().cloned()),
+            Change::ModifyTransaction { old, new, .. } => {
+                if !transactions.contains(old) { return Err(format!("{} no longer exists", describe(old))) }
+                transactions.retain(|x| *x != *old);
+                transactions.push(new.clone());
+            },
+            Change::DeleteTransaction { transaction, .. } => {
+                if !transactions.contains(transaction) { return Err(format!("{} no longer exists", describe(transaction))) }
+                transactions.retain(|x| *x != *transaction);
+            },
+            Change::AddAccount(a) => accounts.push(a.clone()),
+            Change::ModifyAccount { old, new } => replace_account(accounts, old, new)?,
+            Change::DeleteAccount(a) => delete_account(accounts, transactions, a)?,
+        }
+        return Ok(());
+    }
+
+    fn revert(&self, accounts: &mut Vec<Account>, transactions: &mut Vec<Transaction>) -> Result<(), String> {
+        match self {
+            Change::AddTransactions(ts) => {
+                // check first so that a failure leaves everything as it was
+                let mut remaining = transactions.clone();
+                for t in ts.iter().rev() { remove_one(&mut remaining, t)? }
+                *transactions = remaining;
+            },
+            Change::ModifyTransaction { old, new, count } => {
+                remove_one(transactions, new)?;
+                for _ in 0..*count { transactions.push(old.clone()) }
+            },
+            Change::DeleteTransaction { transaction, count } => {
+                for _ in 0..*count { transactions.push(transaction.clone()) }
+            },
+            Change::AddAccount(a) => delete_account(accounts, transactions, a)?,
+            Change::ModifyAccount { old, new } => replace_account(accounts, new, old)?,
+            Change::DeleteAccount(a) => accounts.push(a.clone()),
+        }
+        return Ok(());
+    }
+}
+
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    pub fn new() -> History {
+        return History { undo: Vec::new(), redo: Vec::new() }
+    }
+
+    // a new edit makes anything that was undone unreachable
+    pub fn record(&mut self, change: Change) {
+        self.undo.push(change);
+        self.redo.clear();
+    }
+
+    // after the data has been replaced wholesale (backup restored, profile switched)
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn undo_description(&self) -> Option<String> {
+        return self.undo.last().map(|c| c.to_string());
+    }
+    pub fn redo_description(&self) -> Option<String> {
+        return self.redo.last().map(|c| c.to_string());
+    }
+
+    // returns what was undone
+    pub fn undo(&mut self, accounts: &mut Vec<Account>, transactions: &mut Vec<Transaction>) -> Result<&Change, String> {
+        let change = match self.undo.pop() {
+            Some(c) => c,
+            None => return Err("Nothing to undo".to_string()),
+        };
+        if let Err(e) = change.revert(accounts, transactions) {
+            self.undo.push(change);
+            return Err(format!("Can't undo: {}", e));
+        }
+        self.redo.push(change);
+        return Ok(self.redo.last().unwrap());
+    }
+
+    // returns what was redone
+    pub fn redo(&mut self, accounts: &mut Vec<Account>, transactions: &mut Vec<Transaction>) -> Result<&Change, String> {
+        let change = match self.redo.pop() {
+            Some(c) => c,
+            None => return Err("Nothing to redo".to_string()),
+        };
+        if let Err(e) = change.apply(accounts, transactions) {
+            self.redo.push(change);
+            return Err(format!("Can't redo: {}", e));
+        }
+        self.undo.push(change);
+        return Ok(self.undo.last().unwrap());
+    }
+}
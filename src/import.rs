@@ -0,0 +1,245 @@
+use std::fmt::{Display, Formatter};
+use serde::{Serialize, Deserialize};
+use chrono::NaiveDate;
+use inquire::{Select, Text, Confirm, InquireError};
+use colored::Colorize;
+
+use crate::{Account, Transaction, format_money_colour};
+use crate::money::Money;
+use crate::duplicates::find_duplicates_of;
+use crate::category_rules::{CategoryRule, categorise};
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
+
+// MAPPING PROFILE ---------------------------------------------------------------------------------
+// columns are 0-indexed in the file, but shown 1-indexed to the user
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub enum AmountColumns {
+    Signed(usize),                             // single column, negative is money out
+    DebitCredit { debit: usize, credit: usize }, // money out and money in in separate columns
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MappingProfile {
+    pub name: String,
+    pub has_header: bool,
+    pub date_column: usize,
+    pub date_format: String, // chrono format string e.g. "%d/%m/%Y"
+    pub amount_columns: AmountColumns,
+    pub reference_column: usize,
+}
+
+impl Display for MappingProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl MappingProfile {
+    // one statement line -> (date, amount in minor units of <currency>, reference)
+    pub fn parse_record(&self, record: &csv::StringRecord, currency: &str) -> Result<(NaiveDate, Money, String), String> {
+        let field = |i: usize| -> Result<&str, String> {
+            record.get(i).map(|s| s.trim()).ok_or(format!("missing column {}", i+1))
+        };
+        let date = NaiveDate::parse_from_str(field(self.date_column)?, self.date_format.as_str())
+            .map_err(|e| format!("date \"{}\": {}", field(self.date_column).unwrap_or(""), e))?;
+        let amount = match self.amount_columns {
+            AmountColumns::Signed(i) => Money::parse(field(i)?, currency)?,
+            AmountColumns::DebitCredit { debit, credit } => {
+                let d = field(debit)?;
+                let c = field(credit)?;
+                let d = if d.is_empty() { Money::ZERO } else { Money::parse(d, currency)?.abs() };
+                let c = if c.is_empty() { Money::ZERO } else { Money::parse(c, currency)?.abs() };
+                c.checked_sub(d).ok_or("amount is too large".to_string())?
+            },
+        };
+        let reference = field(self.reference_column)?.to_string();
+        return Ok((date, amount, reference));
+    }
+}
+
+
+// MAPPING PROFILE FILE I/O ------------------------------------------------------------------------
+pub fn get_profiles_from_file(filepath:&str) -> Result<Vec<MappingProfile>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
+}
+
+macro_rules! spfe {
+    ($e:ident) => {
+        return Err(format!("Error saving import profiles to file: {}", $e))
+    }
+}
+
+pub fn save_profiles_to_file(filepath:&str, profiles:Vec<MappingProfile>) -> Result<(), String> {
+    // Serialize to JSON
+    match serde_json::to_string(&profiles) {
+        Ok(s) => {
+            // save to file
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
+                Err(e) => spfe!(e)
+            }
+        },
+        Err(e) => spfe!(e)
+    }
+    return Ok(());
+}
+
+
+// IMPORT FUNCTIONS --------------------------------------------------------------------------------
+const DATE_FORMATS: [&str; 6] = ["%d/%m/%Y", "%Y-%m-%d", "%d-%m-%Y", "%m/%d/%Y", "%d %b %Y", "%d/%m/%y"];
+
+struct Column {
+    index: usize,
+    example: String,
+}
+impl Display for Column {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "Column {}: {}", self.index+1, self.example)
+    }
+}
+
+fn query_column(prompt: &str, first_row: &csv::StringRecord) -> Result<usize, InquireError> {
+    let mut columns = Vec::new();
+    for (index, example) in first_row.iter().enumerate() {
+        columns.push(Column { index, example: example.to_string() });
+    }
+    return Ok(Select::new(prompt, columns).prompt()?.index);
+}
+
+// build a new profile by picking columns from the first row of the statement
+fn new_profile_from_inputs(first_row: &csv::StringRecord) -> Result<MappingProfile, InquireError> {
+    let name = Text::new("Profile Name (e.g. the bank's name):").prompt()?;
+    let has_header = Confirm::new("Is the first row a header row?").with_default(true).prompt()?;
+    let date_column = query_column("Date column:", first_row)?;
+
+    let mut formats: Vec<String> = DATE_FORMATS.iter().map(|f| f.to_string()).collect();
+    formats.push("Other".to_string());
+    let mut date_format = Select::new("Date format (e.g. %d/%m/%Y is 31/12/2023):", formats).prompt()?;
+    if date_format == "Other" {
+        date_format = Text::new("Date format:")
+            .with_help_message("chrono format string, see https://docs.rs/chrono/latest/chrono/format/strftime")
+            .prompt()?;
+    }
+
+    let amount_columns = match Select::new("Amounts are:", vec!(
+        "In a single signed column",
+        "In separate money out and money in columns",
+    )).prompt()? {
+        "In a single signed column" => AmountColumns::Signed(query_column("Amount column:", first_row)?),
+        _ => AmountColumns::DebitCredit {
+            debit: query_column("Money out column:", first_row)?,
+            credit: query_column("Money in column:", first_row)?,
+        },
+    };
+    let reference_column = query_column("Reference column:", first_row)?;
+
+    return Ok(MappingProfile { name, has_header, date_column, date_format, amount_columns, reference_column });
+}
+
+pub fn read_statement(filepath: &str) -> Result<Vec<csv::StringRecord>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false) // handled by the profile
+        .flexible(true)
+        .from_path(filepath)
+        .map_err(|e| e.to_string())?;
+    let mut records = Vec::new();
+    for r in reader.records() {
+        records.push(r.map_err(|e| e.to_string())?);
+    }
+    return Ok(records);
+}
+
+// read a statement into transactions for a chosen account, creating or choosing a mapping profile
+// as needed. Nothing is added to <transactions> here, the caller gets back what would be added
+pub fn import_csv_from_inputs(
+    profiles: &mut Vec<MappingProfile>,
+    acc_accounts: &Vec<Account>,
+    cat_accounts: &Vec<Account>,
+    rules: &Vec<CategoryRule>,
+    existing: &Vec<Transaction>,
+    duplicate_window_days: i64,
+    signed_with_colour: bool,
+    brackets_when_neg: bool,
+) -> Result<Vec<Transaction>, InquireError> {
+    if acc_accounts.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No accounts to choose from".to_string()))}
+    if cat_accounts.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No categories to choose from".to_string()))}
+
+    let filepath = Text::new("Path to the CSV statement:").prompt()?;
+    let records = match read_statement(filepath.trim()) {
+        Ok(r) if !r.is_empty() => r,
+        Ok(_) => return Err(InquireError::InvalidConfiguration("Statement is empty".to_string())),
+        Err(e) => return Err(InquireError::InvalidConfiguration(format!("Could not read statement: {}", e))),
+    };
+
+    let account = Select::new("Import into Account:", acc_accounts.to_vec()).prompt()?;
+    let account_id = account.id;
+
+    // pick an existing profile, or make a new one from the first row
+    let new_profile_option = "New profile".to_string();
+    let mut options: Vec<String> = profiles.iter().map(|p| p.name.clone()).collect();
+    options.push(new_profile_option.clone());
+    let choice = Select::new("Column mapping profile:", options).prompt()?;
+    let profile = if choice == new_profile_option {
+        let p = new_profile_from_inputs(&records[0])?;
+        profiles.retain(|x| x.name != p.name);
+        profiles.push(p.clone());
+        p
+    } else {
+        profiles.iter().find(|p| p.name == choice).unwrap().clone()
+    };
+
+    let category_id = Select::new("Category for imported transactions:", cat_accounts.to_vec())
+        .with_help_message("Used when no categorisation rule matches. These can be changed individually from the calendar afterwards")
+        .prompt()?.id;
+
+    // parse every line, reporting the ones that can't be understood
+    let mut imported = Vec::new();
+    let skip = if profile.has_header { 1 } else { 0 };
+    for (line, record) in records.iter().enumerate().skip(skip) {
+        match profile.parse_record(record, &account.currency) {
+            Ok((date, amount, reference)) => imported.push(Transaction::new_transaction(
+                account_id,
+                categorise(rules, &reference, amount).unwrap_or(category_id),
+                amount,
+                reference,
+                date,
+                format!("Imported from {}", filepath.trim()),
+            )),
+            Err(e) => println!("{} line {}: {}", "Skipping".to_string().red().bold(), line+1, e),
+        }
+    }
+
+    // preview, flagging anything that looks to already be in the ledger (e.g. overlapping statements)
+    println!("The following {} transaction(s) would be added:", imported.len());
+    let mut duplicates = Vec::new();
+    for (i, t) in imported.iter().enumerate() {
+        let flag = match find_duplicates_of(t, existing, duplicate_window_days).first() {
+            Some((_, kind)) => {
+                duplicates.push(i);
+                format!("  [{}]", kind).red().to_string()
+            },
+            None => String::new(),
+        };
+        let category = match cat_accounts.iter().find(|a| a.id == t.account_id_2()) {
+            Some(a) => a.name.clone(),
+            None => "<Category name not found>".to_string(),
+        };
+        println!("{}\t{}\tcat: {}\tref: {}{}", t.date(),
+                 format_money_colour(t.amount(), &account.currency, signed_with_colour, brackets_when_neg),
+                 category, t.reference(), flag);
+    }
+    if imported.is_empty() || !Confirm::new("Add these transactions?").with_default(false).prompt()? {
+        return Ok(Vec::new());
+    }
+    if !duplicates.is_empty() && Confirm::new(format!("Skip the {} flagged as duplicates?", duplicates.len()).as_str())
+        .with_default(true)
+        .prompt()? {
+        for i in duplicates.into_iter().rev() { imported.remove(i); }
+    }
+    return Ok(imported);
+}
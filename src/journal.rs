@@ -0,0 +1,288 @@
+use std::io::prelude::*;
+use serde::{Serialize, Deserialize};
+use chrono::prelude::*;
+
+use crate::{Account, Transaction, pence_to_pound};
+use crate::history::Change;
+use crate::crypto::{seal_line, unseal_line};
+
+// JOURNAL -----------------------------------------------------------------------------------------
+// append-only record of every change made to the accounts and transactions, one JSON object per line.
+// Entries are held in memory and only appended when the ledger itself is saved, so the journal never
+// describes changes that were thrown away
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    AddTransaction { after: Transaction },
+    // <count> identical copies of <before> were replaced by a single <after>
+    ModifyTransaction { before: Transaction, after: Transaction, count: usize },
+    DeleteTransaction { before: Transaction, count: usize },
+    AddAccount { after: Account },
+    ModifyAccount { before: Account, after: Account },
+    DeleteAccount { before: Account },
+    // the whole ledger, written when journalling starts on existing data and after a backup is restored
+    Snapshot { accounts: Vec<Account>, transactions: Vec<Transaction> },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: String, // RFC 3339, local time
+    pub user: String,      // login name of whoever made the change
+    pub source: String,    // where in the program it was made, e.g. "calendar", "import", "undo"
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+fn describe(t: &Transaction) -> String {
+    return format!("{} {} \"{}\"", t.date(), pence_to_pound(t.amount(), true, false), t.reference());
+}
+
+impl JournalEntry {
+    pub fn summary(&self) -> String {
+        let what = match &self.operation {
+            Operation::AddTransaction { after } => format!("Added {}", describe(after)),
+            Operation::ModifyTransaction { before, after, .. } => format!("Modified {} => {}", describe(before), describe(after)),
+            Operation::DeleteTransaction { before, count } if *count > 1 => format!("Deleted {} ({} copies)", describe(before), count),
+            Operation::DeleteTransaction { before, .. } => format!("Deleted {}", describe(before)),
+            Operation::AddAccount { after } => format!("Added {} \"{}\"", if after.category { "category" } else { "account" }, after.name),
+            Operation::ModifyAccount { before, after } => format!("Changed {} \"{}\"{} => \"{}\"{}", if before.category { "category" } else { "account" },
+                before.name, if before.archived { " (archived)" } else { "" }, after.name, if after.archived { " (archived)" } else { "" }),
+            Operation::DeleteAccount { before } => format!("Removed {} \"{}\"", if before.category { "category" } else { "account" }, before.name),
+            Operation::Snapshot { accounts, transactions } =>
+                format!("Snapshot of {} account(s) and {} transaction(s)", accounts.len(), transactions.len()),
+        };
+        return format!("{}  {:<10} {:<10} {}", self.timestamp, self.user, self.source, what);
+    }
+}
+
+fn current_user() -> String {
+    return std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or("unknown".to_string());
+}
+
+pub struct Journal {
+    filepath: String,
+    pending: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub fn new(filepath: &str) -> Journal {
+        return Journal { filepath: filepath.to_string(), pending: Vec::new() }
+    }
+
+    pub fn exists(&self) -> bool {
+        return std::path::Path::new(&self.filepath).exists();
+    }
+
+    pub fn record(&mut self, operation: Operation, source: &str) {
+        self.pending.push(JournalEntry {
+            timestamp: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            user: current_user(),
+            source: source.to_string(),
+            operation,
+        });
+    }
+
+    // an edit that was also recorded for undo
+    pub fn record_change(&mut self, change: &Change, source: &str) {
+        match change {
+            Change::AddTransactions(ts) => for t in ts.iter() {
+                self.record(Operation::AddTransaction { after: t.clone() }, source)
+            },
+            Change::ModifyTransaction { old, new, count } =>
+                self.record(Operation::ModifyTransaction { before: old.clone(), after: new.clone(), count: *count }, source),
+            Change::DeleteTransaction { transaction, count } =>
+                self.record(Operation::DeleteTransaction { before: transaction.clone(), count: *count }, source),
+            Change::AddAccount(a) => self.record(Operation::AddAccount { after: a.clone() }, source),
+            Change::ModifyAccount { old, new } => self.record(Operation::ModifyAccount { before: old.clone(), after: new.clone() }, source),
+            Change::DeleteAccount(a) => self.record(Operation::DeleteAccount { before: a.clone() }, source),
+        }
+    }
+
+    // the opposite of <change>, as done by undo
+    pub fn record_undo(&mut self, change: &Change) {
+        match change {
+            Change::AddTransactions(ts) => for t in ts.iter().rev() {
+                self.record(Operation::DeleteTransaction { before: t.clone(), count: 1 }, "undo")
+            },
+            Change::ModifyTransaction { old, new, count } => {
+                self.record(Operation::DeleteTransaction { before: new.clone(), count: 1 }, "undo");
+                for _ in 0..*count { self.record(Operation::AddTransaction { after: old.clone() }, "undo") }
+            },
+            Change::DeleteTransaction { transaction, count } => for _ in 0..*count {
+                self.record(Operation::AddTransaction { after: transaction.clone() }, "undo")
+            },
+            Change::AddAccount(a) => self.record(Operation::DeleteAccount { before: a.clone() }, "undo"),
+            Change::ModifyAccount { old, new } => self.record(Operation::ModifyAccount { before: new.clone(), after: old.clone() }, "undo"),
+            Change::DeleteAccount(a) => self.record(Operation::AddAccount { after: a.clone() }, "undo"),
+        }
+    }
+
+    // for changes made by code that edits the transactions directly, e.g. the duplicate review
+    pub fn record_difference(&mut self, before: &Vec<Transaction>, after: &Vec<Transaction>, source: &str) {
+        let mut removed = before.clone();
+        for t in after.iter() {
+            match removed.iter().position(|r| r == t) {
+                Some(i) => { removed.remove(i); },
+                None => self.record(Operation::AddTransaction { after: t.clone() }, source),
+            }
+        }
+        for t in removed.into_iter() {
+            self.record(Operation::DeleteTransaction { before: t, count: 1 }, source);
+        }
+    }
+
+    // append everything recorded since the last save
+    pub fn flush(&mut self) -> Result<(), String> {
+        if self.pending.is_empty() { return Ok(()) }
+        let mut lines = String::new();
+        for e in self.pending.iter() {
+            let line = serde_json::to_string(e).map_err(|e| e.to_string())?;
+            lines.push_str(&seal_line(&line)?);
+            lines.push('\n');
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.filepath)
+            .map_err(|e| format!("Error writing journal {}: {}", self.filepath, e))?;
+        file.write_all(lines.as_bytes()).and_then(|_| file.sync_all())
+            .map_err(|e| format!("Error writing journal {}: {}", self.filepath, e))?;
+        self.pending.clear();
+        return Ok(());
+    }
+
+    // everything in the file followed by anything not yet saved. Lines that can't be read are skipped and
+    // counted, rather than losing the rest of the history
+    pub fn entries(&self) -> Result<(Vec<JournalEntry>, usize), String> {
+        let mut entries = Vec::new();
+        let mut unreadable = 0;
+        match std::fs::read_to_string(&self.filepath) {
+            Ok(s) => for line in s.lines() {
+                if line.trim().is_empty() { continue }
+                match unseal_line(line).and_then(|l| serde_json::from_str(&l).map_err(|e| e.to_string())) {
+                    Ok(e) => entries.push(e),
+                    Err(_) => unreadable += 1,
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(format!("Error reading journal {}: {}", self.filepath, e)),
+        }
+        entries.extend(self.pending.iter().cloned());
+        return Ok((entries, unreadable));
+    }
+}
+
+
+// REPLAY ------------------------------------------------------------------------------------------
+// the ledger as the journal describes it, and how many entries didn't fit (e.g. deleting a transaction
+// that the journal never saw being added)
+pub fn replay(entries: &Vec<JournalEntry>) -> (Vec<Account>, Vec<Transaction>, usize) {
+    let mut accounts: Vec<Account> = Vec::new();
+    let mut transactions: Vec<Transaction> = Vec::new();
+    let mut mismatches = 0;
+    let mut remove = |transactions: &mut Vec<Transaction>, t: &Transaction, count: usize| {
+        for _ in 0..count {
+            match transactions.iter().rposition(|x| x == t) {
+                Some(i) => { transactions.remove(i); },
+                None => { mismatches += 1; break },
+            }
+        }
+    };
+    for e in entries.iter() {
+        match &e.operation {
+            Operation::AddTransaction { after } => transactions.push(after.clone()),
+            Operation::ModifyTransaction { before, after, count } => {
+                remove(&mut transactions, before, *count);
+                transactions.push(after.clone());
+            },
+            Operation::DeleteTransaction { before, count } => remove(&mut transactions, before, *count),
+            Operation::AddAccount { after } => accounts.push(after.clone()),
+            Operation::ModifyAccount { after, .. } => for a in accounts.iter_mut() {
+                if a.id == after.id { *a = after.clone() }
+            },
+            Operation::DeleteAccount { before } => accounts.retain(|a| a.id != before.id),
+            Operation::Snapshot { accounts: a, transactions: t } => {
+                accounts = a.clone();
+                transactions = t.clone();
+            },
+        }
+    }
+    return (accounts, transactions, mismatches);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::money::Money;
+
+    // nothing is ever flushed, so entries() only returns what was recorded in memory
+    fn journal() -> Journal { Journal::new("/nonexistent/moxdtrkr-test/journal.jsonl") }
+    fn t(pence: i64, reference: &str) -> Transaction {
+        return Transaction::new_transaction(0, 1, Money::from_minor(pence), reference.to_string(),
+            NaiveDate::from_ymd_opt(2023, 5, 1).unwrap(), String::new());
+    }
+    fn account(id: u32, name: &str) -> Account {
+        let mut a: Account = serde_json::from_str(&format!("{{\"id\":{},\"name\":\"{}\",\"category\":false}}", id, name)).unwrap();
+        a.currency = "GBP".to_string();
+        return a;
+    }
+    fn replayed(j: &Journal) -> (Vec<Account>, Vec<Transaction>, usize) { replay(&j.entries().unwrap().0) }
+
+    #[test]
+    fn replay_applies_each_operation_in_order() {
+        let mut j = journal();
+        j.record(Operation::AddAccount { after: account(0, "Bank") }, "test");
+        j.record(Operation::ModifyAccount { before: account(0, "Bank"), after: account(0, "Current") }, "test");
+        j.record(Operation::AddTransaction { after: t(-500, "Coffee") }, "test");
+        j.record(Operation::AddTransaction { after: t(-500, "Coffee") }, "test");
+        j.record(Operation::AddTransaction { after: t(-900, "Rent") }, "test");
+        j.record(Operation::ModifyTransaction { before: t(-500, "Coffee"), after: t(-450, "Coffee"), count: 2 }, "test");
+        j.record(Operation::DeleteTransaction { before: t(-900, "Rent"), count: 1 }, "test");
+        let (accounts, transactions, mismatches) = replayed(&j);
+        assert_eq!(accounts.iter().map(|a| a.name.clone()).collect::<Vec<_>>(), vec!("Current"));
+        assert!(transactions == vec!(t(-450, "Coffee")));
+        assert_eq!(mismatches, 0);
+    }
+
+    #[test]
+    fn a_snapshot_replaces_everything_before_it() {
+        let mut j = journal();
+        j.record(Operation::AddTransaction { after: t(-100, "Gone") }, "test");
+        j.record(Operation::Snapshot { accounts: vec!(account(3, "Savings")), transactions: vec!(t(-200, "Kept")) }, "restore");
+        j.record(Operation::AddTransaction { after: t(-300, "After") }, "test");
+        let (accounts, transactions, _) = replayed(&j);
+        assert_eq!(accounts.len(), 1);
+        assert!(transactions == vec!(t(-200, "Kept"), t(-300, "After")));
+    }
+
+    #[test]
+    fn undo_records_the_opposite_change() {
+        let mut j = journal();
+        for _ in 0..2 { j.record(Operation::AddTransaction { after: t(-500, "Coffee") }, "test") }
+        let change = Change::ModifyTransaction { old: t(-500, "Coffee"), new: t(-450, "Coffee"), count: 2 };
+        j.record_change(&change, "calendar");
+        j.record_undo(&change);
+        let (_, transactions, mismatches) = replayed(&j);
+        assert!(transactions == vec!(t(-500, "Coffee"), t(-500, "Coffee")));
+        assert_eq!(mismatches, 0);
+    }
+
+    #[test]
+    fn record_difference_replays_to_the_new_transactions() {
+        let before = vec!(t(-100, "A"), t(-200, "B"), t(-200, "B"));
+        let after = vec!(t(-200, "B"), t(-300, "C"));
+        let mut j = journal();
+        j.record(Operation::Snapshot { accounts: Vec::new(), transactions: before.clone() }, "test");
+        j.record_difference(&before, &after, "duplicates");
+        let (_, mut transactions, mismatches) = replayed(&j);
+        transactions.sort_by_key(|t| t.reference());
+        assert!(transactions == after);
+        assert_eq!(mismatches, 0);
+    }
+
+    #[test]
+    fn entries_that_dont_fit_are_counted() {
+        let mut j = journal();
+        j.record(Operation::DeleteTransaction { before: t(-100, "Never added"), count: 1 }, "test");
+        assert_eq!(replayed(&j).2, 1);
+    }
+}
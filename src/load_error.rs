@@ -0,0 +1,92 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use chrono::prelude::*;
+use colored::Colorize;
+
+use crate::crypto::unseal;
+
+// LOAD ERROR --------------------------------------------------------------------------------------
+// a missing file just means nothing has been saved yet, anything else means the file on disk holds data
+// that could not be read, and must not be overwritten
+pub enum LoadError {
+    Missing,
+    PermissionDenied(std::io::Error),
+    Unreadable(std::io::Error),
+    Corrupt(serde_json::Error),
+    Undecryptable(String),
+    Database(rusqlite::Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            LoadError::Missing => write!(f, "file not found"),
+            LoadError::PermissionDenied(e) => write!(f, "permission denied ({})", e),
+            LoadError::Unreadable(e) => write!(f, "could not be read ({})", e),
+            LoadError::Corrupt(e) => write!(f, "file is corrupt ({})", e),
+            LoadError::Undecryptable(e) => write!(f, "could not be decrypted ({})", e),
+            LoadError::Database(e) => write!(f, "database error ({})", e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> LoadError { return LoadError::Corrupt(e) }
+}
+
+impl From<rusqlite::Error> for LoadError {
+    fn from(e: rusqlite::Error) -> LoadError { return LoadError::Database(e) }
+}
+
+
+// LOADING -----------------------------------------------------------------------------------------
+// contents of a data file, decrypted if the profile is encrypted
+pub fn read_data_file(filepath:&str) -> Result<String, LoadError> {
+    match std::fs::read(filepath) {
+        Ok(data) => {
+            let plain = match unseal(data) {
+                Ok(p) => p,
+                Err(e) => return Err(LoadError::Undecryptable(e)),
+            };
+            match String::from_utf8(plain) {
+                Ok(s) => return Ok(s),
+                Err(e) => return Err(LoadError::Unreadable(std::io::Error::new(std::io::ErrorKind::InvalidData, e))),
+            }
+        },
+        Err(e) => match e.kind() {
+            std::io::ErrorKind::NotFound => return Err(LoadError::Missing),
+            std::io::ErrorKind::PermissionDenied => return Err(LoadError::PermissionDenied(e)),
+            _ => return Err(LoadError::Unreadable(e)),
+        }
+    }
+}
+
+// for callers where a file that doesn't exist yet is the same as one holding nothing
+pub fn empty_if_missing<T>(loaded: Result<Vec<T>, LoadError>) -> Result<Vec<T>, LoadError> {
+    return match loaded {
+        Err(LoadError::Missing) => Ok(Vec::new()),
+        other => other,
+    }
+}
+
+// copy a file that failed to load to <filepath>.broken-<timestamp>, so that it can be repaired by hand
+// whatever happens to the original
+pub fn set_aside(filepath:&str) -> std::io::Result<PathBuf> {
+    let path = Path::new(filepath);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".broken-{}", Local::now().format("%Y-%m-%d_%H-%M-%S")));
+    let broken = path.with_file_name(name);
+    std::fs::copy(path, &broken)?;
+    return Ok(broken);
+}
+
+pub fn report_load_error(filepath:&str, e:&LoadError) {
+    println!("{} {}: {}", "Error loading".to_string().red().bold(), filepath, e);
+    match e {
+        LoadError::Corrupt(_) => match set_aside(filepath) {
+            Ok(broken) => println!("A copy has been kept at {}", broken.display()),
+            Err(e) => println!("Could not keep a copy of it: {}", e),
+        },
+        _ => {},
+    }
+}
@@ -1,8 +1,9 @@
 use std::fmt::{Display, Formatter};
-use chrono::Duration;
+use chrono::{Duration, Months};
 use chrono::prelude::*;
-use inquire::{Select, Text, MultiSelect};
+use inquire::{Select, Text, MultiSelect, Confirm, DateSelect, validator::Validation};
 use clearscreen;
+use colored::Colorize;
 
 mod account;
 use crate::account::*;
@@ -16,14 +17,68 @@ use crate::calendar::*;
 mod pence_to_pound_utils;
 use crate::pence_to_pound_utils::*;
 
+mod money;
+use crate::money::*;
+
 mod coloured_string;
 use crate::coloured_string::*;
 
 mod sidebyside;
 mod graph;
 
+mod budget;
+use crate::budget::*;
+
+mod recurring;
+use crate::recurring::*;
+
+mod category_rules;
+use crate::category_rules::*;
+
+mod import;
+use crate::import::*;
+
+mod duplicates;
+use crate::duplicates::*;
+
+mod forecast;
+use crate::forecast::*;
+
+mod reconcile;
+use crate::reconcile::*;
+
+mod cli;
+use clap::Parser;
+
 mod settings;
-use crate::settings::save_settings_to_file;
+use crate::settings::*;
+
+mod backup;
+use crate::backup::*;
+
+mod load_error;
+use crate::load_error::*;
+
+mod data_dir;
+use crate::data_dir::*;
+
+mod crypto;
+use crate::crypto::*;
+
+mod storage;
+use crate::storage::*;
+
+mod history;
+use crate::history::*;
+
+mod journal;
+use crate::journal::*;
+
+mod tags;
+use crate::tags::*;
+
+mod currency;
+use crate::currency::*;
 
 use crate::sidebyside::*;
 
@@ -35,16 +90,31 @@ enum MainloopOption {
     Exit,
     NewTransaction,
     NewTransfer,
+    NewSplitTransaction,
     ListAccounts,
     ShowAccount,
     TextCalendar,
     AddAccount,
     AddCategory,
     EditAccountSelection,
+    EditTagSelection,
     AccountCategoryGraphs,
     ListCategories,
     Settings,
     Save,
+    Budgets,
+    Recurring,
+    ImportCsv,
+    Duplicates,
+    CategoryRules,
+    RestoreBackup,
+    SwitchProfile,
+    UndoRedo,
+    Journal,
+    ManageAccounts,
+    TagReport,
+    ExchangeRates,
+    Reconcile,
 }
 
 impl Display for MainloopOption {
@@ -55,16 +125,31 @@ impl Display for MainloopOption {
             MainloopOption::Nothing => write!(f, "Do Nothing"),
             MainloopOption::NewTransaction => write!(f, "New Transaction"),
             MainloopOption::NewTransfer => write!(f, "New Transfer"),
+            MainloopOption::NewSplitTransaction => write!(f, "New Split Transaction (across multiple categories)"),
             MainloopOption::ListAccounts => write!(f, "List Accounts"),
             MainloopOption::ShowAccount => write!(f, "Show Account"),
             MainloopOption::TextCalendar => write!(f, "Text Calendar"),
             MainloopOption::AddAccount => write!(f, "Add Account"),
             MainloopOption::AddCategory => write!(f, "Add Category"),
             MainloopOption::EditAccountSelection => write!(f, "Choose which accounts to view on the calendar"),
+            MainloopOption::EditTagSelection => write!(f, "Choose which tags to view on the calendar (all when none chosen)"),
             MainloopOption::AccountCategoryGraphs => write!(f, "Bar graph: expenditure per week, for a given account and category(s)"),
             MainloopOption::ListCategories => write!(f, "List Categories"),
             MainloopOption::Settings => write!(f, "Settings"),
             MainloopOption::Save => write!(f, "Save without exiting"),
+            MainloopOption::Budgets => write!(f, "Budgets"),
+            MainloopOption::Recurring => write!(f, "Recurring Transactions"),
+            MainloopOption::ImportCsv => write!(f, "Import CSV statement"),
+            MainloopOption::Duplicates => write!(f, "Find and resolve duplicate transactions"),
+            MainloopOption::CategoryRules => write!(f, "Auto-categorisation rules"),
+            MainloopOption::RestoreBackup => write!(f, "Restore from backup"),
+            MainloopOption::SwitchProfile => write!(f, "Switch profile"),
+            MainloopOption::UndoRedo => write!(f, "Undo / Redo"),
+            MainloopOption::Journal => write!(f, "Change journal (view, rebuild)"),
+            MainloopOption::ManageAccounts => write!(f, "Rename, archive, delete or merge accounts and categories"),
+            MainloopOption::TagReport => write!(f, "Spend per tag"),
+            MainloopOption::ExchangeRates => write!(f, "Currencies and exchange rates"),
+            MainloopOption::Reconcile => write!(f, "Reconcile an account against a statement"),
         }
     }
 }
@@ -92,6 +177,118 @@ impl Display for TransactionOptions {
     }
 }
 
+enum BudgetOptions {
+    Back,
+    Set,
+    Remove,
+}
+impl Display for BudgetOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            BudgetOptions::Back => write!(f, "Back to main menu"),
+            BudgetOptions::Set => write!(f, "Set a budget for a category"),
+            BudgetOptions::Remove => write!(f, "Remove a budget"),
+        }
+    }
+}
+
+enum ExchangeRateOptions {
+    Back,
+    Add,
+    Import,
+    Remove,
+    BaseCurrency,
+}
+impl Display for ExchangeRateOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            ExchangeRateOptions::Back => write!(f, "Back to main menu"),
+            ExchangeRateOptions::Add => write!(f, "Add an exchange rate"),
+            ExchangeRateOptions::Import => write!(f, "Import exchange rates from CSV"),
+            ExchangeRateOptions::Remove => write!(f, "Remove an exchange rate"),
+            ExchangeRateOptions::BaseCurrency => write!(f, "Change the base currency"),
+        }
+    }
+}
+
+enum RecurringOptions {
+    Back,
+    New,
+    PauseResume,
+    Modify,
+    Delete,
+    Materialise,
+}
+impl Display for RecurringOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            RecurringOptions::Back => write!(f, "Back to main menu"),
+            RecurringOptions::New => write!(f, "New recurring transaction"),
+            RecurringOptions::PauseResume => write!(f, "Pause / resume one"),
+            RecurringOptions::Modify => write!(f, "Modify one"),
+            RecurringOptions::Delete => write!(f, "Delete one"),
+            RecurringOptions::Materialise => write!(f, "Add all occurrences due up to today"),
+        }
+    }
+}
+
+enum CategoryRuleOptions {
+    Back,
+    New,
+    Edit,
+    Delete,
+}
+impl Display for CategoryRuleOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            CategoryRuleOptions::Back => write!(f, "Back to main menu"),
+            CategoryRuleOptions::New => write!(f, "New rule"),
+            CategoryRuleOptions::Edit => write!(f, "Edit a rule"),
+            CategoryRuleOptions::Delete => write!(f, "Delete a rule"),
+        }
+    }
+}
+
+enum ManageAccountOptions {
+    Back,
+    Rename,
+    EditDetails,
+    MoveCategory,
+    Archive,
+    Unarchive,
+    Delete,
+    MergeCategories,
+}
+impl Display for ManageAccountOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            ManageAccountOptions::Back => write!(f, "Back to main menu"),
+            ManageAccountOptions::Rename => write!(f, "Rename an account or category"),
+            ManageAccountOptions::EditDetails => write!(f, "Change an account's type, opening balance or limit"),
+            ManageAccountOptions::MoveCategory => write!(f, "Move a category under another (or to the top level)"),
+            ManageAccountOptions::Archive => write!(f, "Archive one (hide it, keeping its transactions)"),
+            ManageAccountOptions::Unarchive => write!(f, "Unarchive one"),
+            ManageAccountOptions::Delete => write!(f, "Delete one"),
+            ManageAccountOptions::MergeCategories => write!(f, "Merge one category into another"),
+        }
+    }
+}
+
+enum JournalOptions {
+    Back,
+    View,
+    Rebuild,
+}
+impl Display for JournalOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            JournalOptions::Back => write!(f, "Back to main menu"),
+            JournalOptions::View => write!(f, "View recent entries"),
+            JournalOptions::Rebuild => write!(f, "Rebuild accounts and transactions by replaying the journal"),
+        }
+    }
+}
+
 enum Actions {
     Nothing,
     Dec,
@@ -99,59 +296,244 @@ enum Actions {
 }
 
 fn main() {
-    const ACCOUNTS_FILEPATH:&str = "data/accounts.json";
-    const TRANSACTIONS_FILEPATH:&str = "data/transactions.json";
-    const SETTINGS_FILEPATH:&str = "data/settings.csv";
-    // load accounts and transactions from file - if unsuccessful then just create empty Vecs
-    let mut accounts:Vec<Account> = get_accounts_from_file(ACCOUNTS_FILEPATH); // all accounts
+    let cli = cli::Cli::parse();
+    if let Err(e) = valid_profile_name(&cli.profile) {
+        eprintln!("Error: profile \"{}\": {}", cli.profile, e);
+        std::process::exit(1);
+    }
+    let data_dir_given = cli.data_dir.is_some();
+    let data_dir = resolve_data_dir(cli.data_dir);
+    let mut paths = DataPaths::new(&data_dir, &cli.profile);
+    if let Err(e) = std::fs::create_dir_all(&paths.dir) {
+        eprintln!("{} {}: {}", "Error creating data directory".to_string().red().bold(), paths.dir.display(), e);
+    }
+    // data used to live in ./data, so point anyone upgrading at it rather than quietly starting afresh
+    if !data_dir_given && !std::path::Path::new(&paths.accounts).exists() && std::path::Path::new("data/accounts.json").exists() {
+        println!("{} Found data in ./data but not in {}. Run with --data-dir data, or move the files across",
+                 "Note:".to_string().yellow().bold(), paths.dir.display());
+    }
+
+    // non-interactive subcommands for scripting, otherwise carry on into the menus
+    if let Some(command) = cli.command {
+        let settings = load_settings_from_file(&settings_filepath(&data_dir), &legacy_settings_filepath(&data_dir));
+        match cli::run(command, &paths, settings.duplicate_window_days) {
+            Ok(_) => return,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // ask for the passphrase of an encrypted profile before anything is loaded
+    match unlock_profile(&paths) {
+        Ok(key) => set_data_key(key),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // accounts and transactions are in JSON files or an SQLite database
+    let mut storage = match open_storage(&paths) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // held until exiting or switching profile, so that the add-* commands can't write underneath the menus
+    let mut profile_lock = lock_profile(&paths);
+
+    // a data file that exists but couldn't be loaded would be overwritten by the next save, so nothing is
+    // saved until it has been dealt with (e.g. by restoring a backup)
+    let mut read_only = false;
+    macro_rules! load {
+        ($loaded:expr, $filepath:expr) => {
+            match $loaded {
+                Ok(v) => v,
+                Err(LoadError::Missing) => Vec::new(), // nothing saved yet
+                Err(e) => {
+                    report_load_error($filepath, &e);
+                    read_only = true;
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    // load accounts and transactions from file - if they don't exist yet then just create empty Vecs
+    let mut accounts:Vec<Account> = load!(storage.load_accounts(), &storage.accounts_location()); // all accounts
     let mut acc_accounts = Account::acc_accounts(&accounts); // account accounts
     let mut cat_accounts = Account::cat_accounts(&accounts); // category accounts
     // visible accounts in calendar
     let mut visible_accounts = acc_accounts.clone();
+    // visible tags in calendar, every transaction shown when empty
+    let mut visible_tags:Vec<String> = Vec::new();
     // all transactions
-    let mut transactions:Vec<Transaction> = get_transactions_from_file(TRANSACTIONS_FILEPATH);
-
-    // (default) SETTINGS parameters:
-    // TODO determine from file and update with setttings page!
-    let mut signed_with_colour = false;
-    let mut brackets_when_neg = false;
-    // text cal params
-    let mut weeks = 4;
-    let weeks_min = 1;
-    let weeks_max = 6;
-    let mut max_transactions_per_day = 6;
-    let max_transactions_per_day_min = 1;
-    let max_transactions_per_day_max = 16;
-    let mut day_width = 16;
-    let day_width_min = 10;
-    let day_width_max = 30;
-    let mut info_width = 28;
-    let info_width_min = 10;
-    let info_width_max = 80;
+    let mut transactions:Vec<Transaction> = load!(storage.load_transactions(), &storage.transactions_location());
+    // per-category budgets
+    let mut budgets:Vec<Budget> = load!(get_budgets_from_file(&paths.budgets), &paths.budgets);
+    // rules suggesting a category from a transaction's reference
+    let mut category_rules:Vec<CategoryRule> = load!(get_category_rules_from_file(&paths.category_rules), &paths.category_rules);
+    // column mappings for importing bank statements
+    let mut import_profiles:Vec<MappingProfile> = load!(get_profiles_from_file(&paths.import_profiles), &paths.import_profiles);
+    // recurring transaction rules, with every occurrence due up to today added straight away
+    let mut recurring:Vec<RecurringRule> = load!(get_recurring_from_file(&paths.recurring), &paths.recurring);
+    // exchange rates, for adding up accounts in different currencies
+    let mut exchange_rates:Vec<ExchangeRate> = load!(get_exchange_rates_from_file(&paths.exchange_rates), &paths.exchange_rates);
+    if read_only {
+        println!("{}", "Starting in read-only mode, nothing will be saved until the files above are fixed or a backup is restored".red().bold());
+    }
+    if let Err(e) = &profile_lock {
+        println!("{}", format!("{}, starting in read-only mode", e).red().bold());
+        read_only = true;
+    }
+    // every change to accounts and transactions, appended to <paths.journal> on each save. Data from before
+    // the journal existed is recorded as a snapshot, so that replaying it gives back the whole ledger
+    let mut journal = Journal::new(&paths.journal);
+    if !journal.exists() && (!accounts.is_empty() || !transactions.is_empty()) {
+        journal.record(Operation::Snapshot { accounts: accounts.clone(), transactions: transactions.clone() }, "startup");
+    }
+    {
+        let mut due = materialise_all(&mut recurring, Utc::now().date_naive());
+        if !due.is_empty() {
+            println!("Added {} recurring transaction(s) due since the last run", due.len());
+        }
+        for t in due.iter() { journal.record(Operation::AddTransaction { after: t.clone() }, "recurring") }
+        transactions.append(&mut due);
+    }
+
+    // in-session undo/redo of edits to transactions and accounts
+    let mut history = History::new();
+    // an edit made from the menus, recorded for undo and in the journal
+    macro_rules! record {
+        ($change:expr, $source:expr) => {{
+            let change = $change;
+            journal.record_change(&change, $source);
+            history.record(change);
+        }}
+    }
+    // accounts were changed (e.g. by undo/redo), so re-derive the lists that are kept alongside them
+    macro_rules! refresh_accounts {
+        () => {{
+            acc_accounts = Account::acc_accounts(&accounts);
+            cat_accounts = Account::cat_accounts(&accounts);
+            // keep the same selection, but with any renames picked up
+            visible_accounts = acc_accounts.iter().filter(|a| visible_accounts.iter().any(|v| v.id == a.id)).cloned().collect();
+        }}
+    }
+
+    // load everything again from <paths>, after restoring a backup or switching profile
+    macro_rules! reload_all {
+        () => {{
+            read_only = false; // set again if anything fails to load
+            history.clear();
+            journal = Journal::new(&paths.journal);
+            match open_storage(&paths) {
+                Ok(s) => storage = s,
+                Err(e) => {
+                    println!("{}: {}", "Error opening storage".to_string().red().bold(), e);
+                    read_only = true;
+                }
+            }
+            accounts = load!(storage.load_accounts(), &storage.accounts_location());
+            acc_accounts = Account::acc_accounts(&accounts);
+            cat_accounts = Account::cat_accounts(&accounts);
+            visible_accounts = acc_accounts.clone();
+            visible_tags = Vec::new();
+            transactions = load!(storage.load_transactions(), &storage.transactions_location());
+            budgets = load!(get_budgets_from_file(&paths.budgets), &paths.budgets);
+            category_rules = load!(get_category_rules_from_file(&paths.category_rules), &paths.category_rules);
+            import_profiles = load!(get_profiles_from_file(&paths.import_profiles), &paths.import_profiles);
+            recurring = load!(get_recurring_from_file(&paths.recurring), &paths.recurring);
+            exchange_rates = load!(get_exchange_rates_from_file(&paths.exchange_rates), &paths.exchange_rates);
+            if !journal.exists() && (!accounts.is_empty() || !transactions.is_empty()) {
+                journal.record(Operation::Snapshot { accounts: accounts.clone(), transactions: transactions.clone() }, "startup");
+            }
+            let mut due = materialise_all(&mut recurring, Utc::now().date_naive());
+            if !due.is_empty() {
+                println!("Added {} recurring transaction(s) due since the last run", due.len());
+            }
+            for t in due.iter() { journal.record(Operation::AddTransaction { after: t.clone() }, "recurring") }
+            transactions.append(&mut due);
+            if let Err(e) = &profile_lock {
+                println!("{}", e.red().bold());
+                read_only = true;
+            }
+            if read_only { println!("{}", "Read-only mode, nothing will be saved".red().bold()) }
+        }}
+    }
+
+    // SETTINGS parameters, falling back to defaults for anything missing from the file
+    let mut settings = load_settings_from_file(&settings_filepath(&data_dir), &legacy_settings_filepath(&data_dir));
     // programattically determined
-    let mut width = 1+(day_width+1)*7+1+info_width;
+    let mut width = settings.width();
     let mut title = ColouredString::from_str("--< moxdtrkr v2.0 >");
     for _ in 19..width { title.push_str("-") }
 
+    // back up what is currently on disk, then save everything, returning any errors
+    macro_rules! save_all {
+        () => {{
+            let mut errors:Vec<String> = Vec::new();
+            if read_only {
+                errors.push("Read-only mode, nothing saved. Fix or restore the data files that failed to load first".to_string());
+            } else {
+            if let Err(e) = create_backup(&paths.data_files(), &paths.backups, BACKUPS_KEPT) {
+                println!("{}: {}", "Warning, could not back up the previous save".to_string().red().bold(), e);
+            }
+            // journal first, so that it is never missing a change that made it into the ledger
+            if let Err(e) = journal.flush() { errors.push(e) }
+            if let Err(e) = storage.save_accounts(&accounts) { errors.push(e) }
+            if let Err(e) = storage.save_transactions(&transactions) { errors.push(e) }
+            if let Err(e) = save_budgets_to_file(&paths.budgets, budgets.to_vec()) { errors.push(e) }
+            if let Err(e) = save_recurring_to_file(&paths.recurring, recurring.to_vec()) { errors.push(e) }
+            if let Err(e) = save_profiles_to_file(&paths.import_profiles, import_profiles.to_vec()) { errors.push(e) }
+            if let Err(e) = save_category_rules_to_file(&paths.category_rules, category_rules.to_vec()) { errors.push(e) }
+            if let Err(e) = save_exchange_rates_to_file(&paths.exchange_rates, exchange_rates.to_vec()) { errors.push(e) }
+            }
+            for e in errors.iter() { println!("{}", e.red().bold()) }
+            errors
+        }}
+    }
+
     // MAINLOOP:
     let mut state = MainloopOption::Nothing;
     while state != MainloopOption::Exit {
         println!("{}", title.yellow());
+        if paths.profile != DEFAULT_PROFILE { println!("Profile: {}", paths.profile.bold()) }
+        if read_only { println!("{}", "READ-ONLY: changes will not be saved".red().bold()) }
 
         // get next state
         match Select::new("Action:", vec!(
             MainloopOption::Clear,
             MainloopOption::TextCalendar,
             MainloopOption::EditAccountSelection,
+            MainloopOption::EditTagSelection,
             MainloopOption::NewTransaction,
             MainloopOption::NewTransfer,
+            MainloopOption::NewSplitTransaction,
+            MainloopOption::UndoRedo,
+            MainloopOption::Journal,
+            MainloopOption::ImportCsv,
             MainloopOption::AccountCategoryGraphs,
             MainloopOption::ListAccounts,
             MainloopOption::ListCategories,
+            MainloopOption::TagReport,
+            MainloopOption::Budgets,
+            MainloopOption::Recurring,
+            MainloopOption::ExchangeRates,
             MainloopOption::AddCategory,
+            MainloopOption::CategoryRules,
             MainloopOption::AddAccount,
+            MainloopOption::ManageAccounts,
             MainloopOption::ShowAccount,
+            MainloopOption::Reconcile,
+            MainloopOption::Duplicates,
             MainloopOption::Save,
+            MainloopOption::RestoreBackup,
+            MainloopOption::SwitchProfile,
             MainloopOption::Settings,
             MainloopOption::Exit,
         )).prompt() {
@@ -165,62 +547,342 @@ fn main() {
         match state{
             MainloopOption::Exit => {
                 println!("Saving...");
-                save_accounts_to_file(ACCOUNTS_FILEPATH, accounts.to_vec());
-                save_transactions_to_file(TRANSACTIONS_FILEPATH, transactions.to_vec());
-                println!("Exiting...")
+                let errors = save_all!();
+                // don't let unsaved changes be silently thrown away
+                if !errors.is_empty() {
+                    match Confirm::new("Saving failed, exit anyway? Unsaved changes will be lost")
+                        .with_default(false)
+                        .prompt() {
+                        Ok(false) => state = MainloopOption::Nothing,
+                        _ => {},
+                    }
+                }
+                if state == MainloopOption::Exit { println!("Exiting...") }
             },
             MainloopOption::Save => {
                 println!("Saving...");
-                save_accounts_to_file(ACCOUNTS_FILEPATH, accounts.to_vec());
-                save_transactions_to_file(TRANSACTIONS_FILEPATH, transactions.to_vec());
+                if save_all!().is_empty() { println!("Saved") }
+            },
+            MainloopOption::UndoRedo => {
+                let undo = match history.undo_description() {
+                    Some(d) => format!("Undo: {}", d),
+                    None => "Nothing to undo".to_string(),
+                };
+                let redo = match history.redo_description() {
+                    Some(d) => format!("Redo: {}", d),
+                    None => "Nothing to redo".to_string(),
+                };
+                match Select::new("Undo or redo:", vec!(undo.clone(), redo.clone(), "Back to main menu".to_string())).prompt() {
+                    Ok(choice) => {
+                        let result = if choice == undo { Some(("Undone", history.undo(&mut accounts, &mut transactions))) }
+                                     else if choice == redo { Some(("Redone", history.redo(&mut accounts, &mut transactions))) }
+                                     else { None };
+                        match result {
+                            Some((done, Ok(c))) => {
+                                if done == "Undone" { journal.record_undo(c) } else { journal.record_change(c, "redo") }
+                                println!("{}: {}", done, c);
+                                refresh_accounts!();
+                            },
+                            Some((_, Err(e))) => println!("{}", e),
+                            None => {},
+                        }
+                    },
+                    Err(_) => {},
+                }
+            },
+            MainloopOption::Journal => {
+                const SHOWN:usize = 40;
+                let mut journalling = true;
+                while journalling {
+                    match Select::new("Journal action:", vec!(
+                        JournalOptions::Back,
+                        JournalOptions::View,
+                        JournalOptions::Rebuild,
+                    )).prompt() {
+                        Ok(JournalOptions::View) => match journal.entries() {
+                            Ok((entries, unreadable)) => {
+                                if entries.is_empty() { println!("The journal is empty") }
+                                // oldest first, so the most recent change ends up next to the prompt
+                                for e in entries.iter().skip(entries.len().saturating_sub(SHOWN)) { println!("{}", e.summary()) }
+                                if entries.len() > SHOWN { println!("... {} older entries not shown, see {}", entries.len() - SHOWN, paths.journal) }
+                                if unreadable > 0 { println!("{}", format!("{} line(s) could not be read", unreadable).red().bold()) }
+                            },
+                            Err(e) => println!("{}", e.red().bold()),
+                        },
+                        Ok(JournalOptions::Rebuild) => match journal.entries() {
+                            Ok((entries, unreadable)) => {
+                                let (new_accounts, new_transactions, mismatches) = replay(&entries);
+                                println!("Replaying {} entries gives {} account(s)/category(s) and {} transaction(s), currently {} and {}",
+                                         entries.len(), new_accounts.len(), new_transactions.len(), accounts.len(), transactions.len());
+                                if unreadable > 0 { println!("{}", format!("{} line(s) could not be read and were skipped", unreadable).red().bold()) }
+                                if mismatches > 0 { println!("{}", format!("{} entries removed transactions that were not there", mismatches).red().bold()) }
+                                match Confirm::new("Replace the current accounts and transactions with these?")
+                                    .with_default(false)
+                                    .prompt() {
+                                    Ok(true) => {
+                                        accounts = new_accounts;
+                                        transactions = new_transactions;
+                                        refresh_accounts!();
+                                        history.clear(); // recorded changes may no longer apply
+                                        println!("Rebuilt, save to keep it");
+                                    },
+                                    _ => println!("Nothing changed"),
+                                }
+                            },
+                            Err(e) => println!("{}", e.red().bold()),
+                        },
+                        Ok(JournalOptions::Back) | Err(_) => journalling = false,
+                    }
+                }
+            },
+            MainloopOption::SwitchProfile => {
+                const NEW_PROFILE:&str = "<New profile>";
+                let mut options = list_profiles(&data_dir);
+                let current = options.iter().position(|p| *p == paths.profile).unwrap_or(0);
+                options.push(NEW_PROFILE.to_string());
+                let chosen = match Select::new("Switch to profile:", options).with_starting_cursor(current).prompt() {
+                    Ok(p) if p == NEW_PROFILE => {
+                        let existing = list_profiles(&data_dir);
+                        match Text::new("Name of the new profile:")
+                            .with_validator(move |s: &str| match valid_profile_name(s) {
+                                Ok(_) if existing.iter().any(|p| p == s) => Ok(Validation::Invalid("That profile already exists".into())),
+                                Ok(_) => Ok(Validation::Valid),
+                                Err(e) => Ok(Validation::Invalid(e.into())),
+                            })
+                            .prompt() {
+                            Ok(name) => Some(name),
+                            Err(_) => None,
+                        }
+                    },
+                    Ok(p) if p == paths.profile => None,
+                    Ok(p) => Some(p),
+                    Err(_) => None,
+                };
+                if let Some(profile) = chosen {
+                    // save the profile being left, making sure nothing is thrown away without asking
+                    let errors = save_all!();
+                    let switch = errors.is_empty() || match Confirm::new("Saving failed, switch anyway? Unsaved changes will be lost")
+                        .with_default(false)
+                        .prompt() {
+                        Ok(b) => b,
+                        Err(_) => false,
+                    };
+                    if switch {
+                        let new_paths = DataPaths::new(&data_dir, &profile);
+                        match unlock_profile(&new_paths) {
+                            Ok(key) => {
+                                set_data_key(key);
+                                paths = new_paths;
+                                if let Err(e) = std::fs::create_dir_all(&paths.dir) {
+                                    println!("{} {}: {}", "Error creating profile directory".to_string().red().bold(), paths.dir.display(), e);
+                                }
+                                profile_lock = lock_profile(&paths);
+                                reload_all!();
+                                println!("Switched to profile {}", profile);
+                            },
+                            Err(e) => println!("{}: {}", "Staying on this profile".to_string().red().bold(), e),
+                        }
+                    }
+                }
+            },
+            MainloopOption::RestoreBackup => {
+                let backups = list_backups(&paths.backups);
+                if backups.is_empty() {
+                    println!("No backups yet, one is made each time the data is saved");
+                } else {
+                    // newest first
+                    let options:Vec<String> = backups.into_iter().rev().collect();
+                    match Select::new("Restore which backup?", options)
+                        .with_help_message("The current data is backed up first, so this can be undone")
+                        .prompt() {
+                        Ok(b) => {
+                            match read_backup(&b, &paths.data_files(), &paths.backups) {
+                                Ok(files) => {
+                                    // save first, so that the backup made below has any unsaved changes in it too
+                                    let errors = save_all!();
+                                    let restore = errors.is_empty() || Confirm::new("Saving failed, restore anyway? Unsaved changes will be lost")
+                                        .with_default(false)
+                                        .prompt()
+                                        .unwrap_or_default();
+                                    if !restore {
+                                        println!("Nothing restored");
+                                    } else if let Err(e) = create_backup(&paths.data_files(), &paths.backups, BACKUPS_KEPT) {
+                                        println!("{}: {}", "Could not back up the current data, nothing restored".to_string().red().bold(), e);
+                                    } else {
+                                        match restore_backup(&files) {
+                                            Ok(_) => {
+                                                println!("Restored {} file(s) from {}", files.len(), b);
+                                                reload_all!();
+                                                journal.record(Operation::Snapshot { accounts: accounts.clone(), transactions: transactions.clone() }, "restore");
+                                            },
+                                            Err(e) => println!("{}: {}", "Error restoring backup".to_string().red().bold(), e),
+                                        }
+                                    }
+                                },
+                                Err(e) => println!("{}: {}", "Error reading backup, nothing restored".to_string().red().bold(), e),
+                            }
+                        },
+                        Err(_) => {},
+                    }
+                }
             },
             MainloopOption::Clear => clearscreen::clear().expect("failed to clear screen"),
             MainloopOption::NewTransaction => {
-                match new_transaction_from_inputs(&acc_accounts, &cat_accounts) { // passing both to save re-calculation
-                    Ok(t) => transactions.push(t),
+                match new_transaction_from_inputs(&acc_accounts, &cat_accounts, &category_rules) { // passing both to save re-calculation
+                    Ok(t) => {
+                        match confirm_not_duplicate(&t, &transactions, settings.duplicate_window_days) {
+                            Ok(true) => {
+                                record!(Change::AddTransactions(vec!(t.clone())), "menu");
+                                transactions.push(t);
+                            },
+                            _ => println!("Transaction not added"),
+                        }
+                    },
                     Err(_) => {}
                 }
             },
             MainloopOption::NewTransfer => {
                 match new_transfer_from_inputs(&acc_accounts) {
-                    Ok(t) => transactions.push(t),
+                    Ok(t) => {
+                        match confirm_not_duplicate(&t, &transactions, settings.duplicate_window_days) {
+                            Ok(true) => {
+                                record!(Change::AddTransactions(vec!(t.clone())), "menu");
+                                transactions.push(t);
+                            },
+                            _ => println!("Transfer not added"),
+                        }
+                    },
                     Err(_) => {}
                 }
             },
+            MainloopOption::NewSplitTransaction => {
+                match new_split_transaction_from_inputs(&acc_accounts, &cat_accounts) {
+                    Ok(t) => {
+                        match confirm_not_duplicate(&t, &transactions, settings.duplicate_window_days) {
+                            Ok(true) => {
+                                record!(Change::AddTransactions(vec!(t.clone())), "menu");
+                                transactions.push(t);
+                            },
+                            _ => println!("Transaction not added"),
+                        }
+                    },
+                    Err(_) => {}
+                }
+            },
+            MainloopOption::ImportCsv => {
+                match import_csv_from_inputs(&mut import_profiles, &acc_accounts, &cat_accounts, &category_rules, &transactions,
+                                             settings.duplicate_window_days,
+                                             settings.signed_with_colour, settings.brackets_when_neg) {
+                    Ok(mut ts) => {
+                        println!("Imported {} transaction(s)", ts.len());
+                        if !ts.is_empty() { record!(Change::AddTransactions(ts.clone()), "import") }
+                        transactions.append(&mut ts);
+                    },
+                    Err(e) => println!("ERROR => Import CSV operation ABORTED: {}", e),
+                }
+            },
+            MainloopOption::Duplicates => {
+                let before = transactions.clone();
+                if let Err(e) = review_duplicates(&mut transactions, settings.duplicate_window_days) {
+                    println!("ERROR => Review Duplicates operation ABORTED: {}", e);
+                }
+                // clusters already resolved before an abort are kept. Like reassigning, this isn't undoable and
+                // anything recorded for undo before it may refer to transactions that have now gone
+                if transactions != before {
+                    journal.record_difference(&before, &transactions, "duplicates");
+                    history.clear();
+                }
+            },
+            MainloopOption::Reconcile => {
+                let before = transactions.clone();
+                match reconcile_from_inputs(&mut transactions, &Account::acc_accounts(&accounts),
+                                            settings.signed_with_colour, settings.brackets_when_neg) {
+                    Ok(n) => {
+                        if n > 0 { println!("Reconciled {} transaction(s)", n) }
+                    },
+                    Err(e) => println!("ERROR => Reconcile operation ABORTED: {}", e),
+                }
+                // undoing an earlier edit would no longer find the transaction, now that its status has changed
+                if transactions != before {
+                    journal.record_difference(&before, &transactions, "reconcile");
+                    history.clear();
+                }
+            },
             MainloopOption::ShowAccount => {
                 // show account and sample of all transactions under it
                 match Select::new("Pick Account:", accounts.to_vec())
                     .prompt() {
                     Ok(account) => {
                         println!("Account Name: {}, id {}", account.name, account.id);
-                        let mut total:i64 = 0;
+                        let mut total = Money::ZERO;
+                        if !account.category {
+                            println!("Type: {} in {}{}", account.account_type, account.currency, match (account.account_type.limit_name(), account.limit) {
+                                (Some(limit_name), Some(l)) => format!(", {} {}", limit_name, format_money(l, &account.currency, false, false)),
+                                _ => String::new(),
+                            });
+                            if !account.opening_balance.is_zero() {
+                                println!("Opening balance: {}{}", format_money_colour(account.opening_balance, &account.currency, true, false),
+                                         match account.opening_date { Some(d) => format!(" on {}", d), None => String::new() });
+                            }
+                            total = account.opening_balance;
+                        }
+                        // categories add up spending from accounts in any currency, so are shown in the base currency
+                        let (currency, shown) = if account.category {
+                            (settings.base_currency.clone(), in_base_currency(&transactions, &accounts, &exchange_rates, &settings.base_currency))
+                        } else {
+                            (account.currency.clone(), transactions.to_vec())
+                        };
                         let mut recent_transactions:Vec<Transaction> = Vec::new();
-                        for t in transactions.to_vec().into_iter() {
+                        for t in shown.into_iter() {
                             if t.account_id_1() == account.id {
                                 total += t.amount();
                                 recent_transactions.push(t);
+                            } else if account.category && t.is_split() {
+                                // only the split lines in this category count towards it
+                                if t.category_lines().iter().any(|(c, _)| *c == account.id) {
+                                    total -= t.amount_in_category(account.id);
+                                    recent_transactions.push(t);
+                                }
                             } else if t.account_id_2() == account.id {
-                                total -= t.amount();
+                                total -= t.amount_2();
                                 recent_transactions.push(t);
                             }
                         }
-                        println!("Contains {}", pence_to_pound_colour_bg(total, true, false));
+                        println!("Contains {}", format_money_colour_bg(total, &currency, true, false));
                         println!("Recent Transactions:");
                         recent_transactions.sort_by_key(|t| t.date());
                         for t in recent_transactions.iter() {
+                            if t.is_split() {
+                                let mut names = Vec::new();
+                                for split in t.splits().iter() {
+                                    names.push(match get_account_name(&accounts, split.category_id) {
+                                        Some(name) => name,
+                                        None => "<Category name not found>".to_string(),
+                                    });
+                                }
+                                println!("{}\t{}\tcat: split ({})\tref: {}",
+                                         t.date(),
+                                         format_money_colour(
+                                             if account.category { t.amount_in_category(account.id) } else { t.amount() },
+                                             &currency, true, false),
+                                         names.join(", "),
+                                         t.reference());
+                                continue;
+                            }
                             match t.account_2(&accounts){
                                 Ok(acc) => {
                                     if acc.category {
                                         println!("{}\t{}\tcat: {}\tref: {}",
                                                  t.date(),
-                                                 pence_to_pound_colour(t.amount(), true, false),
+                                                 format_money_colour(t.amount(), &currency, true, false),
                                                  acc.name,
                                                  t.reference());
 
                                     } else {
                                         println!("{}\t{}\t{}\tref: {}",
                                                  t.date(),
-                                                 pence_to_pound_transfer(-t.amount(), true, false),
+                                                 format_money_transfer(if acc.id == account.id { -t.amount_2() } else { -t.amount() },
+                                                                       &currency, true, false),
                                                  {
                                                      if acc.id == account.id {
                                                          format!("from: {}",match t.account_1(&accounts){
@@ -238,7 +900,7 @@ fn main() {
                                 Err(_) => {
                                     println!("{}\t{}\tcat: <Category/account name not found>\tref: {}",
                                              t.date(),
-                                             pence_to_pound_colour(t.amount(), true, false),
+                                             format_money_colour(t.amount(), &currency, true, false),
                                              t.reference());
                                 }
                             }
@@ -255,38 +917,246 @@ fn main() {
                         max_len = len;
                     }
                 }
-                for a in acc_accounts.to_vec().into_iter() {
-                    let mut s = format!("{}", a);
-                    let mut total:i64 = 0;
-                    for t in transactions.to_vec().into_iter() {
-                        if t.account_id_1() == a.id {
-                            total += t.amount();
+                // grouped by type, in the order the types are offered
+                let today = Utc::now().date_naive();
+                let mut grand_total = Money::ZERO; // in the base currency
+                for account_type in AccountType::ALL.iter() {
+                    let of_type:Vec<Account> = acc_accounts.iter().filter(|a| a.account_type == *account_type).cloned().collect();
+                    if of_type.is_empty() { continue }
+                    println!("{}:", account_type.to_string().bold());
+                    for a in of_type.into_iter() {
+                        let mut s = format!("  {}", a);
+                        let total = Account::get_total_amount(&transactions, &a);
+                        while s.len() < max_len+4 {
+                            s.push(' ');
                         }
-                        if t.account_id_2() == a.id {
-                            total -= t.amount();
+                        // bodge here:
+                        let mut padding = String::new();
+                        let target_spacing = 12usize.saturating_sub(format_money(total, &a.currency, true, false).len());
+                        while padding.len() < target_spacing {
+                            padding.push(' ');
                         }
+                        let in_base = convert_or_same(&exchange_rates, total, &a.currency, &settings.base_currency, today);
+                        grand_total += in_base;
+                        let converted = if a.currency == settings.base_currency { String::new() } else {
+                            format!(" (≈ {})", format_money(in_base, &settings.base_currency, true, false))
+                        };
+                        let available = match a.available_credit(total) {
+                            Some(c) => format!("   Available: {}", format_money_colour(c, &a.currency, true, false)),
+                            None => String::new(),
+                        };
+                        // anything pending or scheduled is in the total, but not yet in the account
+                        let cleared = a.cleared_balance_on(&transactions, today);
+                        let cleared = if cleared == total { String::new() } else {
+                            format!("   Cleared: {}", format_money(cleared, &a.currency, true, false))
+                        };
+                        println!("{s}Total Value: {}{}{}{}{}", padding, format_money_colour_bg(total, &a.currency, true, false), converted, cleared, available);
                     }
-                    while s.len() < max_len+2 {
-                        s.push(' ');
+                }
+                println!("{}: {}", format!("Total in {}", settings.base_currency).bold(),
+                         format_money_colour_bg(grand_total, &settings.base_currency, true, false));
+                for c in missing_rates(&accounts, &exchange_rates, &settings.base_currency) {
+                    println!("Warning: no exchange rate between {} and {}, so the total is not accurate", c, settings.base_currency);
+                }
+                let archived:Vec<String> = accounts.iter().filter(|a| !a.category && a.archived).map(|a| a.name.clone()).collect();
+                if !archived.is_empty() { println!("Archived: {}", archived.join(", ")) }
+            },
+            MainloopOption::ListCategories => {
+                // spending is positive, as in Show Account. A parent's total includes everything below it
+                let in_base = in_base_currency(&transactions, &accounts, &exchange_rates, &settings.base_currency);
+                let own_total = |id: u32| -> Money { -in_base.iter().map(|t| t.amount_in_category(id)).sum::<Money>() };
+                let tree = category_tree(&cat_accounts);
+                let max_len = tree.iter().map(|e| e.to_string().len()).max().unwrap_or(0);
+                println!("Categories, each under its parent:");
+                for e in tree.iter() {
+                    let own = own_total(e.account.id);
+                    let total:Money = with_descendants(&accounts, e.account.id).into_iter().map(|id| own_total(id)).sum();
+                    let mut s = format!("- {}", e);
+                    while s.len() < max_len+4 { s.push(' ') }
+                    println!("{}{}{}", s, format_money_colour(total, &settings.base_currency, true, settings.brackets_when_neg),
+                             if total != own { format!("  (own: {})", format_money(own, &settings.base_currency, true, settings.brackets_when_neg)) } else { String::new() });
+                }
+                let archived:Vec<String> = accounts.iter().filter(|a| a.category && a.archived).map(|a| a.name.clone()).collect();
+                if !archived.is_empty() { println!("Archived: {}", archived.join(", ")) }
+            },
+            MainloopOption::ExchangeRates => {
+                let mut rating = true;
+                while rating {
+                    print_exchange_rates(&exchange_rates, &accounts, &settings.base_currency);
+                    match Select::new("Exchange rate action:", vec!(
+                        ExchangeRateOptions::Back,
+                        ExchangeRateOptions::Add,
+                        ExchangeRateOptions::Import,
+                        ExchangeRateOptions::Remove,
+                        ExchangeRateOptions::BaseCurrency,
+                    )).prompt() {
+                        Ok(ExchangeRateOptions::Add) => {
+                            match new_exchange_rate_from_inputs(&settings.base_currency) {
+                                Ok(r) => {
+                                    // one rate per pair per day
+                                    exchange_rates.retain(|x| !(x.date == r.date && x.from == r.from && x.to == r.to));
+                                    exchange_rates.push(r);
+                                },
+                                Err(e) => println!("ERROR => Add Exchange Rate operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(ExchangeRateOptions::Import) => {
+                            match import_exchange_rates_from_inputs(&mut exchange_rates) {
+                                Ok(count) => println!("Imported {} exchange rate(s)", count),
+                                Err(e) => println!("ERROR => Import Exchange Rates operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(ExchangeRateOptions::Remove) => {
+                            match remove_exchange_rate_from_inputs(&mut exchange_rates) {
+                                Ok(_) => {},
+                                Err(e) => println!("{}", e),
+                            }
+                        },
+                        Ok(ExchangeRateOptions::BaseCurrency) => {
+                            match query_currency("Base currency:", &settings.base_currency) {
+                                Ok(c) => {
+                                    settings.base_currency = c;
+                                    if let Err(e) = save_settings_to_file(&settings_filepath(&data_dir), &settings) {
+                                        println!("{}", e.red().bold());
+                                    }
+                                },
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(ExchangeRateOptions::Back) | Err(_) => rating = false,
                     }
-                    // bodge here:
-                    let mut padding = String::new();
-                    let target_spacing = 12-pence_to_pound(total, true, false).len();
-                    while padding.len() < target_spacing {
-                        padding.push(' ');
+                }
+            },
+            MainloopOption::Budgets => {
+                let mut budgeting = true;
+                while budgeting {
+                    println!("Budgets for the current period:");
+                    print_budgets(&budgets, &accounts,
+                                  &in_base_currency(&transactions, &accounts, &exchange_rates, &settings.base_currency),
+                                  &settings.base_currency, settings.signed_with_colour, settings.brackets_when_neg);
+                    match Select::new("Budget action:", vec!(
+                        BudgetOptions::Back,
+                        BudgetOptions::Set,
+                        BudgetOptions::Remove,
+                    )).prompt() {
+                        Ok(BudgetOptions::Set) => {
+                            match new_budget_from_inputs(&cat_accounts, &settings.base_currency) {
+                                Ok(b) => {
+                                    // only one budget per category
+                                    budgets.retain(|x| x.category_id != b.category_id);
+                                    budgets.push(b);
+                                },
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(BudgetOptions::Remove) => {
+                            match remove_budget_from_inputs(&mut budgets, &accounts) {
+                                Ok(_) => {},
+                                Err(e) => println!("{}", e),
+                            }
+                        },
+                        Ok(BudgetOptions::Back) | Err(_) => budgeting = false,
                     }
-                    println!("{s}Total Value: {}{}", padding, pence_to_pound_colour_bg(total, true, false));
                 }
             },
-            MainloopOption::ListCategories => {
-                println!("Categories listed by creation order:");
-                for a in cat_accounts.to_vec().into_iter() {
-                    println!("- {}", a);
+            MainloopOption::Recurring => {
+                let mut recurring_menu = true;
+                while recurring_menu {
+                    println!("Recurring Transactions:");
+                    print_recurring(&recurring, &accounts, settings.signed_with_colour, settings.brackets_when_neg);
+                    match Select::new("Recurring action:", vec!(
+                        RecurringOptions::Back,
+                        RecurringOptions::New,
+                        RecurringOptions::PauseResume,
+                        RecurringOptions::Modify,
+                        RecurringOptions::Delete,
+                        RecurringOptions::Materialise,
+                    )).prompt() {
+                        Ok(RecurringOptions::New) => {
+                            match new_recurring_from_inputs(&recurring, &acc_accounts, &cat_accounts, &category_rules) {
+                                Ok(r) => recurring.push(r),
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(RecurringOptions::PauseResume) => {
+                            match Select::new("Pause / resume which?", recurring.to_vec()).prompt() {
+                                Ok(r) => {
+                                    for x in recurring.iter_mut() {
+                                        if x.id == r.id { x.paused = !x.paused }
+                                    }
+                                },
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(RecurringOptions::Modify) => {
+                            match Select::new("Modify which?", recurring.to_vec()).prompt() {
+                                Ok(r) => {
+                                    match modify_recurring_from_inputs(&r, &acc_accounts, &cat_accounts) {
+                                        Ok(nr) => {
+                                            recurring.retain(|x| x.id != r.id);
+                                            recurring.push(nr);
+                                        },
+                                        Err(_) => println!("ERROR => Modify Recurring Transaction operation ABORTED"),
+                                    }
+                                },
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(RecurringOptions::Delete) => {
+                            match Select::new("Delete which?", recurring.to_vec()).prompt() {
+                                Ok(r) => recurring.retain(|x| x.id != r.id),
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(RecurringOptions::Materialise) => {
+                            let mut due = materialise_all(&mut recurring, Utc::now().date_naive());
+                            println!("Added {} recurring transaction(s)", due.len());
+                            for t in due.iter() { journal.record(Operation::AddTransaction { after: t.clone() }, "recurring") }
+                            transactions.append(&mut due);
+                        },
+                        Ok(RecurringOptions::Back) | Err(_) => recurring_menu = false,
+                    }
+                }
+            },
+            MainloopOption::CategoryRules => {
+                let mut ruling = true;
+                while ruling {
+                    print_category_rules(&category_rules, &accounts);
+                    match Select::new("Rule action:", vec!(
+                        CategoryRuleOptions::Back,
+                        CategoryRuleOptions::New,
+                        CategoryRuleOptions::Edit,
+                        CategoryRuleOptions::Delete,
+                    )).prompt() {
+                        Ok(CategoryRuleOptions::New) => {
+                            match new_category_rule_from_inputs(&category_rules, &cat_accounts) {
+                                Ok(r) => category_rules.push(r),
+                                Err(e) => println!("ERROR => New Rule operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(CategoryRuleOptions::Edit) => {
+                            match Select::new("Edit which rule?", category_rules.to_vec()).prompt() {
+                                Ok(r) => match modified_category_rule_from_inputs(&r, &cat_accounts) {
+                                    Ok(new) => for x in category_rules.iter_mut() { if x.id == new.id { *x = new.clone() } },
+                                    Err(e) => println!("ERROR => Edit Rule operation ABORTED: {}", e),
+                                },
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(CategoryRuleOptions::Delete) => {
+                            match Select::new("Delete which rule?", category_rules.to_vec()).prompt() {
+                                Ok(r) => category_rules.retain(|x| x.id != r.id),
+                                Err(_) => {}
+                            }
+                        },
+                        Ok(CategoryRuleOptions::Back) | Err(_) => ruling = false,
+                    }
                 }
             },
             MainloopOption::AddAccount => {
                 match new_account_from_inputs(&accounts) {
                     Ok(a) => {
+                        record!(Change::AddAccount(a.clone()), "menu");
                         accounts.push(a.clone());
                         // update acc accounts
                         acc_accounts.push(a);
@@ -297,6 +1167,7 @@ fn main() {
             MainloopOption::AddCategory => {
                 match new_category_from_inputs(&accounts) {
                     Ok(a) => {
+                        record!(Change::AddAccount(a.clone()), "menu");
                         accounts.push(a.clone());
                         // update cat accounts
                         cat_accounts.push(a);
@@ -304,6 +1175,162 @@ fn main() {
                     Err(_) => {}
                 }
             },
+            MainloopOption::ManageAccounts => {
+                // move everything on <from> over to <to>, then delete <from>. This touches every transaction
+                // involved so it can't be undone, and anything recorded for undo before it may no longer apply
+                macro_rules! reassign_and_delete {
+                    ($from:expr, $to:expr) => {{
+                        let before = transactions.clone();
+                        // reconciled transactions that are moved go back to cleared, so ask first
+                        let reconciled = transactions.iter().filter(|t| t.involves($from.id) && t.is_reconciled()).count();
+                        let unlocked = reconciled == 0 || matches!(Confirm::new(&format!(
+                            "{} of the transactions have been reconciled against a statement. Unlock them to move them?", reconciled))
+                            .with_default(false)
+                            .with_help_message("They will need reconciling again")
+                            .prompt(), Ok(true));
+                        // the opening balance and limit belong to <from> alone and go with it
+                        let own_amounts = $from.category || ($from.opening_balance.is_zero() && $from.limit.is_none())
+                            || matches!(Confirm::new(&format!(
+                            "\"{}\" has an opening balance or limit, which won't be moved to \"{}\". Delete it anyway?", $from.name, $to.name))
+                            .with_default(false)
+                            .prompt(), Ok(true));
+                        match if !unlocked {
+                            Err("Reconciled transactions are locked".to_string())
+                        } else if !own_amounts {
+                            Err(format!("\"{}\" still has an opening balance or limit", $from.name))
+                        } else {
+                            reassign_references(&$from, &$to, &mut transactions, &mut budgets, &mut category_rules, &mut recurring)
+                        } {
+                            Ok(_) => {
+                                let moved = before.iter().zip(transactions.iter()).filter(|(b, a)| b != a).count();
+                                journal.record_difference(&before, &transactions, "accounts");
+                                for (old, new) in reparent_children(&mut accounts, &$from, &$to).into_iter() {
+                                    journal.record(Operation::ModifyAccount { before: old, after: new }, "accounts");
+                                }
+                                journal.record(Operation::DeleteAccount { before: $from.clone() }, "accounts");
+                                accounts.retain(|x| x.id != $from.id);
+                                refresh_accounts!();
+                                history.clear();
+                                println!("Moved {} transaction(s) to \"{}\" and deleted \"{}\" (this can't be undone)", moved, $to.name, $from.name);
+                            },
+                            Err(e) => println!("ERROR => Nothing changed: {}", e),
+                        }
+                    }}
+                }
+                let mut managing = true;
+                while managing {
+                    match Select::new("Account action:", vec!(
+                        ManageAccountOptions::Back,
+                        ManageAccountOptions::Rename,
+                        ManageAccountOptions::EditDetails,
+                        ManageAccountOptions::MoveCategory,
+                        ManageAccountOptions::Archive,
+                        ManageAccountOptions::Unarchive,
+                        ManageAccountOptions::Delete,
+                        ManageAccountOptions::MergeCategories,
+                    )).prompt() {
+                        Ok(ManageAccountOptions::Rename) => {
+                            match pick_account(&accounts, "Rename which?", |_| true)
+                                .and_then(|old| renamed_account_from_inputs(&accounts, &old).map(|new| (old, new))) {
+                                Ok((old, new)) if old.name != new.name => {
+                                    record!(Change::ModifyAccount { old, new: new.clone() }, "accounts");
+                                    for a in accounts.iter_mut() { if a.id == new.id { *a = new.clone() } }
+                                    refresh_accounts!();
+                                },
+                                Ok(_) => println!("Name unchanged"),
+                                Err(e) => println!("ERROR => Rename operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(ManageAccountOptions::EditDetails) => {
+                            match pick_account(&accounts, "Change which account?", |a| !a.category) {
+                                Ok(old) => {
+                                    let mut new = old.clone();
+                                    match query_account_details(&mut new, &transactions) {
+                                        Ok(_) => {
+                                            record!(Change::ModifyAccount { old, new: new.clone() }, "accounts");
+                                            for a in accounts.iter_mut() { if a.id == new.id { *a = new.clone() } }
+                                            refresh_accounts!();
+                                        },
+                                        Err(e) => println!("ERROR => Change Account operation ABORTED: {}", e),
+                                    }
+                                },
+                                Err(e) => println!("ERROR => Change Account operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(ManageAccountOptions::MoveCategory) => {
+                            match pick_account(&accounts, "Move which category?", |a| a.category)
+                                .and_then(|old| query_parent(&accounts, &cat_accounts, &old).map(|parent| (old, parent))) {
+                                Ok((old, parent)) if old.parent != parent => {
+                                    let mut new = old.clone();
+                                    new.parent = parent;
+                                    record!(Change::ModifyAccount { old, new: new.clone() }, "accounts");
+                                    for a in accounts.iter_mut() { if a.id == new.id { *a = new.clone() } }
+                                    refresh_accounts!();
+                                },
+                                Ok(_) => println!("Parent unchanged"),
+                                Err(e) => println!("ERROR => Move Category operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(option @ ManageAccountOptions::Archive) | Ok(option @ ManageAccountOptions::Unarchive) => {
+                            let archive = matches!(option, ManageAccountOptions::Archive);
+                            match pick_account(&accounts, &format!("{} which?", if archive { "Archive" } else { "Unarchive" }), |a| a.archived != archive) {
+                                Ok(old) => {
+                                    let mut new = old.clone();
+                                    new.archived = archive;
+                                    record!(Change::ModifyAccount { old, new: new.clone() }, "accounts");
+                                    for a in accounts.iter_mut() { if a.id == new.id { *a = new.clone() } }
+                                    refresh_accounts!();
+                                },
+                                Err(e) => println!("ERROR => {} operation ABORTED: {}", option, e),
+                            }
+                        },
+                        Ok(ManageAccountOptions::Delete) => {
+                            match pick_account(&accounts, "Delete which?", |_| true) {
+                                Ok(a) => {
+                                    let (in_transactions, elsewhere) = count_references(a.id, &accounts, &transactions, &budgets, &category_rules, &recurring);
+                                    if in_transactions + elsewhere == 0 {
+                                        match Confirm::new(&format!("Delete \"{}\"?", a.name)).with_default(false).prompt() {
+                                            Ok(true) => {
+                                                record!(Change::DeleteAccount(a.clone()), "accounts");
+                                                accounts.retain(|x| x.id != a.id);
+                                                refresh_accounts!();
+                                            },
+                                            _ => println!("Nothing deleted"),
+                                        }
+                                    } else {
+                                        println!("\"{}\" is used by {} transaction(s) and {} budget(s), rule(s) or recurring transaction(s). \
+                                                  To keep them as they are, archive it instead", a.name, in_transactions, elsewhere);
+                                        match pick_account(&accounts, "Move them all to:", |x| x.category == a.category && !x.archived && x.id != a.id
+                                                                                              && (a.category || x.currency == a.currency)) {
+                                            Ok(to) => reassign_and_delete!(a, to),
+                                            Err(_) => println!("Nothing deleted"),
+                                        }
+                                    }
+                                },
+                                Err(e) => println!("ERROR => Delete operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(ManageAccountOptions::MergeCategories) => {
+                            match pick_account(&accounts, "Merge which category?", |a| a.category)
+                                .and_then(|from| pick_account(&accounts, "Into which category?", |x| x.category && !x.archived && x.id != from.id)
+                                .map(|to| (from, to))) {
+                                Ok((from, to)) => {
+                                    let (in_transactions, _) = count_references(from.id, &accounts, &transactions, &budgets, &category_rules, &recurring);
+                                    match Confirm::new(&format!("Move {} transaction(s) from \"{}\" to \"{}\" and delete \"{}\"?",
+                                                                in_transactions, from.name, to.name, from.name))
+                                        .with_default(false)
+                                        .prompt() {
+                                        Ok(true) => reassign_and_delete!(from, to),
+                                        _ => println!("Nothing merged"),
+                                    }
+                                },
+                                Err(e) => println!("ERROR => Merge operation ABORTED: {}", e),
+                            }
+                        },
+                        Ok(ManageAccountOptions::Back) | Err(_) => managing = false,
+                    }
+                }
+            },
             MainloopOption::EditAccountSelection => {
                 match MultiSelect::new("Select the accounts you want to view in the calendar", acc_accounts.to_vec())
                     .prompt() {
@@ -311,6 +1338,26 @@ fn main() {
                     Err(_) => {}
                 }
             },
+            MainloopOption::EditTagSelection => {
+                let tags = all_tags(&transactions);
+                if tags.is_empty() {
+                    println!("No transactions have been tagged yet.");
+                } else {
+                    let selected: Vec<usize> = tags.iter().enumerate()
+                        .filter(|(_, t)| visible_tags.contains(t)).map(|(i, _)| i).collect();
+                    match MultiSelect::new("Select the tags you want to view in the calendar (none for all)", tags)
+                        .with_default(&selected)
+                        .prompt() {
+                        Ok(vt) => visible_tags = vt,
+                        Err(_) => {}
+                    }
+                }
+            },
+            MainloopOption::TagReport => {
+                print_tag_report(&in_base_currency(&transactions, &accounts, &exchange_rates, &settings.base_currency),
+                                 &settings.base_currency, settings.signed_with_colour, settings.brackets_when_neg);
+                println!();
+            },
             MainloopOption::TextCalendar => {
                 // print transaction calendar mini-mainloop
 
@@ -320,24 +1367,53 @@ fn main() {
                 let mut zi = 0; // zero index (position for the calendar to start in
                 let mut method = CalInteractMethod::Calendar;
                 let mut thi:isize = 0; // transaction highlight index
+                let mut month_view = false; // whole calendar month rather than weeks around today
                 // let mut highlighted_date_transactions = Vec::new();
 
                 let mut this_transaction = None;
 
                 while calendaring {
+                    // forecast up to the last day visible in the calendar
+                    let today = Utc::now().date_naive();
+                    // month view shows the month of the highlighted day
+                    let highlighted = today + Duration::days(hi - ti);
+                    let month = match month_view {
+                        true => Some(highlighted.with_day(1).unwrap()),
+                        false => None,
+                    };
+                    let (grid_zi, weeks) = match month {
+                        Some(first) => month_grid(first, ti),
+                        None => (zi, settings.weeks),
+                    };
+                    let last_visible = today + Duration::days(7*weeks - 1 - ti + grid_zi);
+                    let projected = forecast_transactions(
+                        settings.forecast_method,
+                        settings.forecast_weeks,
+                        &transactions,
+                        &recurring,
+                        &accounts,
+                        today,
+                        last_visible,
+                    );
                     match method {
                         CalInteractMethod::Calendar => {
                             let cal_pane = render_calendar(
-                                day_width,
+                                settings.day_width,
                                 weeks,
-                                max_transactions_per_day,
+                                settings.max_transactions_per_day,
                                 ti,
-                                signed_with_colour,
-                                brackets_when_neg,
+                                settings.signed_with_colour,
+                                settings.brackets_when_neg,
                                 &transactions,
+                                &projected,
                                 &visible_accounts,
+                                &visible_tags,
+                                &accounts,
+                                &exchange_rates,
+                                &settings.base_currency,
                                 hi, // hidden
-                                zi
+                                grid_zi,
+                                month,
                             );
 
                             let day_submenu_pane;
@@ -345,12 +1421,16 @@ fn main() {
                                 -1,
                                 hi,
                                 ti,
-                                signed_with_colour,
-                                brackets_when_neg,
+                                settings.signed_with_colour,
+                                settings.brackets_when_neg,
                                 &transactions,
+                                &projected,
                                 &visible_accounts,
+                                &visible_tags,
                                 &accounts,
-                                info_width,
+                                &exchange_rates,
+                                &settings.base_currency,
+                                settings.info_width,
                             ) {
                                 (sbs, _, _) => {
                                     day_submenu_pane = sbs;
@@ -360,9 +1440,38 @@ fn main() {
                             clearscreen::clear().expect("failed to clear screen");
                             SideBySide::print2(&cal_pane,&day_submenu_pane, " ");
 
+                            if !visible_tags.is_empty() {
+                                println!("Only showing transactions tagged: {} (balances include everything)", visible_tags.join(", "));
+                            }
+                            if transactions.iter().any(|t| t.status() != Status::Cleared) {
+                                println!("{} pending or scheduled, {} reconciled",
+                                         ColouredString::from_str("~italic").italic(), ColouredString::from_str("underlined").underline());
+                            }
+                            for c in missing_rates(&visible_accounts, &exchange_rates, &settings.base_currency) {
+                                println!("{} no exchange rate between {} and {}, closing balances add them up unconverted",
+                                         ColouredString::from_str("Warning:").red().bold(), c, settings.base_currency);
+                            }
+
+                            for (a, date, balance) in over_limit_warnings(
+                                &transactions, &projected, &visible_accounts, today, last_visible) {
+                                println!("{} {} {} on {} ({})",
+                                         ColouredString::from_str("Warning:").red().bold(),
+                                         a.name,
+                                         match (a.account_type.limit_name(), a.limit) {
+                                             (Some(limit_name), Some(_)) if date == today => format!("is over its {}", limit_name),
+                                             (Some(limit_name), Some(_)) => format!("is projected to go over its {}", limit_name),
+                                             _ if date == today => "is negative".to_string(),
+                                             _ => "is projected to go negative".to_string(),
+                                         },
+                                         date,
+                                         format_money_colour(balance, &a.currency, true, settings.brackets_when_neg));
+                            }
+
                             println!("{} [Ctrl-C from here will break things - don't do it!]",
-                                ColouredString::from_str("[↑↓ → ← to navigate, enter to select, esc to return to main menu]").cyan()
+                                ColouredString::from_str("[↑↓ → ← to navigate, enter to select, m for month/weeks view, g to go to a date, u/r to undo/redo, esc to return to main menu]").cyan()
                             );
+                            if let Some(d) = history.undo_description() { println!("u: undo {}", d) }
+                            if let Some(d) = history.redo_description() { println!("r: redo {}", d) }
 
                             let term = console::Term::stdout();
 
@@ -392,14 +1501,37 @@ fn main() {
                                         },
                                         console::Key::PageDown => {
                                             capturing = false;
-                                            hi += 7*4;
+                                            hi += match month { // same day of the next month in month view
+                                                Some(_) => (highlighted.checked_add_months(Months::new(1)).unwrap() - highlighted).num_days(),
+                                                None => 7*4,
+                                            };
                                             thi = 0;
                                         },
                                         console::Key::PageUp => {
                                             capturing = false;
-                                            hi -= 7*4;
+                                            hi -= match month {
+                                                Some(_) => (highlighted - highlighted.checked_sub_months(Months::new(1)).unwrap()).num_days(),
+                                                None => 7*4,
+                                            };
                                             thi = 0;
                                         },
+                                        console::Key::Char('m') => {
+                                            capturing = false;
+                                            month_view = !month_view;
+                                        },
+                                        console::Key::Char('g') => {
+                                            capturing = false;
+                                            match DateSelect::new("Go to date:")
+                                                .with_week_start(Weekday::Mon)
+                                                .with_default(highlighted)
+                                                .prompt() {
+                                                Ok(d) => {
+                                                    hi = ti + (d - today).num_days();
+                                                    thi = 0;
+                                                },
+                                                Err(_) => println!("ERROR => Go To Date operation ABORTED"),
+                                            }
+                                        },
                                         console::Key::Enter => {
                                             method = CalInteractMethod::Day;
                                             capturing = false;
@@ -409,6 +1541,26 @@ fn main() {
                                             capturing = false;
                                             calendaring = false;
                                         },
+                                        console::Key::Char('u') => {
+                                            match history.undo(&mut accounts, &mut transactions) {
+                                                Ok(c) => {
+                                                    journal.record_undo(c);
+                                                    capturing = false;
+                                                    refresh_accounts!();
+                                                },
+                                                Err(e) => println!("{}", e),
+                                            }
+                                        },
+                                        console::Key::Char('r') => {
+                                            match history.redo(&mut accounts, &mut transactions) {
+                                                Ok(c) => {
+                                                    journal.record_change(c, "redo");
+                                                    capturing = false;
+                                                    refresh_accounts!();
+                                                },
+                                                Err(e) => println!("{}", e),
+                                            }
+                                        },
                                         _ => println!("unknown input!")
                                     },
                                     Err(e) => println!("error {}", e),
@@ -419,16 +1571,22 @@ fn main() {
                         },
                         CalInteractMethod::Day => {
                             let cal_pane = render_calendar(
-                                day_width,
+                                settings.day_width,
                                 weeks,
-                                max_transactions_per_day,
+                                settings.max_transactions_per_day,
                                 ti,
-                                signed_with_colour,
-                                brackets_when_neg,
+                                settings.signed_with_colour,
+                                settings.brackets_when_neg,
                                 &transactions,
+                                &projected,
                                 &visible_accounts,
-                                zi-1, // hidden
-                                zi
+                                &visible_tags,
+                                &accounts,
+                                &exchange_rates,
+                                &settings.base_currency,
+                                grid_zi-1, // hidden
+                                grid_zi,
+                                month,
                             );
 
                             let day_submenu_pane;
@@ -437,12 +1595,16 @@ fn main() {
                                 thi,
                                 hi,
                                 ti,
-                                signed_with_colour,
-                                brackets_when_neg,
+                                settings.signed_with_colour,
+                                settings.brackets_when_neg,
                                 &transactions,
+                                &projected,
                                 &visible_accounts,
+                                &visible_tags,
                                 &accounts,
-                                info_width,
+                                &exchange_rates,
+                                &settings.base_currency,
+                                settings.info_width,
                             ) {
                                 (sbs, opt, ub) => {
                                     day_submenu_pane = sbs;
@@ -454,7 +1616,7 @@ fn main() {
                             clearscreen::clear().expect("failed to clear screen");
                             SideBySide::print2(&cal_pane,&day_submenu_pane, " ");
 
-                            println!("Use Arrow Keys to navigate, Enter to select, Esc to return to day select. [Ctrl-C from here will break things - don't do it!]");
+                            println!("Use Arrow Keys to navigate, Enter to select, c to mark a pending transaction cleared, Esc to return to day select. [Ctrl-C from here will break things - don't do it!]");
 
                             let term = console::Term::stdout();
 
@@ -489,6 +1651,25 @@ fn main() {
                                             capturing = false;
                                             method = CalInteractMethod::Calendar;
                                         },
+                                        console::Key::Char('c') => {
+                                            match &this_transaction {
+                                                Some(t) if t.is_pending() && t.date() <= today => {
+                                                    let cleared = t.with_status(Status::Cleared);
+                                                    let before = transactions.len();
+                                                    transactions.retain(|x| *x != *t);
+                                                    record!(Change::ModifyTransaction {
+                                                        old: t.clone(),
+                                                        new: cleared.clone(),
+                                                        count: before - transactions.len(),
+                                                    }, "calendar");
+                                                    transactions.push(cleared);
+                                                    capturing = false;
+                                                },
+                                                Some(t) if t.date() > today => println!("That transaction is scheduled for {}, it can't clear before then", t.date()),
+                                                Some(_) => println!("That transaction is not pending"),
+                                                None => println!("No transaction highlighted to be cleared!"),
+                                            }
+                                        },
                                         _ => println!("unknown input!")
                                     },
                                     Err(e) => println!("error {}", e),
@@ -497,16 +1678,22 @@ fn main() {
                         },
                         CalInteractMethod::Transact => {
                             let cal_pane = render_calendar(
-                                day_width,
+                                settings.day_width,
                                 weeks,
-                                max_transactions_per_day,
+                                settings.max_transactions_per_day,
                                 ti,
-                                signed_with_colour,
-                                brackets_when_neg,
+                                settings.signed_with_colour,
+                                settings.brackets_when_neg,
                                 &transactions,
+                                &projected,
                                 &visible_accounts,
-                                zi-1, // hidden
-                                zi
+                                &visible_tags,
+                                &accounts,
+                                &exchange_rates,
+                                &settings.base_currency,
+                                grid_zi-1, // hidden
+                                grid_zi,
+                                month,
                             );
 
                             let day_submenu_pane;
@@ -514,12 +1701,16 @@ fn main() {
                                 thi,
                                 hi,
                                 ti,
-                                signed_with_colour,
-                                brackets_when_neg,
+                                settings.signed_with_colour,
+                                settings.brackets_when_neg,
                                 &transactions,
+                                &projected,
                                 &visible_accounts,
+                                &visible_tags,
                                 &accounts,
-                                info_width,
+                                &exchange_rates,
+                                &settings.base_currency,
+                                settings.info_width,
                             ) {
                                 (sbs, _, _) => {
                                     day_submenu_pane = sbs
@@ -541,8 +1732,9 @@ fn main() {
                                             method = CalInteractMethod::Day; // go back to day menu
                                         },
                                         (TransactionOptions::CreateNewFromTemplate, Some(t)) => {
-                                            match new_transaction_based_on(t) {
+                                            match new_transaction_based_on(t, &accounts, &cat_accounts) {
                                                 Ok(nt) => {
+                                                    record!(Change::AddTransactions(vec!(nt.clone())), "calendar");
                                                     transactions.push(nt);
                                                     method = CalInteractMethod::Day; // go back to day menu
                                                 },
@@ -554,24 +1746,49 @@ fn main() {
                                         },
                                         (TransactionOptions::Modify, Some(t)) => {
                                             // Create a new transaction based on the existing one, remove the existing one and add the new one (TODO: atomically?)
-                                            match get_modified_transaction(t, &acc_accounts, &cat_accounts) {
+                                            // an archived account or category stays selectable for its own transactions
+                                            let mut accs = acc_accounts.clone();
+                                            let mut cats = cat_accounts.clone();
+                                            for a in accounts.iter().filter(|a| a.archived && t.involves(a.id)) {
+                                                if a.category { cats.push(a.clone()) } else { accs.push(a.clone()) }
+                                            }
+                                            match get_modified_transaction(t, &accs, &cats) {
                                                 Ok(new_transaction) => {
                                                     // remove old transaction & add new one
+                                                    let before = transactions.len();
                                                     transactions.retain(|x| *x != *t);
+                                                    record!(Change::ModifyTransaction {
+                                                        old: t.clone(),
+                                                        new: new_transaction.clone(),
+                                                        count: before - transactions.len(),
+                                                    }, "calendar");
                                                     transactions.push(new_transaction);
                                                     method = CalInteractMethod::Day; // go back to day menu
                                                     thi = 0; // reset selection when returning to Day
                                                 }
-                                                Err(_) => {
-                                                    println!("ERROR => Modify Transaction operation ABORTED");
+                                                Err(e) => {
+                                                    println!("ERROR => Modify Transaction operation ABORTED: {}", e);
                                                     method = CalInteractMethod::Transact; // return just to the transaction menu
                                                 }
                                             }
                                         },
                                         (TransactionOptions::Delete, Some(t)) => {
-                                            transactions.retain(|x| *x != *t);
-                                            method = CalInteractMethod::Day; // go back to day menu
-                                            thi = 0; // reset selection when returning to Day
+                                            match confirm_unlocked(t, "delete") {
+                                                Ok(_) => {
+                                                    let before = transactions.len();
+                                                    transactions.retain(|x| *x != *t);
+                                                    record!(Change::DeleteTransaction {
+                                                        transaction: t.clone(),
+                                                        count: before - transactions.len(),
+                                                    }, "calendar");
+                                                    method = CalInteractMethod::Day; // go back to day menu
+                                                    thi = 0; // reset selection when returning to Day
+                                                },
+                                                Err(e) => {
+                                                    println!("ERROR => Delete Transaction operation ABORTED: {}", e);
+                                                    method = CalInteractMethod::Transact; // return just to the transaction menu
+                                                }
+                                            }
                                         },
                                         (_, None) => panic!("shouldn't be given a non-existent selected transaction")
                                     }
@@ -594,18 +1811,28 @@ fn main() {
                         }
 
                         // ask for categories
-                        match MultiSelect::new("Select the category(s) to plot for", cat_accounts.to_vec())
+                        match MultiSelect::new("Select the category(s) to plot for (a parent includes those below it)", category_tree(&cat_accounts))
                             .prompt() {
                             Ok(cas) => {
-                                // calc list of IDs from accounts
+                                // calc list of IDs from accounts, a parent bringing in everything below it
                                 let mut selected_categories = Vec::new();
                                 for ca in cas.into_iter() {
-                                    selected_categories.push(ca.id);
+                                    for id in with_descendants(&accounts, ca.account.id) {
+                                        if !selected_categories.contains(&id) { selected_categories.push(id) }
+                                    }
                                 }
 
+                                // optionally only count transactions with some tags
+                                let tags = all_tags(&transactions);
+                                let selected_tags = if tags.is_empty() { Vec::new() } else {
+                                    MultiSelect::new("Select the tag(s) to plot for (none for all)", tags).prompt().unwrap_or_default()
+                                };
+
                                 // do graphing loop for selection and others etc..
                                 // calculates values and populates graph automatically
-                                graph::graph_acc_cats(selected_accounts, selected_categories, &transactions);
+                                graph::graph_acc_cats(selected_accounts, selected_categories,
+                                                      &in_base_currency(&filter_by_tags(&transactions, &selected_tags), &accounts, &exchange_rates, &settings.base_currency),
+                                                      &settings.base_currency, settings.signed_with_colour, settings.brackets_when_neg);
                             },
                             Err(_) => {} //("ERROR => Select accounts for graphing operation ABORTED")
                         }
@@ -617,7 +1844,7 @@ fn main() {
             MainloopOption::Settings => {
                 let mut settingsing = true;
                 let mut hi = 0; // highlighted index;
-                let num_hi = 6; // number of highlightable positions
+                let num_hi = 9; // number of highlightable positions
                 let mut temp;
                 while settingsing {
                     // draw settings
@@ -626,7 +1853,7 @@ fn main() {
                         println!("Settings: [default values in square brackets]");
                         println!(" {}", ColouredString::from_str("General:").purple());
 
-                        temp = ColouredString::from_str(match signed_with_colour { true => "yes", false => "no", });
+                        temp = ColouredString::from_str(match settings.signed_with_colour { true => "yes", false => "no", });
                         println!("  Signed when coloured: {} [no]\n    {}",
                                  match hi {
                                      0 => {temp.black().on_cyan()},
@@ -634,7 +1861,7 @@ fn main() {
                                  },
                                  ColouredString::from_str("show a minus sign for negative values when the value happens to be coloured to show sign").blue(),
                         );
-                        temp = ColouredString::from_str(match brackets_when_neg { true => "yes", false => "no", });
+                        temp = ColouredString::from_str(match settings.brackets_when_neg { true => "yes", false => "no", });
                         println!("  Brackets: {} [no]\n    {}",
                                  match hi {
                                      1 => {temp.black().on_cyan()},
@@ -644,15 +1871,15 @@ fn main() {
                         );
 
                         println!("  e.g.\n   123p:\n    {}\n    {}\n  -123p:\n    {}\n    {}\n",
-                                 pence_to_pound_colour(123, signed_with_colour, brackets_when_neg),
-                                 pence_to_pound(123, true, brackets_when_neg),
-                                 pence_to_pound_colour(-123, signed_with_colour, brackets_when_neg),
-                                 pence_to_pound(-123, true, brackets_when_neg),
+                                 pence_to_pound_colour(Money::from_minor(123), settings.signed_with_colour, settings.brackets_when_neg),
+                                 pence_to_pound(Money::from_minor(123), true, settings.brackets_when_neg),
+                                 pence_to_pound_colour(Money::from_minor(-123), settings.signed_with_colour, settings.brackets_when_neg),
+                                 pence_to_pound(Money::from_minor(-123), true, settings.brackets_when_neg),
                         );
 
                         println!(" {}", ColouredString::from_str("Calendar:").purple());
 
-                        temp = ColouredString::from_string(format!("{}", weeks));
+                        temp = ColouredString::from_string(format!("{}", settings.weeks));
                         println!("  Weeks visible: {} [4]\n    {}",
                                  match hi {
                                      2 => {temp.black().on_cyan()},
@@ -660,7 +1887,7 @@ fn main() {
                                  },
                                  ColouredString::from_str("number of weeks visible at once in the calendar").blue(),
                         );
-                        temp = ColouredString::from_string(format!("{}", max_transactions_per_day));
+                        temp = ColouredString::from_string(format!("{}", settings.max_transactions_per_day));
                         println!("  Transactions per day: {} [6]\n    {}",
                                  match hi {
                                      3 => {temp.black().on_cyan()},
@@ -671,7 +1898,7 @@ fn main() {
 
                         println!("  (not demonstrated for sake of space)\n");
 
-                        temp = ColouredString::from_string(format!("{}", day_width));
+                        temp = ColouredString::from_string(format!("{}", settings.day_width));
                         println!("  Day width: {} [16]\n    {}",
                                  match hi {
                                      4 => {temp.black().on_cyan()},
@@ -679,7 +1906,7 @@ fn main() {
                                  },
                                  ColouredString::from_str("width of each day in the calendar").blue(),
                         );
-                        temp = ColouredString::from_string(format!("{}", info_width));
+                        temp = ColouredString::from_string(format!("{}", settings.info_width));
                         println!("  Info width: {} [28]\n    {}",
                                  match hi {
                                      5 => {temp.black().on_cyan()},
@@ -690,12 +1917,42 @@ fn main() {
 
                         print!("  e.g.\n|");
                         for _ in 0..7 {
-                            for _ in 0..day_width { print!("-"); }
+                            for _ in 0..settings.day_width { print!("-"); }
                             print!("|")
                         }
-                        for _ in 0..info_width { print!("-"); }
+                        for _ in 0..settings.info_width { print!("-"); }
                         println!("|\n");
 
+                        println!(" {}", ColouredString::from_str("Forecast:").purple());
+
+                        temp = ColouredString::from_string(format!("{}", settings.forecast_method));
+                        println!("  Forecast from: {} [recurring transactions]\n    {}",
+                                 match hi {
+                                     6 => {temp.black().on_cyan()},
+                                     _ => {temp.cyan()},
+                                 },
+                                 ColouredString::from_str("how balances on future days in the calendar are projected").blue(),
+                        );
+                        temp = ColouredString::from_string(format!("{}", settings.forecast_weeks));
+                        println!("  Weeks averaged: {} [8]\n    {}\n",
+                                 match hi {
+                                     7 => {temp.black().on_cyan()},
+                                     _ => {temp.cyan()},
+                                 },
+                                 ColouredString::from_str("number of past weeks the average spend per category is taken over").blue(),
+                        );
+
+                        println!(" {}", ColouredString::from_str("Duplicates:").purple());
+
+                        temp = ColouredString::from_string(format!("{}", settings.duplicate_window_days));
+                        println!("  Date window: {} [3]\n    {}\n",
+                                 match hi {
+                                     8 => {temp.black().on_cyan()},
+                                     _ => {temp.cyan()},
+                                 },
+                                 ColouredString::from_str("days either side for a transaction with a similar reference to count as a likely duplicate").blue(),
+                        );
+
                         println!(" {}", ColouredString::from_str("Automatic: [these are calculated from combinations of other settings]").purple());
 
                         println!("  Width: {}\n    {}",
@@ -751,72 +2008,105 @@ fn main() {
                     // update settings:
                     match hi {
                         0 => {match action {
-                            Actions::Dec => {signed_with_colour = false;},
-                            Actions::Inc => {signed_with_colour = true;},
+                            Actions::Dec => {settings.signed_with_colour = false;},
+                            Actions::Inc => {settings.signed_with_colour = true;},
                             _ => {}
                         }},
                         1 => {match action {
-                            Actions::Dec => {brackets_when_neg = false;},
-                            Actions::Inc => {brackets_when_neg = true;},
+                            Actions::Dec => {settings.brackets_when_neg = false;},
+                            Actions::Inc => {settings.brackets_when_neg = true;},
                             _ => {}
                         }},
                         2 => {match action {
                             Actions::Dec => {
-                                weeks -= 1;
-                                if weeks < weeks_min { weeks = weeks_min; }
+                                settings.weeks -= 1;
+                                if settings.weeks < WEEKS_MIN { settings.weeks = WEEKS_MIN; }
                             },
                             Actions::Inc => {
-                                weeks += 1;
-                                if weeks > weeks_max { weeks = weeks_max; }
+                                settings.weeks += 1;
+                                if settings.weeks > WEEKS_MAX { settings.weeks = WEEKS_MAX; }
                             },
                             _ => {}
                         }},
                         3 => {match action {
                             Actions::Dec => {
-                                max_transactions_per_day -= 1;
-                                if max_transactions_per_day < max_transactions_per_day_min {
-                                    max_transactions_per_day = max_transactions_per_day_min; }
+                                settings.max_transactions_per_day -= 1;
+                                if settings.max_transactions_per_day < MAX_TRANSACTIONS_PER_DAY_MIN {
+                                    settings.max_transactions_per_day = MAX_TRANSACTIONS_PER_DAY_MIN; }
                             },
                             Actions::Inc => {
-                                max_transactions_per_day += 1;
-                                if max_transactions_per_day > max_transactions_per_day_max {
-                                    max_transactions_per_day = max_transactions_per_day_max; }
+                                settings.max_transactions_per_day += 1;
+                                if settings.max_transactions_per_day > MAX_TRANSACTIONS_PER_DAY_MAX {
+                                    settings.max_transactions_per_day = MAX_TRANSACTIONS_PER_DAY_MAX; }
                             },
                             _ => {}
                         }},
                         4 => {match action {
                             Actions::Dec => {
-                                day_width -= 1;
-                                if day_width < day_width_min { day_width = day_width_min; }
+                                settings.day_width -= 1;
+                                if settings.day_width < DAY_WIDTH_MIN { settings.day_width = DAY_WIDTH_MIN; }
                             },
                             Actions::Inc => {
-                                day_width += 1;
-                                if day_width > day_width_max { day_width = day_width_max; }
+                                settings.day_width += 1;
+                                if settings.day_width > DAY_WIDTH_MAX { settings.day_width = DAY_WIDTH_MAX; }
                             },
                             _ => {}
-                        }; width = 1+(day_width+1)*7+1+info_width},
+                        }; width = settings.width()},
                         5 => {match action {
                             Actions::Dec => {
-                                info_width -= 1;
-                                if info_width < info_width_min { info_width = info_width_min; }
+                                settings.info_width -= 1;
+                                if settings.info_width < INFO_WIDTH_MIN { settings.info_width = INFO_WIDTH_MIN; }
+                            },
+                            Actions::Inc => {
+                                settings.info_width += 1;
+                                if settings.info_width > INFO_WIDTH_MAX { settings.info_width = INFO_WIDTH_MAX; }
+                            },
+                            _ => {}
+                        }; width = settings.width()},
+                        6 => {match action {
+                            Actions::Dec => {
+                                settings.forecast_method = match settings.forecast_method {
+                                    ForecastMethod::Average => ForecastMethod::Recurring,
+                                    _ => ForecastMethod::Off,
+                                };
                             },
                             Actions::Inc => {
-                                info_width += 1;
-                                if info_width > info_width_max { info_width = info_width_max; }
+                                settings.forecast_method = match settings.forecast_method {
+                                    ForecastMethod::Off => ForecastMethod::Recurring,
+                                    _ => ForecastMethod::Average,
+                                };
                             },
                             _ => {}
-                        }; width = 1+(day_width+1)*7+1+info_width},
+                        }},
+                        7 => {match action {
+                            Actions::Dec => {
+                                settings.forecast_weeks -= 1;
+                                if settings.forecast_weeks < FORECAST_WEEKS_MIN { settings.forecast_weeks = FORECAST_WEEKS_MIN; }
+                            },
+                            Actions::Inc => {
+                                settings.forecast_weeks += 1;
+                                if settings.forecast_weeks > FORECAST_WEEKS_MAX { settings.forecast_weeks = FORECAST_WEEKS_MAX; }
+                            },
+                            _ => {}
+                        }},
+                        8 => {match action {
+                            Actions::Dec => {
+                                settings.duplicate_window_days -= 1;
+                                if settings.duplicate_window_days < DUPLICATE_WINDOW_DAYS_MIN {
+                                    settings.duplicate_window_days = DUPLICATE_WINDOW_DAYS_MIN; }
+                            },
+                            Actions::Inc => {
+                                settings.duplicate_window_days += 1;
+                                if settings.duplicate_window_days > DUPLICATE_WINDOW_DAYS_MAX {
+                                    settings.duplicate_window_days = DUPLICATE_WINDOW_DAYS_MAX; }
+                            },
+                            _ => {}
+                        }},
                         _ => {}
                     }
 
                     // save settings to file
-                    save_settings_to_file(SETTINGS_FILEPATH,
-                                          signed_with_colour,
-                                          brackets_when_neg,
-                                          weeks as u64,
-                                          max_transactions_per_day as u64,
-                                          day_width as u64,
-                                          info_width as u64);
+                    if let Err(e) = save_settings_to_file(&settings_filepath(&data_dir), &settings) { println!("{}", e.red().bold()) }
 
                 }
             },
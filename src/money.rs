@@ -0,0 +1,256 @@
+use std::fmt::{Display, Formatter};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Neg, Sub, SubAssign};
+use serde::{Serialize, Deserialize};
+use inquire::{Text, InquireError, validator::Validation};
+
+use crate::currency::{currency_symbol, minor_digits};
+
+// MONEY -------------------------------------------------------------------------------------------
+// an exact amount in minor units (pence, cents, ...) of whichever currency it belongs to. Saved as a
+// plain integer, so data files are the same as when amounts were bare i64s
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_minor(minor: i64) -> Money { return Money(minor) }
+    pub fn minor(&self) -> i64 { return self.0 }
+    pub fn is_zero(&self) -> bool { return self.0 == 0 }
+    pub fn is_negative(&self) -> bool { return self.0 < 0 }
+    pub fn is_positive(&self) -> bool { return self.0 > 0 }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> { return self.0.checked_add(other.0).map(Money) }
+    pub fn checked_sub(self, other: Money) -> Option<Money> { return self.0.checked_sub(other.0).map(Money) }
+    pub fn checked_neg(self) -> Option<Money> { return self.0.checked_neg().map(Money) }
+    pub fn checked_div(self, n: i64) -> Option<Money> { return self.0.checked_div(n).map(Money) } // rounds towards zero
+    pub fn abs(self) -> Money { return if self.0 < 0 { -self } else { self } }
+
+    // exact decimal string -> minor units of <currency>, without going via floating point. Accepts a
+    // leading sign or surrounding brackets for negative values, the currency's symbol or code, thousands
+    // separators and up to as many decimal places as the currency has
+    pub fn parse(string: &str, currency: &str) -> Result<Money, String> {
+        let mut s = string.trim();
+        let mut negative = false;
+        if s.starts_with('(') && s.ends_with(')') {
+            negative = true;
+            s = &s[1..s.len()-1];
+        }
+        if let Some(rest) = s.strip_prefix('-') {
+            negative = !negative;
+            s = rest;
+        } else if let Some(rest) = s.strip_prefix('+') {
+            s = rest;
+        }
+        let s = s.trim();
+        let s = s.strip_prefix(currency_symbol(currency).trim()).unwrap_or(s);
+        let s = s.strip_prefix(currency).unwrap_or(s);
+        let s = s.trim().replace(',', "");
+        let digits = minor_digits(currency) as usize;
+        let (major, minor) = match s.split_once('.') {
+            Some((major, minor)) => (major, minor),
+            None => (s.as_str(), ""),
+        };
+        if major.is_empty() && minor.is_empty() {
+            return Err(format!("\"{}\" is not an amount", string));
+        }
+        if !major.chars().all(|c| c.is_ascii_digit()) || !minor.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("\"{}\" is not an amount", string));
+        }
+        if minor.len() > digits {
+            return Err(format!("\"{}\" has more than {} decimal places", string, digits));
+        }
+        let major:i64 = if major.is_empty() { 0 } else {
+            major.parse().map_err(|_| format!("\"{}\" is too large", string))?
+        };
+        let minor:i64 = if digits == 0 { 0 } else {
+            format!("{:0<w$}", minor, w = digits).parse().unwrap() // right-pad so that ".5" is 50p
+        };
+        let total = major.checked_mul(10i64.pow(digits as u32)).and_then(|m| m.checked_add(minor))
+            .ok_or(format!("\"{}\" is too large", string))?;
+        return Ok(Money(if negative { -total } else { total }));
+    }
+
+    // as parse, for amounts that must be above zero (transfers, limits)
+    pub fn parse_positive(string: &str, currency: &str) -> Result<Money, String> {
+        let m = Money::parse(string, currency)?;
+        if !m.is_positive() { return Err("Amount must be of strictly positive value".to_string()) }
+        return Ok(m);
+    }
+
+    // written in <currency>. <sign> puts a "-" before negative amounts and <brackets> puts them in
+    // brackets, following the settings of the same names
+    pub fn display(self, currency: &str, sign: bool, brackets: bool) -> MoneyDisplay {
+        return MoneyDisplay { money: self, currency: currency.to_string(), sign, brackets };
+    }
+}
+
+// amounts are far smaller than i64 can hold, so running out of range is a bug. Panic rather than
+// silently wrapping around (which is what release builds do for a bare i64)
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money { return self.checked_add(other).expect("amount out of range") }
+}
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money { return self.checked_sub(other).expect("amount out of range") }
+}
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money { return self.checked_neg().expect("amount out of range") }
+}
+impl Div<i64> for Money {
+    type Output = Money;
+    fn div(self, n: i64) -> Money { return self.checked_div(n).expect("amount divided by zero") }
+}
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) { *self = *self + other }
+}
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) { *self = *self - other }
+}
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money { return iter.fold(Money::ZERO, |a, b| a + b) }
+}
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money { return iter.fold(Money::ZERO, |a, b| a + *b) }
+}
+
+
+// MONEY DISPLAY -----------------------------------------------------------------------------------
+pub struct MoneyDisplay {
+    money: Money,
+    currency: String,
+    sign: bool,
+    brackets: bool,
+}
+
+impl Display for MoneyDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let minor = self.money.0;
+        let negative = minor < 0;
+        let digits = minor_digits(&self.currency);
+        let per_major = 10u64.pow(digits);
+        let magnitude = minor.unsigned_abs(); // i64::MIN has no positive i64
+        write!(f, "{}{}{}{}",
+               if negative && self.sign {"-"} else {""},
+               currency_symbol(&self.currency),
+               if negative && self.brackets {"("} else {""},
+               magnitude / per_major)?;
+        if digits > 0 { write!(f, ".{:0w$}", magnitude % per_major, w = digits as usize)?; }
+        write!(f, "{}", if negative && self.brackets {")"} else {""})
+    }
+}
+
+
+// MONEY INPUT -------------------------------------------------------------------------------------
+// asks for an amount in <currency> until it parses exactly, starting from <initial> if given.
+// <positive> only accepts amounts above zero
+pub fn query_money(prompt: &str, help: &str, currency: &str, initial: Option<Money>, positive: bool)
+                   -> Result<Money, InquireError> {
+    let cur = currency.to_string();
+    let parse = move |s: &str| if positive { Money::parse_positive(s, &cur) } else { Money::parse(s, &cur) };
+    let initial = initial.map(|m| m.display(currency, true, false).to_string()).unwrap_or_default();
+    let validate = parse.clone();
+    let s = Text::new(prompt)
+        .with_initial_value(&initial)
+        .with_help_message(help)
+        .with_validator(move |s: &str| match validate(s) {
+            Ok(_) => Ok(Validation::Valid),
+            Err(e) => Ok(Validation::Invalid(e.into())),
+        })
+        .prompt()?;
+    return Ok(parse(&s).unwrap()); // already validated
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<i64, String> { Money::parse(s, "GBP").map(|m| m.minor()) }
+
+    #[test]
+    fn parses_exact_decimals() {
+        assert_eq!(parse("12.34"), Ok(1234));
+        assert_eq!(parse(".5"), Ok(50));
+        assert_eq!(parse("7"), Ok(700));
+        assert_eq!(parse("1,234.5"), Ok(123450));
+        assert_eq!(parse("£3.10"), Ok(310));
+        assert_eq!(parse("GBP 3.10"), Ok(310));
+        assert_eq!(parse("+0.01"), Ok(1));
+    }
+
+    #[test]
+    fn parses_negatives() {
+        assert_eq!(parse("(1.00)"), Ok(-100));
+        assert_eq!(parse("-£1.00"), Ok(-100));
+        assert_eq!(parse("-1"), Ok(-100));
+        assert_eq!(parse("(1,234.56)"), Ok(-123456));
+    }
+
+    #[test]
+    fn rejects_too_many_decimal_places() {
+        assert!(parse("12.345").is_err());
+        assert!(Money::parse("12.5", "JPY").is_err());
+        assert_eq!(Money::parse("1250", "JPY").map(|m| m.minor()), Ok(1250));
+    }
+
+    #[test]
+    fn rejects_things_that_are_not_amounts() {
+        for s in ["", ".", "abc", "1.2.3", "--1", "1e5", "£"] {
+            assert!(parse(s).is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn rejects_amounts_out_of_range() {
+        assert!(parse("92233720368547758.07").is_ok()); // i64::MAX pence
+        assert!(parse("92233720368547758.08").is_err());
+        assert!(parse("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn parse_positive_needs_more_than_zero() {
+        assert!(Money::parse_positive("0", "GBP").is_err());
+        assert!(Money::parse_positive("-1", "GBP").is_err());
+        assert_eq!(Money::parse_positive("0.01", "GBP"), Ok(Money::from_minor(1)));
+    }
+
+    #[test]
+    fn checked_arithmetic_catches_overflow() {
+        let max = Money::from_minor(i64::MAX);
+        assert_eq!(max.checked_add(Money::from_minor(1)), None);
+        assert_eq!(Money::from_minor(i64::MIN).checked_neg(), None);
+        assert_eq!(Money::from_minor(5).checked_div(0), None);
+        assert_eq!(Money::from_minor(-7) / 2, Money::from_minor(-3));
+        assert_eq!([Money::from_minor(1), Money::from_minor(2)].iter().sum::<Money>(), Money::from_minor(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "amount out of range")]
+    fn operators_panic_rather_than_wrap() {
+        let _ = Money::from_minor(i64::MAX) + Money::from_minor(1);
+    }
+
+    #[test]
+    fn displays_with_sign_and_brackets() {
+        let m = Money::from_minor(-1250);
+        assert_eq!(m.display("GBP", true, false).to_string(), "-£12.50");
+        assert_eq!(m.display("GBP", false, true).to_string(), "£(12.50)");
+        assert_eq!(m.display("GBP", false, false).to_string(), "£12.50");
+        assert_eq!(Money::from_minor(5).display("GBP", true, true).to_string(), "£0.05");
+        assert_eq!(Money::from_minor(1250).display("JPY", true, false).to_string(), "¥1250");
+        assert_eq!(Money::from_minor(i64::MIN).display("GBP", true, false).to_string(), "-£92233720368547758.08");
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_amount() {
+        for minor in [0, 1, -1, 99, -100, 123456, -98765432] {
+            let m = Money::from_minor(minor);
+            assert_eq!(Money::parse(&m.display("EUR", true, false).to_string(), "EUR"), Ok(m));
+        }
+    }
+}
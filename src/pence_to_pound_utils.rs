@@ -1,36 +1,49 @@
 use crate::ColouredString;
+use crate::money::Money;
+use crate::currency::{DEFAULT_CURRENCY, currency_symbol};
 
 
-pub fn pence_to_pound(pence:i64, sign:bool, brackets:bool) -> ColouredString {
-    let mut temp = ColouredString::from_string(format!("{}£{}{}.{:02}{}",
-                   if pence < 0 && sign {"-"} else {""},
-                   if pence < 0 && brackets {"("} else {""},
-                   (pence/100).abs(),
-                   (pence%100).abs(),
-                   if pence < 0 && brackets {")"} else {""},
-    ));
-    temp.bodge_alter_len(1); // I think this is needed because the pound sign messes things up!
-    return temp.clone();
+pub fn pence_to_pound(pence:Money, sign:bool, brackets:bool) -> ColouredString {
+    return format_money(pence, DEFAULT_CURRENCY, sign, brackets);
 }
 
-pub fn pence_to_pound_colour(pence:i64, sign:bool, brackets:bool) -> ColouredString {
+pub fn pence_to_pound_colour(pence:Money, sign:bool, brackets:bool) -> ColouredString {
     let s = pence_to_pound(pence, sign, brackets);
-    if pence >= 0 {
+    if !pence.is_negative() {
         return s.green();
     } else {
         return s.red();
     }
 }
 
-pub fn pence_to_pound_colour_bg(pence:i64, sign:bool, brackets:bool) -> ColouredString {
-    let s = pence_to_pound(pence, sign, brackets);
-    if pence >= 0 {
+
+// OTHER CURRENCIES --------------------------------------------------------------------------------
+// <amount> in <currency>, with its symbol and number of decimal places
+pub fn format_money(amount:Money, currency:&str, sign:bool, brackets:bool) -> ColouredString {
+    let symbol = currency_symbol(currency);
+    let mut temp = ColouredString::from_string(amount.display(currency, sign, brackets).to_string());
+    temp.bodge_alter_len(symbol.len() - symbol.chars().count()); // multi-byte symbols (£, €) are one character wide
+    return temp;
+}
+
+pub fn format_money_colour(amount:Money, currency:&str, sign:bool, brackets:bool) -> ColouredString {
+    let s = format_money(amount, currency, sign, brackets);
+    if !amount.is_negative() {
+        return s.green();
+    } else {
+        return s.red();
+    }
+}
+
+pub fn format_money_colour_bg(amount:Money, currency:&str, sign:bool, brackets:bool) -> ColouredString {
+    let s = format_money(amount, currency, sign, brackets);
+    if !amount.is_negative() {
         return s.on_green().black();
     } else {
         return s.on_red().black();
     }
 }
 
-pub fn pence_to_pound_transfer(pence:i64, sign:bool, brackets:bool) -> ColouredString {
-    pence_to_pound(pence, sign, brackets).yellow()
-}
\ No newline at end of file
+pub fn format_money_transfer(amount:Money, currency:&str, sign:bool, brackets:bool) -> ColouredString {
+    format_money(amount, currency, sign, brackets).yellow()
+}
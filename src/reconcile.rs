@@ -0,0 +1,133 @@
+use std::fmt::{Display, Formatter};
+use chrono::prelude::*;
+use chrono::NaiveDate;
+use inquire::{Confirm, DateSelect, MultiSelect, Select, InquireError};
+
+use crate::{Account, Transaction, format_money_colour};
+use crate::transaction::Status;
+use crate::money::{Money, query_money};
+
+// RECONCILIATION ----------------------------------------------------------------------------------
+// a transaction in the account that hasn't been reconciled yet
+struct Candidate {
+    index: usize, // into the transactions
+    transaction: Transaction,
+    amount: Money, // as it affects the account being reconciled
+    currency: String,
+    sign: bool,
+    brackets: bool,
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}  {}  ref: {}{}{}",
+               self.transaction.date(),
+               format_money_colour(self.amount, &self.currency, self.sign, self.brackets),
+               self.transaction.reference(),
+               if self.transaction.is_transfer() {"  (transfer)"} else {""},
+               if self.transaction.is_pending() {"  (pending)"} else {""})
+    }
+}
+
+enum MismatchOptions {
+    Retick,
+    Stop,
+}
+impl Display for MismatchOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            MismatchOptions::Retick => write!(f, "Change which transactions are ticked"),
+            MismatchOptions::Stop => write!(f, "Stop without reconciling anything"),
+        }
+    }
+}
+
+// the transactions in <account> dated up to <end> that haven't been reconciled, oldest first
+fn unreconciled(transactions: &Vec<Transaction>, account: &Account, end: NaiveDate, sign: bool, brackets: bool)
+    -> Vec<Candidate>
+{
+    let mut candidates: Vec<Candidate> = transactions.iter().enumerate()
+        .filter(|(_, t)| t.date() <= end && !t.is_reconciled())
+        .filter(|(_, t)| t.account_id_1() == account.id || (t.is_transfer() && t.account_id_2() == account.id))
+        .map(|(index, t)| Candidate {
+            index,
+            transaction: t.clone(),
+            amount: t.amount_for(account.id),
+            currency: account.currency.clone(),
+            sign,
+            brackets,
+        })
+        .collect();
+    candidates.sort_by_key(|c| c.transaction.date());
+    return candidates;
+}
+
+// check an account against a bank statement: tick off the transactions on the statement until they
+// account for its closing balance, then mark them reconciled. Returns how many were marked
+pub fn reconcile_from_inputs(transactions: &mut Vec<Transaction>, acc_accounts: &Vec<Account>, sign: bool, brackets: bool)
+    -> Result<usize, InquireError>
+{
+    if acc_accounts.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "No accounts to reconcile".to_string()))}
+    let account = Select::new("Account to reconcile:", acc_accounts.to_vec()).prompt()?;
+    let end = DateSelect::new("Statement end date:")
+        .with_week_start(Weekday::Mon)
+        .with_default(Utc::now().date_naive())
+        .prompt()?;
+    let statement = query_money("Statement closing balance:", "As printed on the statement, negative if overdrawn",
+                                &account.currency, None, false)?;
+    let money = |m: Money| format_money_colour(m, &account.currency, sign, brackets);
+
+    // everything reconciled before is already known to agree with the bank
+    let reconciled = account.reconciled_balance_on(transactions, end);
+    let candidates = unreconciled(transactions, &account, end, sign, brackets);
+    if candidates.is_empty() {
+        if reconciled == statement {
+            println!("Nothing left to reconcile, {} already agrees with the statement.", account.name);
+            return Ok(0);
+        }
+        return Err(InquireError::InvalidConfiguration(format!(
+            "No unreconciled transactions up to {}, but the reconciled balance {} is {} away from the statement",
+            end, money(reconciled), money(statement - reconciled))));
+    }
+
+    let mut ticked: Vec<usize> = Vec::new(); // positions in candidates
+    loop {
+        let chosen = MultiSelect::new("Tick the transactions that are on the statement:", candidates.iter().collect())
+            .with_default(&ticked)
+            .with_help_message("Space to tick, Enter when done, Esc to abort")
+            .prompt()?;
+        ticked = chosen.iter()
+            .map(|c| candidates.iter().position(|x| x.index == c.index).unwrap())
+            .collect();
+        let ticked_total: Money = chosen.iter().map(|c| c.amount).sum();
+        let difference = statement - (reconciled + ticked_total);
+        println!("Previously reconciled: {}", money(reconciled));
+        println!("Ticked:                {} in {} transaction(s)", money(ticked_total), chosen.len());
+        println!("Statement balance:     {}", money(statement));
+        println!("Difference:            {}", money(difference));
+
+        if difference.is_zero() {
+            if chosen.is_empty() {
+                println!("{} already agrees with the statement.", account.name);
+                return Ok(0);
+            }
+            if Confirm::new(&format!("Mark {} transaction(s) as reconciled?", chosen.len()))
+                .with_default(true)
+                .prompt()? {
+                for c in chosen.iter() {
+                    transactions[c.index] = transactions[c.index].with_status(Status::Reconciled);
+                }
+                return Ok(chosen.len());
+            }
+        } else {
+            match Select::new("The ticked transactions don't match the statement yet:", vec!(
+                MismatchOptions::Retick,
+                MismatchOptions::Stop,
+            )).prompt()? {
+                MismatchOptions::Retick => {},
+                MismatchOptions::Stop => return Ok(0),
+            }
+        }
+    }
+}
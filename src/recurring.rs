@@ -0,0 +1,305 @@
+use std::fmt::{Display, Formatter};
+use serde::{Serialize, Deserialize};
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDate};
+use inquire::{DateSelect, Select, CustomType, Confirm, InquireError};
+
+use crate::{Account, Transaction, format_money_colour, format_money_transfer};
+use crate::currency::currency_of;
+use crate::category_rules::CategoryRule;
+use crate::transaction::{new_transaction_from_inputs_on, new_transfer_from_inputs_on, get_modified_transaction};
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
+
+// RECURRING RULE ----------------------------------------------------------------------------------
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Frequency {
+    Weekly,
+    MonthlyOnDay(u32), // day of the month, clamped to the length of shorter months
+    LastWorkingDay,    // last Mon-Fri of each month
+    YearlyOnDay(u32),  // day of the month of the first occurrence, so that 29 Feb comes back in leap years
+}
+
+impl Display for Frequency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            Frequency::Weekly => write!(f, "Weekly"),
+            Frequency::MonthlyOnDay(d) => write!(f, "Monthly on day {}", d),
+            Frequency::LastWorkingDay => write!(f, "Last working day of each month"),
+            Frequency::YearlyOnDay(_) => write!(f, "Yearly"),
+        }
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let first_of_next = match month {
+        12 => NaiveDate::from_ymd_opt(year+1, 1, 1).unwrap(),
+        m => NaiveDate::from_ymd_opt(year, m+1, 1).unwrap(),
+    };
+    return (first_of_next - Duration::days(1)).day();
+}
+
+fn nth_of_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    return NaiveDate::from_ymd_opt(year, month, day.clamp(1, days_in_month(year, month))).unwrap();
+}
+
+fn last_working_day_of_month(year: i32, month: u32) -> NaiveDate {
+    let mut d = nth_of_month(year, month, 31);
+    while d.weekday() == Weekday::Sat || d.weekday() == Weekday::Sun {
+        d = d - Duration::days(1);
+    }
+    return d;
+}
+
+fn next_month(year: i32, month: u32) -> (i32, u32) {
+    return match month {
+        12 => (year+1, 1),
+        m => (year, m+1),
+    }
+}
+
+impl Frequency {
+    // first occurrence on or after <date>
+    pub fn first_on_or_after(&self, date: NaiveDate) -> NaiveDate {
+        match *self {
+            Frequency::Weekly | Frequency::YearlyOnDay(_) => date,
+            Frequency::MonthlyOnDay(n) => {
+                let d = nth_of_month(date.year(), date.month(), n);
+                if d >= date { d } else {
+                    let (y, m) = next_month(date.year(), date.month());
+                    nth_of_month(y, m, n)
+                }
+            },
+            Frequency::LastWorkingDay => {
+                let d = last_working_day_of_month(date.year(), date.month());
+                if d >= date { d } else {
+                    let (y, m) = next_month(date.year(), date.month());
+                    last_working_day_of_month(y, m)
+                }
+            },
+        }
+    }
+    // occurrence following the one on <date>
+    pub fn next_after(&self, date: NaiveDate) -> NaiveDate {
+        match *self {
+            Frequency::Weekly => date + Duration::days(7),
+            Frequency::MonthlyOnDay(n) => {
+                let (y, m) = next_month(date.year(), date.month());
+                nth_of_month(y, m, n)
+            },
+            Frequency::LastWorkingDay => {
+                let (y, m) = next_month(date.year(), date.month());
+                last_working_day_of_month(y, m)
+            },
+            Frequency::YearlyOnDay(n) => nth_of_month(date.year()+1, date.month(), n),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecurringRule {
+    pub id: u32,
+    pub template: Transaction, // copied on each occurrence, with the date replaced
+    pub frequency: Frequency,
+    #[serde(with="crate::transaction::test_date_format")]
+    pub next_due: NaiveDate,
+    pub paused: bool,
+}
+
+impl Display for RecurringRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}{} ({}), next due {}",
+               if self.paused { "[PAUSED] " } else { "" },
+               match self.template.reference().as_str() { "" => "(unnamed)", r => r },
+               self.frequency,
+               self.next_due)
+    }
+}
+
+impl RecurringRule {
+    // every occurrence due up to and including <up_to>, advancing next_due past them
+    pub fn materialise(&mut self, up_to: NaiveDate) -> Vec<Transaction> {
+        let mut due = Vec::new();
+        if self.paused { return due }
+        while self.next_due <= up_to {
+            due.push(self.template.with_date(self.next_due));
+            self.next_due = self.frequency.next_after(self.next_due);
+        }
+        return due;
+    }
+}
+
+pub fn materialise_all(rules: &mut Vec<RecurringRule>, up_to: NaiveDate) -> Vec<Transaction> {
+    let mut due = Vec::new();
+    for r in rules.iter_mut() {
+        due.append(&mut r.materialise(up_to));
+    }
+    return due;
+}
+
+
+// RECURRING FILE I/O ------------------------------------------------------------------------------
+pub fn get_recurring_from_file(filepath:&str) -> Result<Vec<RecurringRule>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
+}
+
+macro_rules! srfe {
+    ($e:ident) => {
+        return Err(format!("Error saving recurring transactions to file: {}", $e))
+    }
+}
+
+pub fn save_recurring_to_file(filepath:&str, rules:Vec<RecurringRule>) -> Result<(), String> {
+    // Serialize to JSON
+    match serde_json::to_string(&rules) {
+        Ok(s) => {
+            // save to file
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
+                Err(e) => srfe!(e)
+            }
+        },
+        Err(e) => srfe!(e)
+    }
+    return Ok(());
+}
+
+
+// RECURRING FUNCTIONS -----------------------------------------------------------------------------
+pub fn print_recurring(rules: &Vec<RecurringRule>, accounts: &Vec<Account>, signed_with_colour: bool, brackets_when_neg: bool) {
+    if rules.is_empty() {
+        println!("No recurring transactions set up yet.");
+        return;
+    }
+    for r in rules.iter() {
+        let currency = currency_of(accounts, r.template.account_id_1());
+        let amount = match r.template.is_transfer() {
+            true => format_money_transfer(r.template.amount(), &currency, signed_with_colour, brackets_when_neg),
+            false => format_money_colour(r.template.amount(), &currency, signed_with_colour, brackets_when_neg),
+        };
+        println!("- {} {}", amount, r);
+    }
+}
+
+fn query_frequency(first: NaiveDate) -> Result<Frequency, InquireError> {
+    let options = vec!(
+        Frequency::MonthlyOnDay(first.day()),
+        Frequency::LastWorkingDay,
+        Frequency::Weekly,
+        Frequency::YearlyOnDay(first.day()),
+    );
+    match Select::new("Repeat:", options).prompt() {
+        Ok(Frequency::MonthlyOnDay(_)) => {
+            match CustomType::<u32>::new("Day of the month:")
+                .with_default((first.day(), &|d| format!("{}", d)))
+                .with_error_message("Please type a whole number between 1 and 31")
+                .with_help_message("Months shorter than this use their last day instead")
+                .prompt() {
+                Ok(d) => return Ok(Frequency::MonthlyOnDay(d.clamp(1, 31))),
+                Err(e) => return Err(e),
+            }
+        },
+        Ok(f) => return Ok(f),
+        Err(e) => return Err(e),
+    }
+}
+
+fn query_first_occurrence() -> Result<NaiveDate, InquireError> {
+    return DateSelect::new("Date of the first occurrence:")
+        .with_week_start(Weekday::Mon)
+        .with_help_message("Occurrences up to today are added to the transactions straight away")
+        .prompt();
+}
+
+pub fn new_recurring_from_inputs(
+    existing_rules: &Vec<RecurringRule>,
+    acc_accounts: &Vec<Account>,
+    cat_accounts: &Vec<Account>,
+    rules: &Vec<CategoryRule>,
+) -> Result<RecurringRule, InquireError> {
+    // find new largest id
+    let mut id = 0;
+    for r in existing_rules.iter() {
+        if r.id >= id { id = r.id + 1; }
+    }
+
+    let transfer = match Select::new("Recurring:", vec!("Transaction", "Transfer")).prompt() {
+        Ok(choice) => choice == "Transfer",
+        Err(e) => return Err(e),
+    };
+    let first = query_first_occurrence()?;
+    let template = match transfer {
+        true => new_transfer_from_inputs_on(acc_accounts, Some(first))?,
+        false => new_transaction_from_inputs_on(acc_accounts, cat_accounts, rules, Some(first))?,
+    };
+    let frequency = query_frequency(first)?;
+
+    return Ok(RecurringRule {
+        id,
+        template,
+        frequency,
+        next_due: frequency.first_on_or_after(first),
+        paused: false,
+    });
+}
+
+pub fn modify_recurring_from_inputs(
+    rule: &RecurringRule,
+    acc_accounts: &Vec<Account>,
+    cat_accounts: &Vec<Account>,
+) -> Result<RecurringRule, InquireError> {
+    let mut new_rule = rule.clone();
+    let modify_help_message = "Press Enter to skip, type \"y\" then press Enter to change";
+
+    if Confirm::new("Modify the transaction itself?")
+        .with_default(false)
+        .with_help_message(modify_help_message)
+        .prompt()? {
+        new_rule.template = get_modified_transaction(&rule.template, acc_accounts, cat_accounts)?;
+    }
+    if Confirm::new(format!("Modify the schedule? (Currently \"{}\", next due {})", rule.frequency, rule.next_due).as_str())
+        .with_default(false)
+        .with_help_message(modify_help_message)
+        .prompt()? {
+        let first = query_first_occurrence()?;
+        new_rule.frequency = query_frequency(first)?;
+        new_rule.next_due = new_rule.frequency.first_on_or_after(first);
+    }
+    return Ok(new_rule);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate { NaiveDate::from_ymd_opt(y, m, d).unwrap() }
+
+    #[test]
+    fn monthly_clamps_to_short_months_and_comes_back() {
+        let f = Frequency::MonthlyOnDay(31);
+        assert_eq!(f.next_after(date(2023, 1, 31)), date(2023, 2, 28));
+        assert_eq!(f.next_after(date(2023, 2, 28)), date(2023, 3, 31));
+        assert_eq!(f.first_on_or_after(date(2023, 4, 10)), date(2023, 4, 30));
+    }
+
+    #[test]
+    fn yearly_on_29_feb_returns_in_leap_years() {
+        let f = Frequency::YearlyOnDay(29);
+        let mut d = date(2024, 2, 29);
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            d = f.next_after(d);
+            seen.push(d);
+        }
+        assert_eq!(seen, vec!(date(2025, 2, 28), date(2026, 2, 28), date(2027, 2, 28), date(2028, 2, 29)));
+    }
+
+    #[test]
+    fn last_working_day_skips_the_weekend() {
+        // 30 Sep 2023 was a Saturday
+        assert_eq!(Frequency::LastWorkingDay.next_after(date(2023, 8, 31)), date(2023, 9, 29));
+    }
+}
@@ -1,39 +1,186 @@
-use std::io::Write;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use serde::{Serialize, Deserialize};
+use colored::Colorize;
+use crate::backup::write_atomically;
+use crate::currency::DEFAULT_CURRENCY;
 
-macro_rules! sfe {
-    ($e:ident) => {
-        println!("Error saving settings to file: {}", $e)
+// bump whenever the meaning of a field changes, so that older files can be migrated on load
+pub const SETTINGS_VERSION: u32 = 1;
+
+// bounds applied to the user-adjustable settings (both in the settings menu and when loading)
+pub const WEEKS_MIN: i64 = 1;
+pub const WEEKS_MAX: i64 = 6;
+pub const MAX_TRANSACTIONS_PER_DAY_MIN: usize = 1;
+pub const MAX_TRANSACTIONS_PER_DAY_MAX: usize = 16;
+pub const DAY_WIDTH_MIN: usize = 10;
+pub const DAY_WIDTH_MAX: usize = 30;
+pub const INFO_WIDTH_MIN: usize = 10;
+pub const INFO_WIDTH_MAX: usize = 80;
+pub const FORECAST_WEEKS_MIN: i64 = 1;
+pub const FORECAST_WEEKS_MAX: i64 = 52;
+pub const DUPLICATE_WINDOW_DAYS_MIN: i64 = 0;
+pub const DUPLICATE_WINDOW_DAYS_MAX: i64 = 14;
+
+
+// how balances on future days in the calendar are projected
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum ForecastMethod {
+    Off,
+    Recurring, // from the recurring transaction rules
+    Average,   // from the average spend per category over the last few weeks
+}
+
+impl Display for ForecastMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            ForecastMethod::Off => write!(f, "off"),
+            ForecastMethod::Recurring => write!(f, "recurring transactions"),
+            ForecastMethod::Average => write!(f, "average spend"),
+        }
+    }
+}
+
+
+// SETTINGS ----------------------------------------------------------------------------------------
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)] // any missing field is filled in from Settings::default()
+pub struct Settings {
+    pub version: u32,
+    // general
+    pub signed_with_colour: bool,
+    pub brackets_when_neg: bool,
+    // text cal params
+    pub weeks: i64,
+    pub max_transactions_per_day: usize,
+    pub day_width: usize,
+    pub info_width: usize,
+    // forecasting
+    pub forecast_method: ForecastMethod,
+    pub forecast_weeks: i64, // number of past weeks averaged over
+    // duplicate detection
+    pub duplicate_window_days: i64, // +/- days for dates to count as a likely duplicate
+    // currencies
+    pub base_currency: String, // totals across accounts, closing balances and graphs are converted into this
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        return Settings {
+            version: SETTINGS_VERSION,
+            signed_with_colour: false,
+            brackets_when_neg: false,
+            weeks: 4,
+            max_transactions_per_day: 6,
+            day_width: 16,
+            info_width: 28,
+            forecast_method: ForecastMethod::Recurring,
+            forecast_weeks: 8,
+            duplicate_window_days: 3,
+            base_currency: DEFAULT_CURRENCY.to_string(),
+        }
     }
 }
 
-pub fn save_settings_to_file(filepath:&str,
-                             signed_with_colour:bool,
-                             brackets_when_neg:bool,
-                             weeks:u64,
-                             max_transactions_per_day:u64,
-                             day_width:u64,
-                             info_width:u64,
-) {
-    // Serialize
-    let s = format!("{},{},{},{},{},{}",
-                    signed_with_colour,
-                    brackets_when_neg,
-                    weeks,
-                    max_transactions_per_day,
-                    day_width,
-                    info_width
-    );
-
-    // Save file
-    // save to file
-    match std::fs::File::create(filepath) {
+impl Settings {
+    // maximum width the program will occupy in the terminal
+    pub fn width(&self) -> usize {
+        return 1+(self.day_width+1)*7+1+self.info_width;
+    }
+    // force every value back inside the bounds above (e.g. after a hand-edited file is loaded)
+    pub fn clamp(&mut self) {
+        self.weeks = self.weeks.clamp(WEEKS_MIN, WEEKS_MAX);
+        self.max_transactions_per_day = self.max_transactions_per_day.clamp(
+            MAX_TRANSACTIONS_PER_DAY_MIN, MAX_TRANSACTIONS_PER_DAY_MAX);
+        self.day_width = self.day_width.clamp(DAY_WIDTH_MIN, DAY_WIDTH_MAX);
+        self.info_width = self.info_width.clamp(INFO_WIDTH_MIN, INFO_WIDTH_MAX);
+        self.forecast_weeks = self.forecast_weeks.clamp(FORECAST_WEEKS_MIN, FORECAST_WEEKS_MAX);
+        self.duplicate_window_days = self.duplicate_window_days.clamp(
+            DUPLICATE_WINDOW_DAYS_MIN, DUPLICATE_WINDOW_DAYS_MAX);
+        if self.base_currency.trim().is_empty() { self.base_currency = DEFAULT_CURRENCY.to_string(); }
+    }
+}
+
+
+// SETTINGS FILE I/O -------------------------------------------------------------------------------
+macro_rules! osfe {
+    ($e:expr) => {
+        println!("{}: {}\nUsing default settings.","Error opening settings file".to_string().red().bold(), $e)
+    }
+}
+
+pub fn load_settings_from_file(filepath:&str, legacy_csv_filepath:&str) -> Settings {
+    let mut settings = match std::fs::File::open(filepath) {
         Ok(f) => {
             let mut file = f;
-            match file.write_all(s.as_bytes()) {
+            let mut s = String::new();
+            match file.read_to_string(&mut s) {
+                Ok(_) => {
+                    match serde_json::from_str::<Settings>(&s) {
+                        Ok(v) => {
+                            if v.version > SETTINGS_VERSION {
+                                osfe!(format!("file version {} is newer than this program supports ({})",
+                                              v.version, SETTINGS_VERSION));
+                                Settings::default()
+                            } else {
+                                v
+                            }
+                        },
+                        Err(e) => {osfe!(e); Settings::default()}
+                    }
+                },
+                Err(e) => {osfe!(e); Settings::default()}
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // first run since the move away from the csv file? -> migrate silently, otherwise defaults
+            match load_legacy_csv_settings(legacy_csv_filepath) {
+                Some(v) => v,
+                None => Settings::default(),
+            }
+        },
+        Err(e) => {osfe!(e); Settings::default()}
+    };
+    settings.version = SETTINGS_VERSION;
+    settings.clamp();
+    return settings;
+}
+
+// settings used to be stored positionally as:
+// signed_with_colour,brackets_when_neg,weeks,max_transactions_per_day,day_width,info_width
+fn load_legacy_csv_settings(filepath:&str) -> Option<Settings> {
+    let s = std::fs::read_to_string(filepath).ok()?;
+    let fields: Vec<&str> = s.trim().split(',').collect();
+    if fields.len() != 6 { return None }
+    return Some(Settings {
+        version: SETTINGS_VERSION,
+        signed_with_colour: fields[0].parse().ok()?,
+        brackets_when_neg: fields[1].parse().ok()?,
+        weeks: fields[2].parse().ok()?,
+        max_transactions_per_day: fields[3].parse().ok()?,
+        day_width: fields[4].parse().ok()?,
+        info_width: fields[5].parse().ok()?,
+        ..Settings::default()
+    });
+}
+
+macro_rules! sfe {
+    ($e:ident) => {
+        return Err(format!("Error saving settings to file: {}", $e))
+    }
+}
+
+pub fn save_settings_to_file(filepath:&str, settings:&Settings) -> Result<(), String> {
+    // Serialize to JSON
+    match serde_json::to_string_pretty(settings) {
+        Ok(s) => {
+            // save to file
+            match write_atomically(filepath, s.as_bytes()) {
                 Ok(_) => {}, // success & exit function without complaining
                 Err(e) => sfe!(e)
             }
         },
         Err(e) => sfe!(e)
     }
-}
\ No newline at end of file
+    return Ok(());
+}
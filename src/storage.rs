@@ -0,0 +1,358 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use chrono::NaiveDate;
+use rusqlite::{Connection, params};
+
+use crate::{Account, Transaction};
+use crate::account::{get_accounts_from_file, save_accounts_to_file};
+use crate::transaction::{get_transactions_from_file, save_transactions_to_file};
+use crate::load_error::{LoadError, empty_if_missing};
+use crate::data_dir::DataPaths;
+use crate::crypto::read_header;
+
+// STORAGE -----------------------------------------------------------------------------------------
+// where a profile's accounts and transactions live. Everything else (budgets, rules, ...) is always
+// kept in JSON files. Transactions have no id, so they are matched on their whole contents
+pub trait Storage {
+    fn backend(&self) -> Backend;
+    fn accounts_location(&self) -> String;
+    fn transactions_location(&self) -> String;
+
+    fn load_accounts(&self) -> Result<Vec<Account>, LoadError>;
+    fn save_accounts(&mut self, accounts: &Vec<Account>) -> Result<(), String>;
+    fn insert_account(&mut self, account: &Account) -> Result<(), String>;
+    fn update_account(&mut self, account: &Account) -> Result<(), String>; // the account with the same id
+    fn delete_account(&mut self, id: u32) -> Result<(), String>;
+
+    fn load_transactions(&self) -> Result<Vec<Transaction>, LoadError>;
+    fn save_transactions(&mut self, transactions: &Vec<Transaction>) -> Result<(), String>;
+    fn insert_transaction(&mut self, transaction: &Transaction) -> Result<(), String>;
+    fn update_transaction(&mut self, old: &Transaction, new: &Transaction) -> Result<(), String>;
+    fn delete_transaction(&mut self, transaction: &Transaction) -> Result<(), String>;
+
+    // transactions dated in [from, to] (either end open when None) involving any of <account_ids> (all
+    // accounts when empty), in the order they were added
+    fn transactions_between(&self, from: Option<NaiveDate>, to: Option<NaiveDate>, account_ids: &[u32])
+        -> Result<Vec<Transaction>, LoadError>;
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            Backend::Json => write!(f, "JSON files"),
+            Backend::Sqlite => write!(f, "SQLite database"),
+        }
+    }
+}
+
+// the database is used if the profile has one, otherwise the JSON files
+pub fn open_storage(paths: &DataPaths) -> Result<Box<dyn Storage>, String> {
+    if Path::new(&paths.ledger_db).exists() {
+        return Ok(Box::new(SqliteStorage::open(&paths.ledger_db)?));
+    }
+    return Ok(Box::new(JsonStorage::new(paths)));
+}
+
+fn in_range(t: &Transaction, from: Option<NaiveDate>, to: Option<NaiveDate>, account_ids: &[u32]) -> bool {
+    return from.map_or(true, |d| t.date() >= d)
+        && to.map_or(true, |d| t.date() <= d)
+        && (account_ids.is_empty() || account_ids.contains(&t.account_id_1()) || account_ids.contains(&t.account_id_2()));
+}
+
+
+// JSON FILES --------------------------------------------------------------------------------------
+// every change rewrites the whole file, as it always has
+pub struct JsonStorage {
+    accounts_filepath: String,
+    transactions_filepath: String,
+}
+
+impl JsonStorage {
+    pub fn new(paths: &DataPaths) -> JsonStorage {
+        return JsonStorage {
+            accounts_filepath: paths.accounts.clone(),
+            transactions_filepath: paths.transactions.clone(),
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn backend(&self) -> Backend { return Backend::Json }
+    fn accounts_location(&self) -> String { return self.accounts_filepath.clone() }
+    fn transactions_location(&self) -> String { return self.transactions_filepath.clone() }
+
+    fn load_accounts(&self) -> Result<Vec<Account>, LoadError> {
+        return get_accounts_from_file(&self.accounts_filepath);
+    }
+    fn save_accounts(&mut self, accounts: &Vec<Account>) -> Result<(), String> {
+        return save_accounts_to_file(&self.accounts_filepath, accounts.to_vec());
+    }
+    fn insert_account(&mut self, account: &Account) -> Result<(), String> {
+        let mut accounts = empty_if_missing(self.load_accounts()).map_err(|e| e.to_string())?;
+        accounts.push(account.clone());
+        return self.save_accounts(&accounts);
+    }
+    fn update_account(&mut self, account: &Account) -> Result<(), String> {
+        let mut accounts = self.load_accounts().map_err(|e| e.to_string())?;
+        for a in accounts.iter_mut() {
+            if a.id == account.id { *a = account.clone() }
+        }
+        return self.save_accounts(&accounts);
+    }
+    fn delete_account(&mut self, id: u32) -> Result<(), String> {
+        let mut accounts = self.load_accounts().map_err(|e| e.to_string())?;
+        accounts.retain(|a| a.id != id);
+        return self.save_accounts(&accounts);
+    }
+
+    fn load_transactions(&self) -> Result<Vec<Transaction>, LoadError> {
+        return get_transactions_from_file(&self.transactions_filepath);
+    }
+    fn save_transactions(&mut self, transactions: &Vec<Transaction>) -> Result<(), String> {
+        return save_transactions_to_file(&self.transactions_filepath, transactions.to_vec());
+    }
+    fn insert_transaction(&mut self, transaction: &Transaction) -> Result<(), String> {
+        let mut transactions = empty_if_missing(self.load_transactions()).map_err(|e| e.to_string())?;
+        transactions.push(transaction.clone());
+        return self.save_transactions(&transactions);
+    }
+    fn update_transaction(&mut self, old: &Transaction, new: &Transaction) -> Result<(), String> {
+        let mut transactions = self.load_transactions().map_err(|e| e.to_string())?;
+        for t in transactions.iter_mut() {
+            if *t == *old { *t = new.clone() }
+        }
+        return self.save_transactions(&transactions);
+    }
+    fn delete_transaction(&mut self, transaction: &Transaction) -> Result<(), String> {
+        let mut transactions = self.load_transactions().map_err(|e| e.to_string())?;
+        transactions.retain(|t| *t != *transaction);
+        return self.save_transactions(&transactions);
+    }
+
+    fn transactions_between(&self, from: Option<NaiveDate>, to: Option<NaiveDate>, account_ids: &[u32])
+        -> Result<Vec<Transaction>, LoadError>
+    {
+        let mut transactions = self.load_transactions()?;
+        transactions.retain(|t| in_range(t, from, to, account_ids));
+        return Ok(transactions);
+    }
+}
+
+
+// SQLITE ------------------------------------------------------------------------------------------
+// each row keeps the whole record as JSON (so new fields need no schema change) alongside the columns
+// that are queried on. Saving only writes the rows that changed
+pub struct SqliteStorage {
+    filepath: String,
+    conn: Connection,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        id INTEGER PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        date TEXT NOT NULL,
+        account_id_1 INTEGER NOT NULL,
+        account_id_2 INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS transactions_date ON transactions (date);
+    CREATE INDEX IF NOT EXISTS transactions_data ON transactions (data);
+";
+
+impl SqliteStorage {
+    pub fn open(filepath: &str) -> Result<SqliteStorage, String> {
+        let conn = Connection::open(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+        conn.execute_batch(SCHEMA).map_err(|e| format!("{}: {}", filepath, e))?;
+        return Ok(SqliteStorage { filepath: filepath.to_string(), conn });
+    }
+
+    // run <f> inside a database transaction, so that a save is all or nothing
+    fn atomically<F>(&mut self, f: F) -> Result<(), String>
+        where F: FnOnce(&mut SqliteStorage) -> Result<(), String>
+    {
+        self.conn.execute_batch("BEGIN").map_err(|e| e.to_string())?;
+        match f(self) {
+            Ok(_) => return self.conn.execute_batch("COMMIT").map_err(|e| e.to_string()),
+            Err(e) => {
+                let _ = self.conn.execute_batch("ROLLBACK");
+                return Err(e);
+            }
+        }
+    }
+
+    fn query_transactions(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<Transaction>, LoadError> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| row.get::<_, String>(0))?;
+        let mut transactions = Vec::new();
+        for data in rows {
+            transactions.push(serde_json::from_str(&data?)?);
+        }
+        return Ok(transactions);
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+    return serde_json::to_string(value).map_err(|e| e.to_string());
+}
+
+impl Storage for SqliteStorage {
+    fn backend(&self) -> Backend { return Backend::Sqlite }
+    fn accounts_location(&self) -> String { return self.filepath.clone() }
+    fn transactions_location(&self) -> String { return self.filepath.clone() }
+
+    fn load_accounts(&self) -> Result<Vec<Account>, LoadError> {
+        let mut stmt = self.conn.prepare("SELECT data FROM accounts ORDER BY id")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut accounts = Vec::new();
+        for data in rows {
+            accounts.push(serde_json::from_str(&data?)?);
+        }
+        return Ok(accounts);
+    }
+    fn save_accounts(&mut self, accounts: &Vec<Account>) -> Result<(), String> {
+        let existing = self.load_accounts().map_err(|e| e.to_string())?;
+        return self.atomically(|s| {
+            for e in existing.iter() {
+                if !accounts.iter().any(|a| a.id == e.id) { s.delete_account(e.id)? }
+            }
+            for a in accounts.iter() {
+                match existing.iter().find(|e| e.id == a.id) {
+                    Some(e) if to_json(e)? == to_json(a)? => {},
+                    Some(_) => s.update_account(a)?,
+                    None => s.insert_account(a)?,
+                }
+            }
+            Ok(())
+        });
+    }
+    fn insert_account(&mut self, account: &Account) -> Result<(), String> {
+        self.conn.execute("INSERT INTO accounts (id, data) VALUES (?1, ?2)", params![account.id, to_json(account)?])
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+    fn update_account(&mut self, account: &Account) -> Result<(), String> {
+        self.conn.execute("UPDATE accounts SET data = ?2 WHERE id = ?1", params![account.id, to_json(account)?])
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+    fn delete_account(&mut self, id: u32) -> Result<(), String> {
+        self.conn.execute("DELETE FROM accounts WHERE id = ?1", params![id]).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    fn load_transactions(&self) -> Result<Vec<Transaction>, LoadError> {
+        return self.query_transactions("SELECT data FROM transactions ORDER BY id", &[]);
+    }
+    // compare against what is stored and only write the difference, a modified transaction shows up as one
+    // removed and one added, which are paired up into an update
+    fn save_transactions(&mut self, transactions: &Vec<Transaction>) -> Result<(), String> {
+        let mut removed = self.load_transactions().map_err(|e| e.to_string())?;
+        let mut added = Vec::new();
+        for t in transactions.iter() {
+            match removed.iter().position(|r| r == t) {
+                Some(i) => { removed.remove(i); },
+                None => added.push(t.clone()),
+            }
+        }
+        return self.atomically(|s| {
+            let paired = removed.len().min(added.len());
+            for i in 0..paired { s.update_transaction(&removed[i], &added[i])? }
+            for t in removed[paired..].iter() { s.delete_transaction(t)? }
+            for t in added[paired..].iter() { s.insert_transaction(t)? }
+            Ok(())
+        });
+    }
+    fn insert_transaction(&mut self, transaction: &Transaction) -> Result<(), String> {
+        self.conn.execute(
+            "INSERT INTO transactions (date, account_id_1, account_id_2, data) VALUES (?1, ?2, ?3, ?4)",
+            params![transaction.date().to_string(), transaction.account_id_1(), transaction.account_id_2(), to_json(transaction)?],
+        ).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+    fn update_transaction(&mut self, old: &Transaction, new: &Transaction) -> Result<(), String> {
+        self.conn.execute(
+            "UPDATE transactions SET date = ?1, account_id_1 = ?2, account_id_2 = ?3, data = ?4
+             WHERE id = (SELECT id FROM transactions WHERE data = ?5 LIMIT 1)",
+            params![new.date().to_string(), new.account_id_1(), new.account_id_2(), to_json(new)?, to_json(old)?],
+        ).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+    fn delete_transaction(&mut self, transaction: &Transaction) -> Result<(), String> {
+        self.conn.execute(
+            "DELETE FROM transactions WHERE id = (SELECT id FROM transactions WHERE data = ?1 LIMIT 1)",
+            params![to_json(transaction)?],
+        ).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    fn transactions_between(&self, from: Option<NaiveDate>, to: Option<NaiveDate>, account_ids: &[u32])
+        -> Result<Vec<Transaction>, LoadError>
+    {
+        // dates are stored as YYYY-MM-DD, so compare as text
+        let from = from.map_or("0000-01-01".to_string(), |d| d.to_string());
+        let to = to.map_or("9999-12-31".to_string(), |d| d.to_string());
+        if account_ids.is_empty() {
+            return self.query_transactions(
+                "SELECT data FROM transactions WHERE date BETWEEN ?1 AND ?2 ORDER BY id",
+                &[&from, &to]);
+        }
+        // ids are numbers, so are safe to put in the query directly
+        let ids: Vec<String> = account_ids.iter().map(|id| id.to_string()).collect();
+        let sql = format!(
+            "SELECT data FROM transactions WHERE date BETWEEN ?1 AND ?2
+             AND (account_id_1 IN ({ids}) OR account_id_2 IN ({ids})) ORDER BY id",
+            ids = ids.join(","));
+        return self.query_transactions(&sql, &[&from, &to]);
+    }
+}
+
+
+// MIGRATION ---------------------------------------------------------------------------------------
+// copy a profile's accounts and transactions into the other backend. The new store is built under a
+// temporary name and renamed into place, and the old files are kept with a .migrated suffix
+pub fn migrate_storage(paths: &DataPaths, to: Backend) -> Result<(usize, usize), String> {
+    let from = open_storage(paths)?;
+    if from.backend() == to { return Err(format!("Profile already uses {}", to)) }
+    let accounts = empty_if_missing(from.load_accounts()).map_err(|e| format!("{}: {}", from.accounts_location(), e))?;
+    let transactions = empty_if_missing(from.load_transactions()).map_err(|e| format!("{}: {}", from.transactions_location(), e))?;
+    drop(from);
+
+    match to {
+        Backend::Sqlite => {
+            // the database is not covered by the encryption of data files
+            if read_header(&paths.encryption)?.is_some() {
+                return Err("Encrypted profiles can only use JSON files".to_string());
+            }
+            let tmp = format!("{}.tmp", paths.ledger_db);
+            let _ = std::fs::remove_file(&tmp); // left over from an interrupted migration
+            {
+                let mut db = SqliteStorage::open(&tmp)?;
+                db.save_accounts(&accounts)?;
+                db.save_transactions(&transactions)?;
+            }
+            std::fs::rename(&tmp, &paths.ledger_db).map_err(|e| e.to_string())?;
+            for filepath in [&paths.accounts, &paths.transactions] {
+                if Path::new(filepath).exists() {
+                    std::fs::rename(filepath, format!("{}.migrated", filepath)).map_err(|e| e.to_string())?;
+                }
+            }
+        },
+        Backend::Json => {
+            let mut json = JsonStorage::new(paths);
+            json.save_accounts(&accounts)?;
+            json.save_transactions(&transactions)?;
+            std::fs::rename(&paths.ledger_db, format!("{}.migrated", paths.ledger_db)).map_err(|e| e.to_string())?;
+        },
+    }
+    return Ok((accounts.len(), transactions.len()));
+}
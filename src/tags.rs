@@ -0,0 +1,69 @@
+use crate::{Transaction, format_money, format_money_colour_bg};
+use crate::money::Money;
+
+
+// TAGS --------------------------------------------------------------------------------------------
+// free-form labels that cut across categories, e.g. "holiday-2026" or "reimbursable"
+
+// comma separated tags, trimmed, with empty and repeated ones dropped
+pub fn parse_tags(s: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for t in s.split(',').map(|t| t.trim()) {
+        if !t.is_empty() && !tags.iter().any(|x| x == t) {
+            tags.push(t.to_string());
+        }
+    }
+    return tags;
+}
+
+// every tag used by any transaction, sorted
+pub fn all_tags(transactions: &Vec<Transaction>) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for t in transactions.iter() {
+        for tag in t.tags().into_iter() {
+            if !tags.contains(&tag) { tags.push(tag) }
+        }
+    }
+    tags.sort();
+    return tags;
+}
+
+// transactions with any of the given tags, all of them when none are given
+pub fn filter_by_tags(transactions: &Vec<Transaction>, tags: &Vec<String>) -> Vec<Transaction> {
+    return transactions.iter().filter(|t| t.matches_tags(tags)).cloned().collect();
+}
+
+
+// TAG REPORT --------------------------------------------------------------------------------------
+// money spent and received per tag, with <transactions> all in <currency>. Transfers only move money
+// between accounts so aren't counted
+pub fn print_tag_report(transactions: &Vec<Transaction>, currency: &str, signed_with_colour: bool, brackets_when_neg: bool) {
+    let tags = all_tags(transactions);
+    if tags.is_empty() {
+        println!("No transactions have been tagged yet.");
+        return;
+    }
+
+    let mut max_len = 0; // find longest tag
+    for tag in tags.iter() {
+        if tag.len() > max_len { max_len = tag.len(); }
+    }
+
+    for tag in tags.iter() {
+        let mut count = 0;
+        let mut spent = Money::ZERO;
+        let mut received = Money::ZERO;
+        for t in transactions.iter().filter(|t| !t.is_transfer() && t.tags().contains(tag)) {
+            count += 1;
+            if t.amount().is_negative() { spent -= t.amount() } else { received += t.amount() }
+        }
+        let mut s = tag.clone();
+        while s.len() < max_len+2 { s.push(' '); }
+        println!("{s}{:>4} transaction(s)  Spent: {}  Received: {}  Net: {}",
+                 count,
+                 format_money(spent, currency, true, brackets_when_neg),
+                 format_money(received, currency, true, brackets_when_neg),
+                 format_money_colour_bg(received - spent, currency, signed_with_colour, brackets_when_neg),
+        );
+    }
+}
@@ -1,31 +1,84 @@
 use serde::{Serialize, Deserialize};
 use chrono::prelude::*;
 use chrono::NaiveDate;
-use std::io::Read;
-use std::io::prelude::*;
-use inquire::{DateSelect, validator::Validation, Select, CustomType, Confirm, Text, InquireError};
-use colored::Colorize;
-use crate::{Account, pence_to_pound};
+use std::fmt::{Display, Formatter};
+use inquire::{DateSelect, Select, Confirm, Text, InquireError};
+use crate::{Account, format_money};
+use crate::money::{Money, query_money};
+use crate::currency::currency_of;
+use crate::account::{TreeEntry, category_tree};
+use crate::tags::parse_tags;
+use crate::category_rules::{CategoryRule, categorise};
+use crate::backup::write_data_file;
+use crate::load_error::{LoadError, read_data_file};
 
 
 // TRANSACTION -------------------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Pending,    // not gone through the account yet, including anything scheduled for a future date
+    #[default]
+    Cleared,    // also what every transaction saved before statuses existed becomes
+    Reconciled, // checked against a bank statement
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match *self {
+            Status::Pending => write!(f, "pending"),
+            Status::Cleared => write!(f, "cleared"),
+            Status::Reconciled => write!(f, "reconciled"),
+        }
+    }
+}
+
+impl Status {
+    fn is_cleared(&self) -> bool { return *self == Status::Cleared }
+}
+
+// a transaction dated after today can only be scheduled, so stays pending until it clears
+pub fn status_for(date: NaiveDate, status: Status) -> Status {
+    if date > Utc::now().date_naive() { return Status::Pending }
+    return status;
+}
+
+// one category line of a split transaction
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct Split {
+    pub category_id: u32,
+    pub amount: Money,
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq)]
 pub struct Transaction {
     account_id_1: u32, // account
     account_id_2: u32, // other account if transfer, category account if not
     transfer: bool, // true when transfer between two account accounts, false then from one account to a category account
-    amount: i64,    // will store any sensible amount of money,
-    // i32 would probably work but not be future proof
+    amount: Money,
+    // transfers between accounts in different currencies only: the amount in account_id_2's currency,
+    // with the same sign as amount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount_2: Option<Money>,
     reference: String,
     #[serde(with="test_date_format")]
     date: NaiveDate,
     notes: String,
+    // when not empty, the amount is split across these categories (which sum to amount), and
+    // account_id_2 is the category of the first line
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    splits: Vec<Split>,
+    // free-form labels across categories, e.g. "holiday-2026" or "reimbursable"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Status::is_cleared")]
+    status: Status,
 }
 impl Transaction {
     pub fn new_transaction(
         account_id: u32,
         category_account_id: u32,
-        amount: i64,
+        amount: Money,
         reference: String,
         date: NaiveDate,
         notes: String
@@ -35,15 +88,41 @@ impl Transaction {
             account_id_2: category_account_id,
             transfer: false,
             amount,
+            amount_2: None,
             reference,
             date,
-            notes
+            notes,
+            splits: Vec::new(),
+            tags: Vec::new(),
+            status: Status::Cleared,
+        }
+    }
+    // <splits> must have at least one line, the first of which stands in as account_id_2
+    pub fn new_split_transaction(
+        account_id: u32,
+        splits: Vec<Split>,
+        reference: String,
+        date: NaiveDate,
+        notes: String
+    ) -> Transaction {
+        return Transaction{
+            account_id_1: account_id,
+            account_id_2: splits.first().expect("split transaction without any lines").category_id,
+            transfer: false,
+            amount: splits.iter().map(|s| s.amount).sum(),
+            amount_2: None,
+            reference,
+            date,
+            notes,
+            splits,
+            tags: Vec::new(),
+            status: Status::Cleared,
         }
     }
     pub fn new_transfer(
         src_account_id: u32,
         dest_account_id: u32,
-        amount: u64,
+        amount: Money, // strictly positive, leaving the source account
         reference: String,
         date: NaiveDate,
         notes: String
@@ -52,10 +131,14 @@ impl Transaction {
             account_id_1: src_account_id,
             account_id_2: dest_account_id,
             transfer: true,
-            amount: -(amount as i64),
+            amount: -amount,
+            amount_2: None,
             reference,
             date,
-            notes
+            notes,
+            splits: Vec::new(),
+            tags: Vec::new(),
+            status: Status::Cleared,
         }
     }
     pub fn account_id_1(&self) -> u32 { return self.account_id_1 }
@@ -73,67 +156,132 @@ impl Transaction {
         return Err("Account not found".to_string())
     }
     pub fn is_transfer(&self) -> bool { return self.transfer }
-    pub fn amount(&self) -> i64 { return self.amount }
+    pub fn amount(&self) -> Money { return self.amount }
+    // amount in account_id_2's currency, the same as amount unless the transfer changes currency
+    pub fn amount_2(&self) -> Money { return self.amount_2.unwrap_or(self.amount) }
+    pub fn changes_currency(&self) -> bool { return self.amount_2.is_some() }
     pub fn reference(&self) -> String { return self.reference.clone() }
     pub fn date(&self) -> NaiveDate { return self.date }
     pub fn notes(&self) -> String { return self.notes.clone() }
+    pub fn is_split(&self) -> bool { return !self.splits.is_empty() }
+    pub fn splits(&self) -> Vec<Split> { return self.splits.clone() }
+    pub fn tags(&self) -> Vec<String> { return self.tags.clone() }
+    pub fn status(&self) -> Status { return self.status }
+    pub fn is_pending(&self) -> bool { return self.status == Status::Pending }
+    pub fn is_reconciled(&self) -> bool { return self.status == Status::Reconciled }
+    // true if the transaction has any of the given tags, or no tags are given
+    pub fn matches_tags(&self, tags: &Vec<String>) -> bool {
+        return tags.is_empty() || self.tags.iter().any(|t| tags.contains(t));
+    }
+    // (category id, amount) for each category this transaction counts towards, none for transfers
+    pub fn category_lines(&self) -> Vec<(u32, Money)> {
+        if self.transfer { return Vec::new() }
+        if self.splits.is_empty() { return vec!((self.account_id_2, self.amount)) }
+        return self.splits.iter().map(|s| (s.category_id, s.amount)).collect();
+    }
+    // amount counted towards the given category
+    pub fn amount_in_category(&self, category_id: u32) -> Money {
+        return self.category_lines().iter().filter(|(c, _)| *c == category_id).map(|(_, a)| a).sum();
+    }
+    // how much this moves the balance of acc account <account_id>, in its currency
+    pub fn amount_for(&self, account_id: u32) -> Money {
+        if self.account_id_1 == account_id { return self.amount }
+        if self.transfer && self.account_id_2 == account_id { return -self.amount_2() }
+        return Money::ZERO;
+    }
+    // true if <id> is the account, the other account of a transfer, or any of the categories
+    pub fn involves(&self, id: u32) -> bool {
+        return self.account_id_1 == id || self.account_id_2 == id || self.splits.iter().any(|s| s.category_id == id);
+    }
+    // the same transaction with account or category <from> replaced by <to>. Split lines that end up in
+    // the same category are combined, and a split left with one line becomes an ordinary transaction
+    pub fn reassigned(&self, from: u32, to: u32) -> Transaction {
+        let mut t = self.clone();
+        if t.account_id_1 == from { t.account_id_1 = to }
+        if t.account_id_2 == from { t.account_id_2 = to }
+        let mut splits: Vec<Split> = Vec::new();
+        for s in self.splits.iter() {
+            let category_id = if s.category_id == from { to } else { s.category_id };
+            match splits.iter_mut().find(|x| x.category_id == category_id) {
+                Some(x) => x.amount += s.amount,
+                None => splits.push(Split { category_id, amount: s.amount }),
+            }
+        }
+        if splits.len() == 1 { splits.clear() }
+        t.splits = splits;
+        return t;
+    }
+    pub fn with_date(&self, date: NaiveDate) -> Transaction {
+        let mut t = self.clone();
+        t.date = date;
+        return t;
+    }
+    pub fn with_notes(&self, notes: String) -> Transaction {
+        let mut t = self.clone();
+        t.notes = notes;
+        return t;
+    }
+    pub fn with_amount_2(&self, amount_2: Money) -> Transaction {
+        let mut t = self.clone();
+        t.amount_2 = Some(amount_2);
+        return t;
+    }
+    // the same transaction with account_id_1's amounts passed through <side_1> and account_id_2's through
+    // <side_2>, e.g. to convert both into one currency
+    pub fn converted(&self, side_1: impl Fn(Money) -> Money, side_2: impl Fn(Money) -> Money) -> Transaction {
+        let mut t = self.clone();
+        if t.splits.is_empty() {
+            t.amount = side_1(self.amount);
+        } else {
+            for s in t.splits.iter_mut() { s.amount = side_1(s.amount) }
+            t.amount = t.splits.iter().map(|s| s.amount).sum();
+        }
+        if t.transfer { t.amount_2 = Some(side_2(self.amount_2())) }
+        return t;
+    }
+    pub fn with_tags(&self, tags: Vec<String>) -> Transaction {
+        let mut t = self.clone();
+        t.tags = tags;
+        return t;
+    }
+    pub fn with_status(&self, status: Status) -> Transaction {
+        let mut t = self.clone();
+        t.status = status;
+        return t;
+    }
 }
 
 
 // TRANSACTION FILE I/O ----------------------------------------------------------------------------
-macro_rules! otfe {
-    ($e:ident) => {
-        println!("{}: {}\nStarting with fresh Transaction Database.","Error opening transactions file".to_string().red().bold(), $e)
-    }
-}
-pub fn get_transactions_from_file(filepath:&str) -> Vec<Transaction> {
-    match std::fs::File::open(filepath){
-        Ok(f) => {
-            let mut file = f;
-            let mut s = String::new();
-            match file.read_to_string(&mut s) {
-                Ok(_) => {
-                    // Deserialize JSON
-                    match serde_json::from_str(&mut s) {
-                        Ok(v) => return v,
-                        Err(e) => {otfe!(e); println!("HERE"); Vec::new()}
-                    }
-                },
-                Err(e) => {otfe!(e); Vec::new()}
-            }
-        },
-        Err(e) => {otfe!(e); Vec::new()}
-    }
+pub fn get_transactions_from_file(filepath:&str) -> Result<Vec<Transaction>, LoadError> {
+    let s = read_data_file(filepath)?;
+    // Deserialize JSON
+    return Ok(serde_json::from_str(&s)?);
 }
 
 macro_rules! stfe {
     ($e:ident) => {
-        println!("Error saving transactions to file: {}", $e)
+        return Err(format!("Error saving transactions to file: {}", $e))
     }
 }
-pub fn save_transactions_to_file(filepath:&str, transactions:Vec<Transaction>) {
+pub fn save_transactions_to_file(filepath:&str, transactions:Vec<Transaction>) -> Result<(), String> {
     // Serialize to JSON
     match serde_json::to_string(&transactions) {
         Ok(s) => {
             // save to file
-            match std::fs::File::create(filepath) {
-                Ok(f) => {
-                    let mut file = f;
-                    match file.write_all(s.as_bytes()) {
-                        Ok(_) => {}, // success & exit function without complaining
-                        Err(e) => stfe!(e)
-                    }
-                },
+            match write_data_file(filepath, s.as_bytes()) {
+                Ok(_) => {}, // success & exit function without complaining
                 Err(e) => stfe!(e)
             }
         },
         Err(e) => stfe!(e)
     }
+    return Ok(());
 }
 
 
 // DATE FORMAT -------------------------------------------------------------------------------------
-mod test_date_format {
+pub mod test_date_format {
     use chrono::{NaiveDate};
     use serde::{Deserializer, Serializer, Deserialize};
 
@@ -158,6 +306,33 @@ mod test_date_format {
     }
 }
 
+// the same, for dates that may be missing
+pub mod optional_date_format {
+    use chrono::{NaiveDate};
+    use serde::{Deserializer, Serializer, Deserialize};
+
+    pub fn serialize<S>(
+        date: &Option<NaiveDate>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+        match date {
+            Some(d) => super::test_date_format::serialize(d, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<NaiveDate>, D::Error>
+        where D: Deserializer<'de> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => NaiveDate::parse_from_str(&s, "%F").map(Some).map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
 
 // TRANSACTION FUNCTIONS ---------------------------------------------------------------------------
 
@@ -171,47 +346,77 @@ macro_rules! query_account {
         }
     }
 }
+// a date after today is only accepted once confirmed as scheduled, see status_for
 macro_rules! query_date {
     ($date:ident, $prompt:expr) => {
-        match DateSelect::new($prompt)
-        .with_week_start(Weekday::Mon)
-        .with_validator(|d: NaiveDate| {
-            let now = Utc::now().date_naive();
-            if d.gt(&now) {
-                Ok(Validation::Invalid("Date must be in the past or today".into()))
-            } else {
-                Ok(Validation::Valid)
+        loop {
+            match DateSelect::new($prompt)
+            .with_week_start(Weekday::Mon)
+            .prompt() {
+                Ok(d) => {
+                    if d > Utc::now().date_naive() {
+                        match Confirm::new(&format!("{} is in the future, record it as scheduled (pending until it clears)?", d))
+                            .with_default(true)
+                            .prompt() {
+                            Ok(true) => {},
+                            Ok(false) => continue, // pick another date
+                            Err(error) => return Err(error),
+                        }
+                    }
+                    $date = d;
+                    break;
+                },
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+macro_rules! query_status {
+    ($status:ident, $date:ident, $modify_help_message:ident) => {
+        if $date > Utc::now().date_naive() {
+            $status = Status::Pending; // scheduled
+        } else if should_modify!(format!("Modify Status (Currently \"{}\")", $status), $modify_help_message) {
+            let options = vec!(Status::Cleared, Status::Pending);
+            let start = options.iter().position(|s| *s == $status).unwrap_or(0);
+            match Select::new("Status:", options).with_starting_cursor(start).prompt() {
+                Ok(s) => $status = s,
+                Err(error) => return Err(error),
             }
-        })
-        .prompt() {
-            Ok(d) => {
-                $date = d;
-            },
-            Err(error) => return Err(error),
         }
     }
 }
 
 macro_rules! query_category {
     ($cat:ident, $prompt:expr, $cats:ident) => {
-        match Select::new($prompt, $cats).prompt() {
-            Ok(choice) => {
-                $cat = choice.id;
-            },
-            Err(error) => return Err(error),
+        query_category!($cat, $prompt, $cats, None::<u32>)
+    };
+    // start with the cursor on the <default> category id, if there is one. Shown as a tree, with each
+    // category indented under its parent
+    ($cat:ident, $prompt:expr, $cats:ident, $default:expr) => {
+        {
+            let tree = category_tree(&$cats);
+            let starting_cursor = match $default {
+                Some(id) => tree.iter().position(|e: &TreeEntry| e.account.id == id).unwrap_or(0),
+                None => 0,
+            };
+            match Select::new($prompt, tree).with_starting_cursor(starting_cursor).prompt() {
+                Ok(choice) => {
+                    $cat = choice.account.id;
+                },
+                Err(error) => return Err(error),
+            }
         }
     }
 }
 
 macro_rules! query_amount {
-    ($amount:ident, $prompt:expr) => {
-        match CustomType::<f64>::new($prompt)
-            .with_formatter(&|i| format!("£{:.2}", i))
-            .with_error_message("Please type a valid number")
-            .with_help_message("Type the amount in GBP using a decimal point as a separator. Positive values are money in, negative is money spent")
-            .prompt() {
+    ($amount:ident, $prompt:expr, $currency:expr) => {
+        match query_money($prompt,
+                          &format!("Type the amount in {}, e.g. 12.50. Positive values are money in, negative is money spent", $currency),
+                          $currency, None, false) {
             Ok(am) => {
-                $amount = (am*100.0).round() as i64; // convert to pence
+                $amount = am;
             },
             Err(error) => return Err(error),
         }
@@ -219,25 +424,15 @@ macro_rules! query_amount {
 }
 
 macro_rules! query_amount_positive {
-    ($amount:ident, $prompt:expr) => {
-        match CustomType::<f64>::new($prompt)
-                .with_formatter(&|i| format!("£{:.2}", i))
-                // TODO validate
-                // .with_validator(|float: f64| {
-                //     if float < 0.01 {
-                //         Ok(Validation::Invalid("Transfer must be of strictly positive value".into()))
-                //     } else {
-                //         Ok(Validation::Valid)
-                //     }
-                // })
-                .with_error_message("Please type a valid number")
-                .with_help_message("Type the amount in GBP using a decimal point as a separator. Strictly positive values only")
-                .prompt() {
+    ($amount:ident, $prompt:expr, $currency:expr) => {
+        match query_money($prompt,
+                          &format!("Type the amount in {}, e.g. 12.50. Strictly positive values only", $currency),
+                          $currency, None, true) {
             Ok(am) => {
-                $amount = (am*100.0).round() as u64; // convert to pence
+                $amount = am;
             },
-                Err(error) => return Err(error),
-            }
+            Err(error) => return Err(error),
+        }
     }
 }
 
@@ -263,6 +458,20 @@ macro_rules! query_notes {
     }
 }
 
+macro_rules! query_tags {
+    ($tags:ident, $prompt:expr) => {
+        match Text::new($prompt)
+            .with_initial_value(&$tags.join(", "))
+            .with_help_message("Separate tags with commas, e.g. holiday-2026, reimbursable. Leave empty for none")
+            .prompt() {
+            Ok(r) => {
+                $tags = parse_tags(&r);
+            },
+            Err(error) => return Err(error),
+        }
+    }
+}
+
 macro_rules! should_modify {
     ($msg:expr, $modify_help_message:ident) => {
         match Confirm::new(
@@ -276,13 +485,19 @@ macro_rules! should_modify {
     }
 }
 
-pub fn new_transaction_based_on(transaction: &Transaction)
+pub fn new_transaction_based_on(transaction: &Transaction, accounts: &Vec<Account>, cat_accounts: &Vec<Account>)
                                 -> Result<Transaction, InquireError> {
+    let currency = currency_of(accounts, transaction.account_id_1());
+    if transaction.is_split() {
+        return new_split_transaction_based_on(transaction, cat_accounts, &currency);
+    }
     return new_transaction_from_inputs_with_presets(
         transaction.account_id_1(),
         transaction.account_id_2(),
         transaction.reference(),
-        transaction.is_transfer() // pass through if is a transfer or not - the logic is the same either wya
+        transaction.is_transfer(), // pass through if is a transfer or not - the logic is the same either wya
+        transaction.tags(),
+        (currency.clone(), currency_of(accounts, transaction.account_id_2())),
     )
 }
 
@@ -291,6 +506,8 @@ fn new_transaction_from_inputs_with_presets(
     cat_id: u32,
     refr: String,
     transfer: bool,
+    mut tags: Vec<String>,
+    currencies: (String, String), // of acc_id and cat_id
 ) -> Result<Transaction, InquireError>
 {
     let amnt;
@@ -301,11 +518,22 @@ fn new_transaction_from_inputs_with_presets(
     query_date!(dat, "Transaction Date:");
 
     // Amount
-    query_amount!(amnt, "Transaction Amount:");
+    query_amount!(amnt, "Transaction Amount:", &currencies.0);
+
+    // Amount in the other account's currency, for transfers that change currency
+    let mut amount_2 = None;
+    if transfer && currencies.0 != currencies.1 {
+        let amnt_2;
+        query_amount!(amnt_2, format!("Transaction Amount in {}:", currencies.1).as_str(), &currencies.1);
+        amount_2 = Some(amnt_2);
+    }
 
     // Notes
     query_notes!(notes, "Notes:");
 
+    // Tags, starting from those of the transaction it is based on
+    query_tags!(tags, "Tags:");
+
     return Ok(Transaction{
         account_id_1: acc_id,
         account_id_2: cat_id,
@@ -313,10 +541,127 @@ fn new_transaction_from_inputs_with_presets(
         reference: refr,
         date: dat,
         notes,
-        transfer
+        transfer,
+        amount_2,
+        splits: Vec::new(),
+        tags,
+        status: status_for(dat, Status::Cleared),
     });
 }
 
+fn new_split_transaction_based_on(transaction: &Transaction, cat_accounts: &Vec<Account>, currency: &str)
+                                  -> Result<Transaction, InquireError>
+{
+    let dat;
+    let notes;
+
+    // Date
+    query_date!(dat, "Transaction Date:");
+
+    // Split, either exactly as before or re-entered
+    let splits = match Confirm::new("Use the same amounts and categories?")
+        .with_default(true)
+        .prompt() {
+        Ok(true) => transaction.splits(),
+        Ok(false) => {
+            let amnt;
+            query_amount!(amnt, "Transaction Amount:", currency);
+            query_split_lines(cat_accounts, amnt, currency)?
+        },
+        Err(e) => return Err(e),
+    };
+
+    // Notes
+    query_notes!(notes, "Notes:");
+
+    // Tags, starting from those of the transaction it is based on
+    let mut tags = transaction.tags();
+    query_tags!(tags, "Tags:");
+
+    return Ok(Transaction::new_split_transaction(
+        transaction.account_id_1(),
+        splits,
+        transaction.reference(),
+        dat,
+        notes,
+    ).with_tags(tags).with_status(status_for(dat, Status::Cleared)));
+}
+
+// allocate <total> across categories, one line at a time until nothing is left to allocate
+fn query_split_lines(cat_accounts: &Vec<Account>, total: Money, currency: &str) -> Result<Vec<Split>, InquireError> {
+    if cat_accounts.len() < 1 { return Err(InquireError::InvalidConfiguration(
+        "No categories to choose from".to_string()))}
+
+    let mut splits: Vec<Split> = Vec::new();
+    let mut remaining = total;
+    while splits.is_empty() || !remaining.is_zero() {
+        println!("Left to allocate: {}", format_money(remaining, currency, true, false));
+        let cat;
+        let cats = cat_accounts.clone();
+        query_category!(cat, format!("Category for line {}:", splits.len()+1).as_str(), cats);
+        let amnt = query_money("Amount for this category:", "Starts at everything left to allocate",
+                               currency, Some(remaining), false)?;
+        // merge lines for the same category
+        match splits.iter_mut().find(|s| s.category_id == cat) {
+            Some(s) => s.amount += amnt,
+            None => splits.push(Split { category_id: cat, amount: amnt }),
+        }
+        remaining -= amnt;
+    }
+    splits.retain(|s| !s.amount.is_zero());
+    // only possible with a zero total, which has nothing to split
+    if splits.is_empty() { return Err(InquireError::InvalidConfiguration(
+        "A split transaction needs at least one category with a non-zero amount".to_string()))}
+    return Ok(splits);
+}
+
+pub fn new_split_transaction_from_inputs(acc_accounts: &Vec<Account>, cat_accounts: &Vec<Account>)
+                                         -> Result<Transaction, InquireError>
+{
+    let acc_id;
+    let amnt;
+    let refr;
+    let dat;
+    let notes;
+
+    // Check accounts & categories before continuing
+    let accs = acc_accounts.clone(); // clone it so that it's not lost!
+    if accs.len() < 1 { return Err(InquireError::InvalidConfiguration(
+        "No accounts to choose from".to_string()))}
+
+    // Account
+    query_account!(acc_id, "Pick Account:", accs);
+
+    // Amount (total)
+    let currency = currency_of(acc_accounts, acc_id);
+    query_amount!(amnt, "Total Transaction Amount:", &currency);
+
+    // Date
+    query_date!(dat, "Transaction Date:");
+
+    // Reference
+    query_reference!(refr, "Reference:");
+
+    // Split
+    let splits = query_split_lines(cat_accounts, amnt, &currency)?;
+
+    // Notes
+    query_notes!(notes, "Notes:");
+
+    // Tags
+    let mut tags = Vec::new();
+    query_tags!(tags, "Tags:");
+
+    // Return
+    return Ok(Transaction::new_split_transaction(
+        acc_id,
+        splits,
+        refr,
+        dat,
+        notes,
+    ).with_tags(tags).with_status(status_for(dat, Status::Cleared)));
+}
+
 fn get_acc_from_id(accounts: &Vec<Account>, id: u32) -> Result<Account, bool> {
     for a in accounts.to_vec().into_iter() {
         if a.id == id {
@@ -336,12 +681,30 @@ fn get_cat_accounts(accounts: &Vec<Account>) -> Vec<Account> {
     return cat_accs
 }
 
+// reconciled transactions agree with a bank statement, so aren't changed by accident. Asks before going
+// ahead and <action>-ing one, erroring if the user doesn't want to
+pub fn confirm_unlocked(transaction: &Transaction, action: &str) -> Result<(), InquireError> {
+    if !transaction.is_reconciled() { return Ok(()) }
+    let unlock = Confirm::new(&format!("This transaction has been reconciled against a statement. Unlock it to {} it?", action))
+        .with_default(false)
+        .with_help_message("It will need reconciling again")
+        .prompt()?;
+    if !unlock { return Err(InquireError::InvalidConfiguration("Reconciled transactions are locked".to_string())) }
+    return Ok(());
+}
+
 pub fn get_modified_transaction(
     transaction: &Transaction,
     acc_accounts: &Vec<Account>,
     cat_accounts: &Vec<Account>)
     -> Result<Transaction, InquireError>
 {
+    confirm_unlocked(transaction, "modify")?;
+    let unlocked;
+    let transaction = if transaction.is_reconciled() {
+        unlocked = transaction.with_status(Status::Cleared);
+        &unlocked
+    } else { transaction };
     if transaction.is_transfer() {
         return get_modified_transaction_transfer(transaction, acc_accounts);
     } else {
@@ -376,25 +739,42 @@ fn get_modified_transaction_transaction(
         format!("Modify Account (Currently \"{}\")", acc.name), modify_help_message);
     if user_wants_to_modify { query_account!(acc_id_1, "Pick Account:", acc_accounts); }
 
-    // Category
+    // Category (or split across categories)
     let mut acc_id_2 = transaction.account_id_2(); // un-modified value
-    let acc2 = match get_acc_from_id(cat_accounts, acc_id_2) {
-        Ok(a) => a,
-        Err(_) => return Err(InquireError::InvalidConfiguration(
-            "Account under preset account id does not exist".to_string()))
-    };
-    user_wants_to_modify = should_modify!(
-        format!("Modify Category (Currently \"{}\")", acc2.name), modify_help_message);
-    if user_wants_to_modify {
-        let cats = cat_accounts.clone();
-        query_category!(acc_id_2, "Transaction Category:", cats)
-    }
-
-    // Amount
     let mut amnt = transaction.amount();
-    user_wants_to_modify = should_modify!(format!("Modify Amount (Currently \"{}\")",
-    pence_to_pound(amnt, true, false)), modify_help_message);
-    if user_wants_to_modify { query_amount!(amnt, "Transaction Amount:"); }
+    let mut splits = transaction.splits();
+    let old_currency = currency_of(acc_accounts, transaction.account_id_1());
+    let currency = currency_of(acc_accounts, acc_id_1); // amounts are entered in the (new) account's currency
+    if transaction.is_split() {
+        // Amount, re-allocating the split if it changes
+        user_wants_to_modify = should_modify!(format!("Modify Amount (Currently \"{}\")",
+        format_money(amnt, &old_currency, true, false)), modify_help_message);
+        if user_wants_to_modify {
+            query_amount!(amnt, "Transaction Amount:", &currency);
+            splits = query_split_lines(cat_accounts, amnt, &currency)?;
+        } else {
+            user_wants_to_modify = should_modify!(
+                format!("Modify Split (Currently across {} categories)", splits.len()), modify_help_message);
+            if user_wants_to_modify { splits = query_split_lines(cat_accounts, amnt, &currency)?; }
+        }
+    } else {
+        let acc2 = match get_acc_from_id(cat_accounts, acc_id_2) {
+            Ok(a) => a,
+            Err(_) => return Err(InquireError::InvalidConfiguration(
+                "Account under preset account id does not exist".to_string()))
+        };
+        user_wants_to_modify = should_modify!(
+            format!("Modify Category (Currently \"{}\")", acc2.name), modify_help_message);
+        if user_wants_to_modify {
+            let cats = cat_accounts.clone();
+            query_category!(acc_id_2, "Transaction Category:", cats, Some(acc_id_2))
+        }
+
+        // Amount
+        user_wants_to_modify = should_modify!(format!("Modify Amount (Currently \"{}\")",
+        format_money(amnt, &old_currency, true, false)), modify_help_message);
+        if user_wants_to_modify { query_amount!(amnt, "Transaction Amount:", &currency); }
+    }
 
     // Date
     let mut dat = transaction.date();
@@ -402,6 +782,10 @@ fn get_modified_transaction_transaction(
     format!("Modify Date (Currently \"{}\")", dat), modify_help_message);
     if user_wants_to_modify { query_date!(dat, "Transaction Date:"); }
 
+    // Status, scheduled when in the future
+    let mut status = transaction.status();
+    query_status!(status, dat, modify_help_message);
+
     // Reference
     let mut refr = transaction.reference();
     user_wants_to_modify = should_modify!(
@@ -414,7 +798,16 @@ fn get_modified_transaction_transaction(
     format!("Modify Notes (Currently \"{}\")", notes), modify_help_message);
     if user_wants_to_modify { query_notes!(notes, "Notes:"); }
 
+    // Tags
+    let mut tags = transaction.tags();
+    user_wants_to_modify = should_modify!(
+    format!("Modify Tags (Currently \"{}\")", tags.join(", ")), modify_help_message);
+    if user_wants_to_modify { query_tags!(tags, "Tags:"); }
+
     // Return
+    if !splits.is_empty() {
+        return Ok(Transaction::new_split_transaction(acc_id_1, splits, refr, dat, notes).with_tags(tags).with_status(status));
+    }
     return Ok(Transaction::new_transaction(
         acc_id_1,
         acc_id_2,
@@ -422,7 +815,7 @@ fn get_modified_transaction_transaction(
         refr,
         dat,
         notes,
-    ));
+    ).with_tags(tags).with_status(status));
 }
 
 fn get_modified_transaction_transfer(
@@ -467,17 +860,33 @@ fn get_modified_transaction_transfer(
     if user_wants_to_modify { query_account!(acc_id_2, "Pick Destination Account:", dest_accounts); }
 
     // Amount
-    let mut amnt = (-transaction.amount()) as u64; // TODO what happens for all these "as othertype" when it can't go ?? presumable it panics, but this shouldn't be too much of an issue with the size of the numbers here
+    let mut amnt = -transaction.amount();
+    let currency_1 = currency_of(acc_accounts, acc_id_1);
     user_wants_to_modify = match Confirm::new(
         format!("Modify Amount (Currently \"{}\")",
-                pence_to_pound(amnt as i64, true, false)).as_str())
+                format_money(amnt, &currency_of(acc_accounts, transaction.account_id_1()), true, false)).as_str())
             .with_default(false)
             .with_help_message(modify_help_message)
             .prompt() {
         Ok(b) => b,
         Err(e) => return Err(e)
     };
-    if user_wants_to_modify { query_amount_positive!(amnt, "Transfer Amount: "); }
+    if user_wants_to_modify { query_amount_positive!(amnt, "Transfer Amount: ", &currency_1); }
+
+    // Amount received, when the accounts are in different currencies
+    let currency_2 = currency_of(acc_accounts, acc_id_2);
+    let mut amnt_2 = None;
+    if currency_1 != currency_2 {
+        let mut received = -transaction.amount_2();
+        user_wants_to_modify = if !transaction.changes_currency() {
+            true // it didn't before, so there is nothing to keep
+        } else {
+            should_modify!(format!("Modify Amount Received (Currently \"{}\")",
+            format_money(received, &currency_of(acc_accounts, transaction.account_id_2()), true, false)), modify_help_message)
+        };
+        if user_wants_to_modify { query_amount_positive!(received, format!("Amount Received in {}: ", currency_2).as_str(), &currency_2); }
+        amnt_2 = Some(-received);
+    }
 
     // Date
     let mut dat = transaction.date();
@@ -485,6 +894,10 @@ fn get_modified_transaction_transfer(
     format!("Modify Date (Currently \"{}\")", dat), modify_help_message);
     if user_wants_to_modify { query_date!(dat, "Transaction Date:"); }
 
+    // Status, scheduled when in the future
+    let mut status = transaction.status();
+    query_status!(status, dat, modify_help_message);
+
 
     // Reference
     let mut refr = transaction.reference();
@@ -498,19 +911,37 @@ fn get_modified_transaction_transfer(
     format!("Modify Notes (Currently \"{}\")", notes), modify_help_message);
     if user_wants_to_modify { query_notes!(notes, "Notes:"); }
 
+    // Tags
+    let mut tags = transaction.tags();
+    user_wants_to_modify = should_modify!(
+    format!("Modify Tags (Currently \"{}\")", tags.join(", ")), modify_help_message);
+    if user_wants_to_modify { query_tags!(tags, "Tags:"); }
+
     // Return
-    return Ok(Transaction::new_transfer(
+    let t = Transaction::new_transfer(
         acc_id_1,
         acc_id_2,
         amnt,
         refr,
         dat,
         notes,
-    ));
+    ).with_tags(tags).with_status(status);
+    return Ok(match amnt_2 { Some(a) => t.with_amount_2(a), None => t });
 }
 
-pub fn new_transaction_from_inputs(acc_accounts: &Vec<Account>, cat_accounts: &Vec<Account>)
+pub fn new_transaction_from_inputs(acc_accounts: &Vec<Account>, cat_accounts: &Vec<Account>, rules: &Vec<CategoryRule>)
                                    -> Result<Transaction, InquireError>
+{
+    return new_transaction_from_inputs_on(acc_accounts, cat_accounts, rules, None);
+}
+
+// as new_transaction_from_inputs, but skips asking for the date when one is given
+pub fn new_transaction_from_inputs_on(
+    acc_accounts: &Vec<Account>,
+    cat_accounts: &Vec<Account>,
+    rules: &Vec<CategoryRule>,
+    date: Option<NaiveDate>
+) -> Result<Transaction, InquireError>
 {
     let acc_id;
     let amnt;
@@ -531,21 +962,28 @@ pub fn new_transaction_from_inputs(acc_accounts: &Vec<Account>, cat_accounts: &V
     // Account
     query_account!(acc_id, "Pick Account:", accs);
 
-    // Category
-    query_category!(cat, "Transaction Category:", cats);
-
     // Amount
-    query_amount!(amnt, "Transaction Amount:");
+    query_amount!(amnt, "Transaction Amount:", &currency_of(acc_accounts, acc_id));
 
     // Date
-    query_date!(dat, "Transaction Date:");
+    match date {
+        Some(d) => dat = d,
+        None => query_date!(dat, "Transaction Date:"),
+    }
 
     // Reference
     query_reference!(refr, "Reference:");
 
+    // Category, suggested from the reference and amount by the categorisation rules
+    query_category!(cat, "Transaction Category:", cats, categorise(rules, &refr, amnt));
+
     // Notes
     query_notes!(notes, "Notes:");
 
+    // Tags
+    let mut tags = Vec::new();
+    query_tags!(tags, "Tags:");
+
     // Return
     return Ok(Transaction::new_transaction(
         acc_id,
@@ -554,11 +992,18 @@ pub fn new_transaction_from_inputs(acc_accounts: &Vec<Account>, cat_accounts: &V
         refr,
         dat,
         notes,
-    ));
+    ).with_tags(tags).with_status(status_for(dat, Status::Cleared)));
 }
 
 pub fn new_transfer_from_inputs(acc_accounts: &Vec<Account>)
                                    -> Result<Transaction, InquireError>
+{
+    return new_transfer_from_inputs_on(acc_accounts, None);
+}
+
+// as new_transfer_from_inputs, but skips asking for the date when one is given
+pub fn new_transfer_from_inputs_on(acc_accounts: &Vec<Account>, date: Option<NaiveDate>)
+                                   -> Result<Transaction, InquireError>
 {
     let src_acc_id;
     let dest_acc_id;
@@ -582,10 +1027,23 @@ pub fn new_transfer_from_inputs(acc_accounts: &Vec<Account>)
     query_account!(dest_acc_id, "Pick Destination Account:", accs);
 
     // Amount // strictly positive value
-    query_amount_positive!(amnt, "Transfer Amount: ");
+    let src_currency = currency_of(acc_accounts, src_acc_id);
+    let dest_currency = currency_of(acc_accounts, dest_acc_id);
+    query_amount_positive!(amnt, "Transfer Amount: ", &src_currency);
+
+    // Amount received, when the accounts are in different currencies
+    let mut amnt_2 = None;
+    if src_currency != dest_currency {
+        let received;
+        query_amount_positive!(received, format!("Amount Received in {}: ", dest_currency).as_str(), &dest_currency);
+        amnt_2 = Some(-received);
+    }
 
     // Date
-    query_date!(dat, "Transaction Date:");
+    match date {
+        Some(d) => dat = d,
+        None => query_date!(dat, "Transaction Date:"),
+    }
 
     // Reference
     query_reference!(refr, "Reference:");
@@ -593,13 +1051,18 @@ pub fn new_transfer_from_inputs(acc_accounts: &Vec<Account>)
     // Notes
     query_notes!(notes, "Notes:");
 
+    // Tags
+    let mut tags = Vec::new();
+    query_tags!(tags, "Tags:");
+
     // Return
-    return Ok(Transaction::new_transfer(
+    let t = Transaction::new_transfer(
         src_acc_id,
         dest_acc_id,
         amnt,
         refr,
         dat,
         notes,
-    ));
+    ).with_tags(tags).with_status(status_for(dat, Status::Cleared));
+    return Ok(match amnt_2 { Some(a) => t.with_amount_2(a), None => t });
 }